@@ -0,0 +1,82 @@
+use super::task::Task;
+use super::{VideoAnalyzerOutput, VideoAnalyzerReport, VideoAnalyzerResult};
+use std::collections::VecDeque;
+
+/// The step of an analysis that turns a [`Task`] into a [`VideoAnalyzerReport`], which runs the
+/// inference procedure by default. Another backend can be set with
+/// [`VideoAnalyzer::backend`](super::VideoAnalyzer::backend), which replaces the whole step, so
+/// the settings of the inference procedure, such as the enabled meme types, don't apply to it.
+pub trait InferenceBackend: Send {
+    /// Analyzes the video of `task`. A crash of the analysis is reported as an output of
+    /// [`VideoAnalyzerOutput::default`], while an error means that the analysis couldn't run at
+    /// all.
+    fn analyze(&mut self, task: &Task) -> VideoAnalyzerResult;
+}
+
+/// An [`InferenceBackend`] answering the tasks with canned outputs instead of running the
+/// inference procedure, so that the analysis pipeline can be tested without a Python environment.
+///
+/// The outputs are given to the tasks in the order they are added, and the last one is repeated
+/// once the others are used up. The tasks get no suggestions if no output is added.
+#[derive(Debug, Default)]
+pub struct StubInference {
+    outputs: VecDeque<VideoAnalyzerOutput>,
+}
+
+impl StubInference {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `output` as the output of the next task without one.
+    #[inline]
+    pub fn output(mut self, output: VideoAnalyzerOutput) -> Self {
+        self.outputs.push_back(output);
+        self
+    }
+}
+
+impl InferenceBackend for StubInference {
+    fn analyze(&mut self, task: &Task) -> VideoAnalyzerResult {
+        let output = match self.outputs.len() {
+            0 => VideoAnalyzerOutput::from(Vec::new()),
+            1 => self.outputs[0].clone(),
+            _ => self.outputs.pop_front().unwrap_or_default(),
+        };
+        log::debug!("[{}] answered with a canned output", task.id());
+        Ok(VideoAnalyzerReport {
+            output,
+            ..VideoAnalyzerReport::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::task::TaskConfig;
+    use crate::analyzer::{MemeType, VideoAnalyzerSuggestion};
+
+    #[test]
+    fn test_stub_inference() {
+        let task = TaskConfig::new("video.mp4").build();
+        let suggestions = |stub: &mut StubInference| {
+            stub.analyze(&task)
+                .unwrap()
+                .output
+                .suggestions()
+                .map(<[_]>::to_vec)
+        };
+
+        assert_eq!(suggestions(&mut StubInference::new()), Some(Vec::new()));
+
+        let suggestion = VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger);
+        let mut stub = StubInference::new()
+            .output(VideoAnalyzerOutput::default())
+            .output(VideoAnalyzerOutput::from_iter([suggestion.clone()]));
+        assert_eq!(suggestions(&mut stub), None);
+        assert_eq!(suggestions(&mut stub), Some(vec![suggestion.clone()]));
+        assert_eq!(suggestions(&mut stub), Some(vec![suggestion]));
+    }
+}
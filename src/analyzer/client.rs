@@ -0,0 +1,73 @@
+use super::task::Task;
+use super::{VideoAnalyzerBuffer, VideoAnalyzerMode, VideoAnalyzerOutput};
+use std::io;
+use std::path::Path;
+
+/// A client of a [`VideoAnalyzer`](super::VideoAnalyzer) for running analyses in-process, without
+/// going through the HTTP API. It wraps the [`VideoAnalyzerBuffer`] returned along with the
+/// analyzer, which has to be running in a background thread.
+///
+/// ```no_run
+/// # async fn analyze() -> std::io::Result<()> {
+/// use std::path::PathBuf;
+/// use std::thread;
+/// use streameme_backend::analyzer::{VideoAnalyzer, VideoAnalyzerClient, VideoAnalyzerMode};
+///
+/// let (analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("/opt/streameme_inference"));
+/// thread::spawn(move || analyzer.run());
+/// let client = VideoAnalyzerClient::new(analyzer_buf);
+///
+/// let output = client
+///     .analyze("/tmp/video.mp4", VideoAnalyzerMode::Multi, "video")
+///     .await?;
+/// for suggestion in output.suggestions().unwrap_or_default() {
+///     println!("{}..{}: {:?}", suggestion.start(), suggestion.end(), suggestion.meme_type());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct VideoAnalyzerClient {
+    buffer: VideoAnalyzerBuffer,
+}
+
+impl VideoAnalyzerClient {
+    /// Creates a [`VideoAnalyzerClient`] sending tasks through `buffer`.
+    #[inline]
+    pub fn new(buffer: VideoAnalyzerBuffer) -> Self {
+        Self { buffer }
+    }
+
+    /// Analyzes the video at `path` in `mode`, and waits for the results. `name` is the name of the
+    /// video without its extension, which the inference procedure uses for naming its files.
+    ///
+    /// The task is queued behind the ones already sent to the analyzer, as the uploads are. The
+    /// output is [failed](VideoAnalyzerOutput::is_failed) if the inference procedure failed on the
+    /// video.
+    ///
+    /// # Errors
+    /// An error is returned if the analyzer has stopped, or the inference procedure can't be run at
+    /// all.
+    pub async fn analyze<P: AsRef<Path> + ?Sized>(
+        &self,
+        path: &P,
+        mode: VideoAnalyzerMode,
+        name: &str,
+    ) -> io::Result<VideoAnalyzerOutput> {
+        let handle = Task::new(path, name, mode)
+            .spawn(&self.buffer)
+            .map_err(|_| analyzer_stopped())?;
+        let report = handle.recv().await.map_err(|_| analyzer_stopped())??;
+        Ok(report.output)
+    }
+}
+
+impl From<VideoAnalyzerBuffer> for VideoAnalyzerClient {
+    #[inline]
+    fn from(buffer: VideoAnalyzerBuffer) -> Self {
+        Self::new(buffer)
+    }
+}
+
+fn analyzer_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the analyzer has stopped")
+}
@@ -1,4 +1,6 @@
 use serde::Deserialize;
+use std::ffi::OsStr;
+use std::process::Command;
 
 #[derive(Debug, Deserialize)]
 #[repr(transparent)]
@@ -9,6 +11,32 @@ impl InferenceOutput {
     pub fn into_inner(self) -> Vec<InferenceOutputUnit> {
         self.0
     }
+
+    /// Parses the output of an inference procedure that crashed while writing it, keeping the
+    /// units written in full before the point it stopped. Returns [`None`] if the output isn't
+    /// the beginning of an array, or not even a unit of it was written.
+    pub fn parse_partial(s: &str) -> Option<Self> {
+        let mut rest = s.trim_start().strip_prefix('[')?;
+        let mut units = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.starts_with(']') {
+                return Some(Self(units));
+            }
+            let mut stream = serde_json::Deserializer::from_str(rest).into_iter();
+            let Some(Ok(unit)) = stream.next() else {
+                break;
+            };
+            units.push(unit);
+            rest = rest[stream.byte_offset()..].trim_start();
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None if rest.starts_with(']') => return Some(Self(units)),
+                None => break,
+            }
+        }
+        (!units.is_empty()).then_some(Self(units))
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -18,6 +46,78 @@ pub struct InferenceOutputUnit {
     pub suggestion: String,
 }
 
+/// The prefix marking a line of the inference procedure's stderr as a warning meant for users.
+const WARNING_PREFIX: &str = "WARN ";
+
+/// Splits the stderr of the inference procedure into warnings and the remaining lines.
+///
+/// A warning is a line starting with `WARN `, and the prefix is stripped from the returned
+/// warnings. Blank lines are discarded.
+pub fn split_warnings(stderr: &str) -> (Vec<String>, Vec<&str>) {
+    let mut warnings = Vec::new();
+    let mut others = Vec::new();
+    for line in stderr.lines().filter(|line| !line.trim().is_empty()) {
+        if let Some(warning) = line.strip_prefix(WARNING_PREFIX) {
+            warnings.push(String::from(warning.trim()));
+        } else {
+            others.push(line);
+        }
+    }
+    (warnings, others)
+}
+
+/// Returns the last `lines` non-blank lines of the stderr of the inference procedure, where every
+/// occurrence of a path in `redactions` is replaced with its placeholder, so that the layout of
+/// the host isn't exposed.
+pub fn stderr_tail(stderr: &str, lines: usize, redactions: &[(&str, &str)]) -> Vec<String> {
+    // The longer paths go first, in case one contains another.
+    let mut redactions = redactions.to_vec();
+    redactions.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+    let all: Vec<&str> = stderr
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    all[all.len().saturating_sub(lines)..]
+        .iter()
+        .map(|line| {
+            redactions
+                .iter()
+                .filter(|(path, _)| !path.is_empty())
+                .fold(String::from(*line), |line, (path, placeholder)| {
+                    line.replace(path, placeholder)
+                })
+        })
+        .collect()
+}
+
+/// Returns the command line run by `command`, derived from the `Command` itself so that it can't
+/// drift from what actually runs. The environment variables set on `command` lead the line with
+/// their values redacted, since they may hold secrets such as API keys, and the arguments with
+/// spaces or quotes are single-quoted.
+pub fn command_line(command: &Command) -> String {
+    let envs = command.get_envs().map(|(key, value)| match value {
+        Some(_) => format!("{}=<redacted>", key.to_string_lossy()),
+        None => format!("-u {}", key.to_string_lossy()),
+    });
+    let args = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(quote);
+    envs.chain(args).collect::<Vec<_>>().join(" ")
+}
+
+/// Single-quotes `arg` for a shell if it's empty or has a character the shell would interpret.
+fn quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c))
+    {
+        return arg.into_owned();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,4 +145,79 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_parse_partial_inference_output() {
+        let units = |s: &str| InferenceOutput::parse_partial(s).map(|output| output.0.len());
+
+        let complete = r#"[{"start": 30, "end": 60, "suggestion": "sorrow"}, {"start": 120, "end": 150, "suggestion": "anger"}]"#;
+        assert_eq!(units(complete), Some(2));
+        // Cut off within the second unit, and right after it.
+        assert_eq!(units(&complete[..60]), Some(1));
+        assert_eq!(units(&complete[..complete.len() - 1]), Some(2));
+        assert_eq!(
+            units(r#"[{"start": 30, "end": 60, "suggestion": "sorrow"},"#),
+            Some(1)
+        );
+        // A unit of a wrong shape ends the salvageable part.
+        assert_eq!(
+            units(r#"[{"start": 30, "end": 60, "suggestion": "sorrow"}, {"start": -1}]"#),
+            Some(1)
+        );
+        assert_eq!(units("[]"), Some(0));
+        assert_eq!(units(r#"[{"start": 30, "#), None);
+        assert_eq!(units("["), None);
+        assert_eq!(units(""), None);
+        assert_eq!(units(r#"{"start": 30}"#), None);
+    }
+
+    #[test]
+    fn test_split_warnings() {
+        let stderr = "WARN frame rate too low\nloading model...\n\nWARN  audio track missing \n";
+        let (warnings, others) = split_warnings(stderr);
+
+        assert_eq!(
+            warnings,
+            vec![
+                String::from("frame rate too low"),
+                String::from("audio track missing")
+            ]
+        );
+        assert_eq!(others, vec!["loading model..."]);
+    }
+
+    #[test]
+    fn test_stderr_tail() {
+        let stderr = "loading model...\n\nTraceback (most recent call last):\n  File \"/srv/inference/inference.py\", line 3\nOSError: /srv/inference/models/x.bin not found\n";
+        let redactions = [
+            ("/srv/inference", "<inference_dir>"),
+            ("/srv/inference/models", "<models>"),
+        ];
+
+        assert_eq!(
+            stderr_tail(stderr, 2, &redactions),
+            vec![
+                String::from("  File \"<inference_dir>/inference.py\", line 3"),
+                String::from("OSError: <models>/x.bin not found"),
+            ]
+        );
+        assert_eq!(stderr_tail(stderr, 10, &redactions).len(), 4);
+        assert!(stderr_tail(stderr, 0, &redactions).is_empty());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut command = Command::new("/opt/inference/.venv/bin/python");
+        command
+            .env("API_KEY", "s3cr3t")
+            .arg("inference.py")
+            .args(["--video_name", "my video's name"])
+            .args(["--mode", "single"]);
+        let line = command_line(&command);
+        assert_eq!(
+            line,
+            r"API_KEY=<redacted> /opt/inference/.venv/bin/python inference.py --video_name 'my video'\''s name' --mode single"
+        );
+        assert!(!line.contains("s3cr3t"));
+    }
 }
@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The weight of the latest analysis in the average duration of analyses.
+const DURATION_WEIGHT: f64 = 0.2;
+
+/// The load of an analyzer, shared between the analyzer and its buffer.
+#[derive(Debug, Default)]
+pub(crate) struct AnalyzerLoad {
+    /// The number of the tasks queued or being analyzed.
+    depth: AtomicUsize,
+    /// The number of the tasks queued and not yet picked up by the analyzer.
+    queued: AtomicUsize,
+    /// The exponential moving average of the durations of analyses in milliseconds, where 0 means
+    /// that no task has been analyzed yet.
+    average_duration_ms: AtomicU64,
+    /// Whether the analyzer has had no task for the idle timeout.
+    idle: AtomicBool,
+}
+
+impl AnalyzerLoad {
+    /// Counts a task sent to the analyzer.
+    #[inline]
+    pub fn task_queued(&self) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a task picked up by the analyzer.
+    #[inline]
+    pub fn task_started(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Counts a task that has left the analyzer, either analyzed in `duration` or never received.
+    pub fn task_done(&self, duration: Option<Duration>) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        let Some(duration) = duration else {
+            // The task never reached the analyzer, so it was still counted as queued.
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return;
+        };
+        // Only the analyzer thread updates the average, so loading and storing don't race.
+        let latest = (duration.as_millis() as u64).max(1);
+        let average = match self.average_duration_ms.load(Ordering::Relaxed) {
+            0 => latest,
+            average => {
+                (average as f64 * (1.0 - DURATION_WEIGHT) + latest as f64 * DURATION_WEIGHT) as u64
+            }
+        };
+        self.average_duration_ms.store(average, Ordering::Relaxed);
+    }
+
+    /// Counts a task picked up by the analyzer and dropped without being analyzed.
+    #[inline]
+    pub fn task_cancelled(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Marks the analyzer idle or busy. Only the analyzer thread calls this.
+    #[inline]
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::Relaxed);
+    }
+
+    /// Returns whether the analyzer has had no task for the idle timeout.
+    #[inline]
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the tasks queued or being analyzed.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the tasks queued and not yet picked up by the analyzer.
+    #[inline]
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns how long a task sent now would wait before its analysis starts, assuming every task
+    /// ahead of it takes the average duration. This is unknown until a task has been analyzed.
+    pub fn estimated_wait(&self) -> Option<Duration> {
+        match self.average_duration_ms.load(Ordering::Relaxed) {
+            0 => None,
+            average => Some(Duration::from_millis(average) * self.depth() as u32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyzer_load() {
+        let load = AnalyzerLoad::default();
+        load.task_queued();
+        load.task_queued();
+        load.task_queued();
+        assert_eq!(load.depth(), 3);
+        assert_eq!(load.queued(), 3);
+        assert_eq!(load.estimated_wait(), None);
+
+        load.task_started();
+        assert_eq!(load.depth(), 3);
+        assert_eq!(load.queued(), 2);
+        load.task_done(Some(Duration::from_secs(10)));
+        assert_eq!(load.depth(), 2);
+        assert_eq!(load.queued(), 2);
+        assert_eq!(load.estimated_wait(), Some(Duration::from_secs(20)));
+
+        load.task_started();
+        load.task_done(Some(Duration::from_secs(20)));
+        assert_eq!(load.estimated_wait(), Some(Duration::from_secs(12)));
+
+        load.task_queued();
+        load.task_started();
+        load.task_cancelled();
+        assert_eq!(load.depth(), 1);
+        assert_eq!(load.queued(), 1);
+
+        load.task_done(None);
+        assert_eq!(load.depth(), 0);
+        assert_eq!(load.queued(), 0);
+        assert_eq!(load.estimated_wait(), Some(Duration::ZERO));
+    }
+}
@@ -1,63 +1,195 @@
+mod backend;
+mod client;
 /// This is a module for parsing output from the inference procedure.
 mod inference;
-pub(crate) mod task;
+mod load;
+mod model;
+mod name_template;
+mod readiness;
+mod stats;
+pub mod task;
+mod watchdog;
 
+pub use backend::{InferenceBackend, StubInference};
+pub use client::VideoAnalyzerClient;
 use inference::InferenceOutput;
-use serde::Serialize;
-use serde_repr::{Deserialize_repr, Serialize_repr};
-use std::fmt::Debug;
+use load::AnalyzerLoad;
+pub use model::InferenceModel;
+pub use name_template::VideoNameTemplate;
+pub use readiness::{ReadinessFailure, ReadinessProbe};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_repr::Serialize_repr;
+use stats::AnalyzerStats;
+pub use stats::VideoAnalyzerStats;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::mpsc;
+use std::str::FromStr;
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
 use task::{SpawnedTask, Task};
 use tempfile::TempDir;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+use uuid::Uuid;
+pub use watchdog::ResourceExceeded;
 
-pub(crate) type VideoAnalyzerResult = io::Result<VideoAnalyzerOutput>;
+/// The file the inference procedure writes its results to by default.
+const DEFAULT_OUTPUT_FILE_NAME: &str = "suggestions.json";
 
-#[derive(Debug, Copy, Clone, Deserialize_repr)]
+/// The file describing an analysis, written next to the kept output of the inference procedure.
+const KEPT_OUTPUT_META_FILE_NAME: &str = "meta.json";
+
+/// The number of the last lines of stderr kept for a failed task asking for the raw output, by
+/// default.
+const DEFAULT_STDERR_TAIL_LINES: usize = 20;
+
+/// The result of analyzing a video, which is an error if the inference procedure can't be run at
+/// all.
+pub type VideoAnalyzerResult = io::Result<VideoAnalyzerReport>;
+
+/// The kind of analysis run on a video.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ToSchema)]
 #[repr(u8)]
-pub(crate) enum VideoAnalyzerMode {
+pub enum VideoAnalyzerMode {
     Binary = 0,
+    #[default]
     Multi = 1,
 }
 
-impl VideoAnalyzerMode {
-    pub fn desc(self) -> String {
-        VideoAnalyzerModeDesc::new(self).0
+/// The error message for a mode that is neither of the valid ones.
+const INVALID_MODE: &str = "mode must be 0 (binary) or 1 (multi)";
+
+/// Deserializes a mode from its number. Anything else is answered with the valid numbers, instead
+/// of the error of the underlying integer type.
+impl<'de> Deserialize<'de> for VideoAnalyzerMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match i64::deserialize(deserializer) {
+            Ok(0) => Ok(Self::Binary),
+            Ok(1) => Ok(Self::Multi),
+            _ => Err(D::Error::custom(INVALID_MODE)),
+        }
     }
 }
 
-impl Default for VideoAnalyzerMode {
-    fn default() -> Self {
-        Self::Multi
+/// Renders the mode as its name, such as `multi`.
+impl fmt::Display for VideoAnalyzerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Binary => "binary",
+            Self::Multi => "multi",
+        })
     }
 }
 
-#[derive(Debug, Serialize)]
+/// Parses a mode from either its name or its number, such as `multi` or `1`.
+impl FromStr for VideoAnalyzerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" | "0" => Ok(Self::Binary),
+            "multi" | "1" => Ok(Self::Multi),
+            _ => Err(String::from(INVALID_MODE)),
+        }
+    }
+}
+
+impl VideoAnalyzerMode {
+    /// Returns the mode in the form of `{"id": 1, "key": "multi"}`.
+    #[inline]
+    pub(crate) fn keyed(self) -> KeyedId {
+        KeyedId {
+            id: self as u8,
+            key: self.to_string(),
+        }
+    }
+}
+
+/// How thoroughly a video is analyzed.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisQuality {
+    /// The video is analyzed as it is.
+    #[default]
+    Full,
+    /// The inference procedure downscales and samples the video before analyzing it, which gives
+    /// approximate results in a fraction of the time.
+    Preview,
+}
+
+/// An enum value serialized as an object carrying both its numeric `id` and its string `key`, such
+/// as `{"id": 0, "key": "happiness"}`. This is used by the nested response shape in place of the
+/// parallel number and string fields of the flat one.
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct KeyedId {
+    id: u8,
+    key: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 #[repr(transparent)]
 pub(crate) struct VideoAnalyzerModeDesc(String);
 
 impl VideoAnalyzerModeDesc {
     #[inline]
     pub fn new(mode: VideoAnalyzerMode) -> Self {
-        use VideoAnalyzerMode::*;
-        Self(match mode {
-            Binary => String::from("binary"),
-            Multi => String::from("multi"),
-        })
+        Self(mode.to_string())
     }
 }
 
 /// A sender to the analyzer's task buffer. See documentation of [`VideoAnalyzer::new()`] for the
 /// usage of this type.
-#[repr(transparent)]
-pub struct VideoAnalyzerBuffer(mpsc::Sender<SpawnedTask>);
+pub struct VideoAnalyzerBuffer {
+    sender: mpsc::Sender<SpawnedTask>,
+    load: Arc<AnalyzerLoad>,
+    stats: Arc<AnalyzerStats>,
+}
 
 impl VideoAnalyzerBuffer {
     #[inline]
     fn send(&self, task: SpawnedTask) -> Result<(), mpsc::SendError<SpawnedTask>> {
-        self.0.send(task)
+        self.load.task_queued();
+        self.sender
+            .send(task)
+            .inspect_err(|_| self.load.task_done(None))
+    }
+
+    /// Returns the number of the tasks queued or being analyzed.
+    #[inline]
+    pub(crate) fn queue_depth(&self) -> usize {
+        self.load.depth()
+    }
+
+    /// Returns the number of the tasks waiting in the queue, which are sent to the analyzer and not
+    /// yet picked up by it. Unlike [`Self::queue_depth`], the task being analyzed isn't counted.
+    #[inline]
+    pub fn queue_len(&self) -> usize {
+        self.load.queued()
+    }
+
+    /// Returns how long a task sent now would wait before its analysis starts, estimated from the
+    /// recent analyses. This is unknown until a task has been analyzed.
+    #[inline]
+    pub(crate) fn estimated_wait(&self) -> Option<Duration> {
+        self.load.estimated_wait()
+    }
+
+    /// Returns whether the analyzer has had no task for its idle timeout. This is always false if
+    /// no idle timeout is set with [`VideoAnalyzer::idle_timeout`].
+    #[inline]
+    pub(crate) fn is_idle(&self) -> bool {
+        self.load.is_idle()
+    }
+
+    /// Returns the number of the analyses since the analyzer started, and the success rate and the
+    /// durations of the latest of them.
+    #[inline]
+    pub fn stats(&self) -> VideoAnalyzerStats {
+        self.stats.snapshot(self.load.depth())
     }
 }
 
@@ -65,10 +197,26 @@ impl VideoAnalyzerBuffer {
 ///
 /// An instance of [`VideoAnalyzer`] should be run in a background thread.
 pub struct VideoAnalyzer {
-    inference_dir: PathBuf,
-    interpreter_path: PathBuf,
-    inference_script_path: PathBuf,
+    /// The inference procedure run unless a task names another.
+    model: InferenceModel,
+    /// The other inference procedures, which the tasks select by name.
+    models: HashMap<String, InferenceModel>,
+    output_file_name: String,
+    video_name_template: VideoNameTemplate,
+    envs: Vec<(String, String)>,
+    dedup_tolerance_sec: u32,
+    stderr_tail_lines: usize,
+    kept_output_dir: Option<PathBuf>,
+    temp_dir: PathBuf,
+    idle_timeout: Option<Duration>,
+    memory_limit: Option<u64>,
+    partial_results: bool,
+    meme_types: Vec<MemeType>,
+    /// What analyzes the tasks instead of the inference procedure, if set.
+    backend: Option<Box<dyn InferenceBackend>>,
     scheduled: mpsc::Receiver<SpawnedTask>,
+    load: Arc<AnalyzerLoad>,
+    stats: Arc<AnalyzerStats>,
 }
 
 impl VideoAnalyzer {
@@ -82,102 +230,536 @@ impl VideoAnalyzer {
     /// of unstability.
     #[inline]
     pub fn new(inference_dir: PathBuf) -> (Self, VideoAnalyzerBuffer) {
-        let interpreter_path = inference_dir.join(".venv/bin/python");
-        let inference_script_path = inference_dir.join("inference.py");
         let (tx, rx) = mpsc::channel();
+        let load = Arc::new(AnalyzerLoad::default());
+        let stats = Arc::new(AnalyzerStats::default());
         (
             Self {
-                inference_dir,
-                interpreter_path,
-                inference_script_path,
+                model: InferenceModel::new(inference_dir),
+                models: HashMap::new(),
+                output_file_name: String::from(DEFAULT_OUTPUT_FILE_NAME),
+                video_name_template: VideoNameTemplate::default(),
+                envs: Vec::new(),
+                dedup_tolerance_sec: 0,
+                stderr_tail_lines: DEFAULT_STDERR_TAIL_LINES,
+                kept_output_dir: None,
+                temp_dir: PathBuf::from("."),
+                idle_timeout: None,
+                memory_limit: None,
+                partial_results: false,
+                meme_types: MemeType::ALL.to_vec(),
+                backend: None,
                 scheduled: rx,
+                load: Arc::clone(&load),
+                stats: Arc::clone(&stats),
+            },
+            VideoAnalyzerBuffer {
+                sender: tx,
+                load,
+                stats,
             },
-            VideoAnalyzerBuffer(tx),
         )
     }
 
+    /// Returns the path of the Python interpreter running the inference script, which is
+    /// `.venv/bin/python` in the inference directory.
+    #[inline]
+    pub fn interpreter_path(&self) -> &Path {
+        self.model.interpreter_path()
+    }
+
+    /// Sets the path of the Python interpreter running the inference script, such as the one of
+    /// another virtual environment. It defaults to `.venv/bin/python` in the inference directory.
+    #[inline]
+    pub fn interpreter(mut self, path: impl Into<PathBuf>) -> Self {
+        self.model = self.model.interpreter(path);
+        self
+    }
+
+    /// Registers another inference procedure under `name`, which runs the tasks naming it with
+    /// [`TaskConfig::model`](task::TaskConfig::model) instead of the default one. The settings of
+    /// the analyzer, such as the environment variables and the memory limit, apply to all of them.
+    #[inline]
+    pub fn model(mut self, name: impl Into<String>, model: InferenceModel) -> Self {
+        self.models.insert(name.into(), model);
+        self
+    }
+
+    /// Sets the name of the file the inference procedure writes its results to, within the output
+    /// directory passed to it. It defaults to `suggestions.json`.
+    #[inline]
+    pub fn output_file_name(mut self, output_file_name: impl Into<String>) -> Self {
+        self.output_file_name = output_file_name.into();
+        self
+    }
+
+    /// Sets the template of the video name passed to the inference procedure, which names its
+    /// output files after it. It defaults to `{name}`, the name of the video as it is.
+    #[inline]
+    pub fn video_name_template(mut self, video_name_template: VideoNameTemplate) -> Self {
+        self.video_name_template = video_name_template;
+        self
+    }
+
+    /// Sets an environment variable for the inference procedure, such as `CUDA_VISIBLE_DEVICES`.
+    /// The inference procedure inherits the environment of the backend otherwise.
+    #[inline]
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the tolerance of deduplicating suggestions, in seconds. A suggestion is dropped if an
+    /// earlier one of the same type starts and ends within `dedup_tolerance_sec` seconds of it.
+    /// It defaults to 0, which only drops exact duplicates.
+    #[inline]
+    pub fn dedup_tolerance_sec(mut self, dedup_tolerance_sec: u32) -> Self {
+        self.dedup_tolerance_sec = dedup_tolerance_sec;
+        self
+    }
+
+    /// Sets the number of the last lines of stderr kept in the report when the inference procedure
+    /// fails, for the tasks asking for the raw output. It defaults to 20.
+    #[inline]
+    pub fn stderr_tail_lines(mut self, stderr_tail_lines: usize) -> Self {
+        self.stderr_tail_lines = stderr_tail_lines;
+        self
+    }
+
+    /// Makes the inference procedure write its output to `<task ID>/<mode>` under `dir`, which is
+    /// kept after the analysis for inspecting the model artifacts. The mode keeps apart the outputs
+    /// of the tasks of an upload analyzed in several modes, which share the ID. By default, the
+    /// output is written to a temporary directory deleted after the analysis.
+    #[inline]
+    pub fn keep_inference_output(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.kept_output_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the directory the temporary output directories of the inference procedure are created
+    /// in. It defaults to the working directory. Pointing it into a directory that is removed on
+    /// shutdown makes sure that an analysis cut off by the shutdown leaves nothing behind.
+    #[inline]
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Makes the analyzer turn idle once it has had no task for `idle_timeout`, which is logged
+    /// and told by [`VideoAnalyzerBuffer`], so that an autoscaler can release an idle instance.
+    /// The analyzer keeps running while idle, and turns busy again on the next task.
+    #[inline]
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Makes the analyzer kill the inference procedure once its resident memory exceeds
+    /// `memory_limit` bytes, so that a single greedy analysis can't get the whole process killed
+    /// for running out of memory. The analysis then fails with a [`ResourceExceeded`], and the
+    /// peak memory of every analysis is logged. The memory is read from `/proc`, so this has no
+    /// effect on other platforms than Linux. There is no limit by default.
+    #[inline]
+    pub fn memory_limit(mut self, memory_limit: u64) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    /// Makes the analyzer salvage the suggestions the inference procedure wrote in full before it
+    /// failed, instead of discarding them, which is told by [`VideoAnalyzerReport::partial`]. The
+    /// analysis still fails if there is no such suggestion. It is disabled by default.
+    #[inline]
+    pub fn partial_results(mut self) -> Self {
+        self.partial_results = true;
+        self
+    }
+
+    /// Enables only the meme types in `meme_types`. The suggestions of the other types are removed
+    /// from the results of every analysis, including the raw output of the inference procedure,
+    /// so that they never reach a client. Every type is enabled by default.
+    #[inline]
+    pub fn meme_types(mut self, meme_types: impl IntoIterator<Item = MemeType>) -> Self {
+        self.meme_types = meme_types.into_iter().collect();
+        self
+    }
+
+    /// Makes `backend` analyze the tasks instead of the inference procedure, such as a
+    /// [`StubInference`] answering with canned outputs in tests. The settings of the inference
+    /// procedure, such as the enabled meme types, don't apply to `backend`.
+    #[inline]
+    pub fn backend(mut self, backend: impl InferenceBackend + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Waits for the next task and counts it as picked up, turning the analyzer idle if none
+    /// arrives within the idle timeout. Returns [`None`] once all the senders are gone.
+    fn next_task(&self) -> Option<SpawnedTask> {
+        let Some(idle_timeout) = self.idle_timeout else {
+            let task = self.scheduled.recv().ok()?;
+            self.load.task_started();
+            return Some(task);
+        };
+        loop {
+            match self.scheduled.recv_timeout(idle_timeout) {
+                Ok(task) => {
+                    if self.load.is_idle() {
+                        log::info!("the analyzer is busy again");
+                        self.load.set_idle(false);
+                    }
+                    self.load.task_started();
+                    return Some(task);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if !self.load.is_idle() {
+                        log::info!("the analyzer has been idle for {:?}", idle_timeout);
+                        self.load.set_idle(true);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Returns a [`ReadinessProbe`] checking the prerequisites of the inference procedure run by
+    /// this analyzer. It can be shared between threads, unlike the analyzer itself.
+    #[inline]
+    pub fn readiness_probe(&self) -> ReadinessProbe {
+        self.model.readiness_probe()
+    }
+
     /// Starts receving analysis requests. The requests are processed sequentially due to limited
     /// computing resources.
-    pub fn run(self) {
-        while let Ok(task) = self.scheduled.recv() {
-            let output = self.analyze(task.task());
-            let _ = task.send(output);
+    pub fn run(mut self) {
+        while let Some(task) = self.next_task() {
+            let id = task.task().id();
+            if task.is_cancelled() {
+                log::info!("[{}] the requester is gone, skipping the analysis", id);
+                self.load.task_cancelled();
+                continue;
+            }
+            task.set_running();
+            log::debug!("[{}] task received by the analyzer", id);
+            let started_at = Instant::now();
+            let started_time = OffsetDateTime::now_utc();
+            let mut output = match self.backend.as_mut() {
+                Some(backend) => backend.analyze(task.task()),
+                None => self.analyze(task.task()),
+            };
+            let elapsed = started_at.elapsed();
+            self.load.task_done(Some(elapsed));
+            self.stats.record(
+                elapsed,
+                output
+                    .as_ref()
+                    .is_ok_and(|report| !report.output.is_failed() && !report.partial),
+            );
+            if let Ok(report) = &mut output {
+                report.timing = Some(Box::new(AnalysisTiming {
+                    started_at: started_time,
+                    finished_at: started_time + elapsed,
+                    queue_wait: started_at.duration_since(task.queued_at()),
+                    inference: elapsed,
+                }));
+            }
+            if let Err(e) = &output {
+                log::error!(
+                    "[{}] {} analysis failed: {}",
+                    id,
+                    task.task().analyze_mode(),
+                    e
+                );
+            }
+            // The receiver is dropped if the client disconnected while the task was running.
+            // There is nowhere else to keep the results, so they are discarded.
+            if task.send(output).is_err() {
+                log::info!(
+                    "[{}] the requester is gone, discarding the analysis results",
+                    id
+                );
+            }
         }
     }
 
     /// This API dirves the whole video analysis pipeline and returns the analysis results.
     ///
-    /// This method returns a [`VideoAnalyzerOutput`] instance. If the inference procedure ends
-    /// successfully, it wraps the analysis results; otherwise, it simply wraps a [`None`] inside.
+    /// This method returns a [`VideoAnalyzerReport`] instance. If the inference procedure ends
+    /// successfully, its [`VideoAnalyzerOutput`] wraps the analysis results; otherwise, it simply
+    /// wraps a [`None`] inside. The warnings the inference procedure wrote to stderr are collected
+    /// into the report in either case.
     ///
     /// Note that the inference procedure crashing won't make this function failed. That is, even
     /// if the inference procedure exits within error, this function still returns an [`Ok`] that
     /// contains a [`VideoAnalyzerOutput`], which contains a [`None`]. This helps distinguishing
-    /// between the failure of the inference procedure and the function itself.
+    /// between the failure of the inference procedure and the function itself. If partial results
+    /// are enabled, the output holds the suggestions salvaged from the crashed procedure instead,
+    /// if there are any.
     ///
     /// # Errors
     /// An error is returned if the inference script can not be found, the inference procedure
-    /// can not be spawned for whatever reason, or the analysis results aren't parsed successfully.
+    /// can not be spawned for whatever reason, the inference procedure exits successfully without
+    /// writing the output file, or the analysis results aren't parsed successfully.
     fn analyze(&self, task: &Task) -> VideoAnalyzerResult {
-        let out_dir = TempDir::new_in(".")?;
+        let id = task.id();
+        let model = match task.model() {
+            Some(name) => self.models.get(name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no inference model is named {:?}", name),
+                )
+            })?,
+            None => &self.model,
+        };
+        let started_at = OffsetDateTime::now_utc();
+        let video_name = self
+            .video_name_template
+            .render(task.video_name(), id, started_at);
+        let out_dir = match &self.kept_output_dir {
+            Some(dir) => {
+                let dir = dir
+                    .join(id.to_string())
+                    .join(task.analyze_mode().to_string());
+                std::fs::create_dir_all(&dir)?;
+                log::info!("[{}] keeping inference output in {}", id, dir.display());
+                let meta = KeptOutputMeta {
+                    id,
+                    mode: VideoAnalyzerModeDesc::new(task.analyze_mode()),
+                    quality: task.quality(),
+                    fps_sample: task.fps_sample(),
+                    model: task.model(),
+                    file_name: task.file_name(),
+                    video_name: &video_name,
+                    output_file_name: &self.output_file_name,
+                    started_at,
+                };
+                std::fs::write(
+                    dir.join(KEPT_OUTPUT_META_FILE_NAME),
+                    serde_json::to_vec_pretty(&meta)?,
+                )?;
+                OutputDir::Kept(dir)
+            }
+            None => OutputDir::Temp(TempDir::new_in(&self.temp_dir)?),
+        };
         let video_path = task.video_path();
-        let video_name = task.video_name();
-        let analyze_mode_desc = task.analyze_mode().desc();
+        let analyze_mode_desc = task.analyze_mode().to_string();
 
-        log::info!("starting inference procedure");
-        log::debug!(
-            "working directory is set to {}",
-            self.inference_dir.display()
-        );
-        log::debug!(
-            "running command: {} {} --video_path {} --video_name {} --mode {} --output_dir {}",
-            self.interpreter_path.display(),
-            self.inference_script_path.display(),
-            video_path.display(),
-            video_name,
-            &analyze_mode_desc,
-            out_dir.path().display()
-        );
-
-        let output = Command::new(&self.interpreter_path)
-            .current_dir(&self.inference_dir)
-            .arg(&self.inference_script_path)
+        let mut command = Command::new(model.interpreter_path());
+        command
+            .current_dir(model.inference_dir())
+            .envs(self.envs.iter().map(|(key, value)| (key, value)))
+            .arg(model.inference_script_path())
             .arg("--video_path")
-            .arg(task.video_path())
+            .arg(video_path)
             .arg("--video_name")
-            .arg(task.video_name())
+            .arg(&video_name)
             .arg("--mode")
             .arg(&analyze_mode_desc)
             .arg("--output_dir")
-            .arg(out_dir.path())
-            .output()?;
+            .arg(out_dir.path());
+        if let Some(start_sec) = task.start_sec() {
+            command.arg("--start").arg(start_sec.to_string());
+        }
+        if let Some(end_sec) = task.end_sec() {
+            command.arg("--end").arg(end_sec.to_string());
+        }
+        if let Some(fps_sample) = task.fps_sample() {
+            command.arg("--fps").arg(fps_sample.to_string());
+        }
+        if task.quality() == AnalysisQuality::Preview {
+            command.arg("--preview");
+        }
+        if task.image() {
+            command.arg("--image");
+        }
+        command.args(model.args());
+
+        log::info!(
+            "[{}] starting inference procedure in {} mode",
+            id,
+            task.analyze_mode()
+        );
+        log::debug!(
+            "[{}] working directory is set to {}",
+            id,
+            model.inference_dir().display()
+        );
+        log::debug!(
+            "[{}] running command: {}",
+            id,
+            inference::command_line(&command)
+        );
+        let (status, stderr) = match self.memory_limit {
+            Some(limit) => {
+                let output = watchdog::output(&mut command, limit).inspect_err(|e| {
+                    if let Some(exceeded) = ResourceExceeded::find(e) {
+                        log::warn!(
+                            "[{}] inference procedure killed at {} bytes of memory",
+                            id,
+                            exceeded.peak()
+                        );
+                    }
+                })?;
+                match output.peak_memory {
+                    Some(peak) => log::info!(
+                        "[{}] peak memory of the inference procedure: {} bytes",
+                        id,
+                        peak
+                    ),
+                    None => log::warn!(
+                        "[{}] the memory of the inference procedure couldn't be read",
+                        id
+                    ),
+                }
+                (output.status, output.stderr)
+            }
+            None => {
+                let output = command.output()?;
+                (output.status, output.stderr)
+            }
+        };
+
+        let stderr = String::from_utf8_lossy(&stderr);
+        let (warnings, others) = inference::split_warnings(&stderr);
+        for warning in warnings.iter() {
+            log::warn!("[{}] inference procedure reported: {}", id, warning);
+        }
 
-        if output.status.success() {
-            log::info!("inference procedure exited successfully");
-            let inference_out_path = out_dir.path().join("suggestions.json");
+        if status.success() {
+            log::info!("[{}] inference procedure exited successfully", id);
+            for line in others {
+                log::debug!("[{}] inference stderr: {}", id, line);
+            }
+            let inference_out_path = out_dir.path().join(&self.output_file_name);
             log::debug!(
-                "parsing inference results from {}",
+                "[{}] parsing inference results from {}",
+                id,
                 inference_out_path.display()
             );
-            let inference_out_str = std::fs::read_to_string(&inference_out_path)?;
+            let inference_out_str =
+                std::fs::read_to_string(&inference_out_path).map_err(|e| match e.kind() {
+                    // A `NotFound` would be answered with a 404 as if the video were missing.
+                    io::ErrorKind::NotFound => io::Error::other(format!(
+                        "the inference procedure didn't write {} to its output directory",
+                        self.output_file_name
+                    )),
+                    _ => e,
+                })?;
             let inference_output: InferenceOutput = serde_json::from_str(&inference_out_str)?;
+            let raw_output = if task.raw_output() {
+                let mut raw_output = serde_json::from_str(&inference_out_str)?;
+                retain_raw_meme_types(&mut raw_output, &self.meme_types);
+                Some(raw_output)
+            } else {
+                None
+            };
 
-            Ok(VideoAnalyzerOutput::from(inference_output))
+            Ok(VideoAnalyzerReport {
+                output: self.convert_output(task, inference_output),
+                warnings,
+                raw_output,
+                stderr_tail: None,
+                partial: false,
+                timing: None,
+            })
         } else {
-            let stderr = String::from_utf8_lossy(&output.stderr);
             log::error!(
-                "inference procedure exited within error; dumping stderr:\n{}",
+                "[{}] inference procedure exited within error; dumping stderr:\n{}",
+                id,
                 stderr
             );
 
-            Ok(VideoAnalyzerOutput::default())
+            // The paths would tell the layout of the host to the client.
+            let stderr_tail = task.raw_output().then(|| {
+                inference::stderr_tail(
+                    &stderr,
+                    self.stderr_tail_lines,
+                    &[
+                        (&model.inference_dir().to_string_lossy(), "<inference_dir>"),
+                        (&out_dir.path().to_string_lossy(), "<output_dir>"),
+                        (&video_path.to_string_lossy(), "<video_path>"),
+                    ],
+                )
+            });
+            let partial_output = self
+                .partial_results
+                .then(|| self.read_partial_output(task, out_dir.path()))
+                .flatten();
+            Ok(VideoAnalyzerReport {
+                partial: partial_output.is_some(),
+                output: partial_output.unwrap_or_default(),
+                warnings,
+                raw_output: None,
+                stderr_tail,
+                timing: None,
+            })
         }
     }
+
+    /// Reads the output the inference procedure of `task` wrote to `out_dir` before it failed, and
+    /// converts the suggestions written in full. Returns [`None`] if there is no such suggestion.
+    fn read_partial_output(&self, task: &Task, out_dir: &Path) -> Option<VideoAnalyzerOutput> {
+        let id = task.id();
+        let inference_out_path = out_dir.join(&self.output_file_name);
+        let inference_out_str = std::fs::read_to_string(&inference_out_path).ok()?;
+        let Some(inference_output) = InferenceOutput::parse_partial(&inference_out_str) else {
+            log::info!(
+                "[{}] no suggestions can be salvaged from {}",
+                id,
+                inference_out_path.display()
+            );
+            return None;
+        };
+        let output = self.convert_output(task, inference_output);
+        log::warn!(
+            "[{}] returning {} suggestions salvaged from the partial output",
+            id,
+            output.suggestions().map_or(0, <[_]>::len)
+        );
+        Some(output)
+    }
+
+    /// Converts the output of the inference procedure of `task` into the suggestions, removing the
+    /// disabled meme types and the duplicates.
+    fn convert_output(
+        &self,
+        task: &Task,
+        inference_output: InferenceOutput,
+    ) -> VideoAnalyzerOutput {
+        let id = task.id();
+        let mut output = VideoAnalyzerOutput::from(inference_output);
+        let disabled = output.retain_meme_types(&self.meme_types);
+        if disabled > 0 {
+            log::debug!(
+                "[{}] {} suggestions of disabled meme types removed",
+                id,
+                disabled
+            );
+        }
+        let removed = output.dedup(self.dedup_tolerance_sec);
+        if removed > 0 {
+            log::debug!("[{}] {} duplicate suggestions removed", id, removed);
+        }
+        // The inference procedure reports timestamps relative to the start of the analyzed time
+        // range, so they are shifted back to be relative to the start of the video.
+        output.offset(task.start_sec().unwrap_or(0));
+        if task.image() {
+            let dropped = output.collapse_to_frame();
+            if dropped > 0 {
+                log::warn!(
+                    "[{}] {} suggestions dropped, since an image gets a single one",
+                    id,
+                    dropped
+                );
+            }
+        }
+        output
+    }
 }
 
-#[derive(Debug, Copy, Clone, Serialize_repr)]
+/// The category of a suggested meme.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize_repr, ToSchema)]
 #[repr(u8)]
-enum MemeType {
+pub enum MemeType {
     Happiness = 0,
     Love = 1,
     Anger = 2,
@@ -186,49 +768,447 @@ enum MemeType {
     Surprise = 5,
 }
 
-#[derive(Debug, Serialize)]
+impl MemeType {
+    /// Every meme type, in the order of their numeric values.
+    pub const ALL: [Self; 6] = [
+        Self::Happiness,
+        Self::Love,
+        Self::Anger,
+        Self::Sorrow,
+        Self::Hate,
+        Self::Surprise,
+    ];
+}
+
+/// Renders the meme type as its name, such as `happiness`.
+impl fmt::Display for MemeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Happiness => "happiness",
+            Self::Love => "love",
+            Self::Anger => "anger",
+            Self::Sorrow => "sorrow",
+            Self::Hate => "hate",
+            Self::Surprise => "surprise",
+        })
+    }
+}
+
+/// Parses a meme type from its name, such as `happiness`, which is also how the inference
+/// procedure reports it.
+impl FromStr for MemeType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|meme_type| meme_type.to_string() == s)
+            .ok_or_else(|| format!("unknown meme type {:?}", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
 #[repr(transparent)]
 struct MemeTypeDesc(String);
 
 impl MemeTypeDesc {
     #[inline]
     fn new(meme_type: MemeType) -> Self {
-        use MemeType::*;
-
-        match meme_type {
-            Happiness => Self(String::from("happiness")),
-            Love => Self(String::from("love")),
-            Anger => Self(String::from("anger")),
-            Sorrow => Self(String::from("sorrow")),
-            Hate => Self(String::from("hate")),
-            Surprise => Self(String::from("surprise")),
-        }
+        Self(meme_type.to_string())
     }
 }
 
-#[derive(Debug, Serialize)]
-struct VideoAnalyzerSuggestion {
+/// A part of a video suggested to be made into a meme.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct VideoAnalyzerSuggestion {
     start: u32,
     end: u32,
+    /// The index of the frame at `start`, present only if the frame rate of the video is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_frame: Option<u64>,
+    /// The index of the frame at `end`, present only if the frame rate of the video is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_frame: Option<u64>,
     meme_type: MemeType,
     meme_type_desc: MemeTypeDesc,
 }
 
 impl VideoAnalyzerSuggestion {
+    /// Creates a suggestion of the part of a video from `start` to `end` seconds.
     #[inline]
-    fn new(start: u32, end: u32, meme_type: MemeType) -> Self {
+    pub fn new(start: u32, end: u32, meme_type: MemeType) -> Self {
         Self {
             start,
             end,
+            start_frame: None,
+            end_frame: None,
             meme_type,
             meme_type_desc: MemeTypeDesc::new(meme_type),
         }
     }
+
+    /// Returns when the suggested part starts, in seconds from the beginning of the video.
+    #[inline]
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Returns when the suggested part ends, in seconds from the beginning of the video.
+    #[inline]
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Returns the category of the suggested meme.
+    #[inline]
+    pub fn meme_type(&self) -> MemeType {
+        self.meme_type
+    }
 }
 
-#[derive(Debug, Default, Serialize)]
+/// Removes the units of the raw output of the inference procedure suggesting a meme type not in
+/// `meme_types`. The units suggesting an unknown type are kept, since the raw output is meant to
+/// tell what the inference procedure wrote.
+fn retain_raw_meme_types(raw_output: &mut serde_json::Value, meme_types: &[MemeType]) {
+    if let Some(units) = raw_output.as_array_mut() {
+        units.retain(|unit| {
+            unit["suggestion"]
+                .as_str()
+                .and_then(|suggestion| suggestion.parse().ok())
+                .is_none_or(|meme_type| meme_types.contains(&meme_type))
+        });
+    }
+}
+
+/// Converts `secs` seconds to the index of the frame at that moment of a video of `fps` frames per
+/// second.
+#[inline]
+fn frame_at(secs: u32, fps: f64) -> u64 {
+    (f64::from(secs) * fps).round() as u64
+}
+
+/// The suggestions of an analysis, or [`None`] if the inference procedure failed.
+///
+/// The suggestions converted from an [`InferenceOutput`] are ordered by `start`, with ties broken
+/// by `end` and then by the numeric value of `meme_type`, so the order doesn't depend on the order
+/// the inference procedure reported them in.
+#[derive(Debug, Default, Clone, Serialize, ToSchema)]
 #[repr(transparent)]
-pub(crate) struct VideoAnalyzerOutput(Option<Vec<VideoAnalyzerSuggestion>>);
+pub struct VideoAnalyzerOutput(Option<Vec<VideoAnalyzerSuggestion>>);
+
+impl VideoAnalyzerOutput {
+    /// Returns the suggestions in the order documented on [`VideoAnalyzerOutput`], or [`None`] if
+    /// the inference procedure failed.
+    #[inline]
+    pub fn suggestions(&self) -> Option<&[VideoAnalyzerSuggestion]> {
+        self.0.as_deref()
+    }
+
+    /// Shifts all the suggestions `secs` seconds later.
+    fn offset(&mut self, secs: u32) {
+        for suggestion in self.0.iter_mut().flatten() {
+            suggestion.start = suggestion.start.saturating_add(secs);
+            suggestion.end = suggestion.end.saturating_add(secs);
+        }
+    }
+
+    /// Multiplies the boundaries of all the suggestions by `factor`, for reporting them in a unit
+    /// finer than seconds. This should be called last, since the other methods take the
+    /// boundaries to be in seconds.
+    pub(crate) fn scale_times(&mut self, factor: u32) {
+        for suggestion in self.0.iter_mut().flatten() {
+            suggestion.start = suggestion.start.saturating_mul(factor);
+            suggestion.end = suggestion.end.saturating_mul(factor);
+        }
+    }
+
+    /// Keeps the first suggestion only and makes it span the whole frame of a still image, which
+    /// starts and ends at 0. The suggestions must be in the order documented on
+    /// [`VideoAnalyzerOutput`].
+    ///
+    /// Returns the number of the dropped suggestions.
+    fn collapse_to_frame(&mut self) -> usize {
+        let Some(suggestions) = self.0.as_mut() else {
+            return 0;
+        };
+        let len = suggestions.len();
+        suggestions.truncate(1);
+        for suggestion in suggestions.iter_mut() {
+            suggestion.start = 0;
+            suggestion.end = 0;
+        }
+        len - suggestions.len()
+    }
+
+    /// Returns whether the inference procedure failed, leaving no suggestions.
+    #[inline]
+    pub fn is_failed(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Counts the suggestions and sums up their durations per [`MemeType`].
+    pub(crate) fn summary(&self) -> VideoAnalyzerSummary {
+        VideoAnalyzerSummary(self.0.as_ref().map(|suggestions| {
+            let mut summaries = [MemeTypeSummary::default(); MemeType::ALL.len()];
+            for suggestion in suggestions {
+                let summary = &mut summaries[suggestion.meme_type as usize];
+                summary.count += 1;
+                summary.total_seconds += suggestion.end.saturating_sub(suggestion.start);
+            }
+            summaries
+        }))
+    }
+
+    /// Adds the frame indices at the boundaries of the suggestions, given the frame rate `fps` of
+    /// the video. This should be called after the suggestions are finalized, since the frame
+    /// indices aren't updated along with the boundaries.
+    pub(crate) fn set_frame_rate(&mut self, fps: f64) {
+        for suggestion in self.0.iter_mut().flatten() {
+            suggestion.start_frame = Some(frame_at(suggestion.start, fps));
+            suggestion.end_frame = Some(frame_at(suggestion.end, fps));
+        }
+    }
+
+    /// Removes the duplicate suggestions, keeping the first of them. A suggestion is a duplicate of
+    /// an earlier one if they are of the same type, and both their starts and ends are within
+    /// `tolerance_sec` seconds of each other. The suggestions must be in the order documented on
+    /// [`VideoAnalyzerOutput`].
+    ///
+    /// Returns the number of the removed suggestions.
+    fn dedup(&mut self, tolerance_sec: u32) -> usize {
+        let Some(suggestions) = self.0.as_mut() else {
+            return 0;
+        };
+        let len = suggestions.len();
+        let mut kept: Vec<VideoAnalyzerSuggestion> = Vec::with_capacity(len);
+        for suggestion in suggestions.drain(..) {
+            // `kept` is ordered by `start` as well, so only its tail can be near `suggestion`.
+            let duplicate = kept
+                .iter()
+                .rev()
+                .take_while(|k| suggestion.start - k.start <= tolerance_sec)
+                .any(|k| {
+                    k.meme_type == suggestion.meme_type
+                        && k.end.abs_diff(suggestion.end) <= tolerance_sec
+                });
+            if !duplicate {
+                kept.push(suggestion);
+            }
+        }
+        *suggestions = kept;
+        len - suggestions.len()
+    }
+
+    /// Partitions the timeline into non-overlapping suggestions, for a client showing them on a
+    /// single track. The suggestions carry no confidence, so wherever several of them overlap, the
+    /// one of the type with the lowest numeric value wins. The adjacent spans of the same type are
+    /// merged, and a suggestion covering no time is dropped unless it's the only one, such as the
+    /// one of a still image. The frame indices are cleared, so this should be called before
+    /// [`Self::set_frame_rate`].
+    pub(crate) fn flatten(&mut self) {
+        let Some(suggestions) = self.0.as_mut() else {
+            return;
+        };
+        if suggestions.len() <= 1 {
+            return;
+        }
+        let mut bounds: Vec<u32> = suggestions.iter().flat_map(|s| [s.start, s.end]).collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let mut flat: Vec<VideoAnalyzerSuggestion> = Vec::new();
+        for span in bounds.windows(2) {
+            let (start, end) = (span[0], span[1]);
+            let Some(meme_type) = suggestions
+                .iter()
+                .filter(|s| s.start <= start && end <= s.end)
+                .map(|s| s.meme_type)
+                .min_by_key(|&meme_type| meme_type as u8)
+            else {
+                continue;
+            };
+            match flat.last_mut() {
+                Some(last) if last.end == start && last.meme_type == meme_type => last.end = end,
+                _ => flat.push(VideoAnalyzerSuggestion::new(start, end, meme_type)),
+            }
+        }
+        *suggestions = flat;
+    }
+
+    /// Fits the suggestions into a video lasting `duration_sec` seconds, since a suggestion past
+    /// the end indicates a bug of the model. The suggestions ending after `duration_sec` are
+    /// clamped to end at `duration_sec`, and the ones starting at or after `duration_sec` are
+    /// dropped.
+    ///
+    /// Returns the numbers of the clamped and the dropped suggestions.
+    pub(crate) fn clamp_to_duration(&mut self, duration_sec: u32) -> (usize, usize) {
+        let Some(suggestions) = self.0.as_mut() else {
+            return (0, 0);
+        };
+        let len = suggestions.len();
+        suggestions.retain(|suggestion| suggestion.start < duration_sec);
+        let dropped = len - suggestions.len();
+        let mut clamped = 0;
+        for suggestion in suggestions.iter_mut().filter(|s| s.end > duration_sec) {
+            suggestion.end = duration_sec;
+            clamped += 1;
+        }
+        (clamped, dropped)
+    }
+
+    /// Removes the suggestions of the types not in `meme_types`.
+    ///
+    /// Returns the number of the removed suggestions.
+    fn retain_meme_types(&mut self, meme_types: &[MemeType]) -> usize {
+        let Some(suggestions) = self.0.as_mut() else {
+            return 0;
+        };
+        let len = suggestions.len();
+        suggestions.retain(|suggestion| meme_types.contains(&suggestion.meme_type));
+        len - suggestions.len()
+    }
+
+    /// Keeps the first `max_suggestions` suggestions and drops the rest, so that a pathological
+    /// input can't produce an arbitrarily large response. The suggestions are ordered by `start`,
+    /// so the ones at the end of the video are dropped first.
+    ///
+    /// Returns the number of the dropped suggestions.
+    pub(crate) fn truncate(&mut self, max_suggestions: usize) -> usize {
+        let Some(suggestions) = self.0.as_mut() else {
+            return 0;
+        };
+        let len = suggestions.len();
+        suggestions.truncate(max_suggestions);
+        len - suggestions.len()
+    }
+}
+
+/// The count and the total duration of the suggestions of a [`MemeType`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, ToSchema)]
+struct MemeTypeSummary {
+    count: usize,
+    total_seconds: u32,
+}
+
+/// The summary of a [`VideoAnalyzerOutput`] per [`MemeType`], serialized as a map from
+/// `meme_type_desc` to [`MemeTypeSummary`], such as `{"happiness": {"count": 3, "total_seconds":
+/// 12}, ...}`. Every type is present, in the order of `meme_type`, or the summary is [`None`] if
+/// the inference procedure failed.
+#[derive(Debug, PartialEq, Eq, ToSchema)]
+#[schema(value_type = Option<BTreeMap<String, MemeTypeSummary>>)]
+#[repr(transparent)]
+pub(crate) struct VideoAnalyzerSummary(Option<[MemeTypeSummary; MemeType::ALL.len()]>);
+
+impl Serialize for VideoAnalyzerSummary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(summaries) = &self.0 else {
+            return serializer.serialize_none();
+        };
+        serializer.collect_map(
+            MemeType::ALL
+                .iter()
+                .zip(summaries)
+                .map(|(&meme_type, summary)| (MemeTypeDesc::new(meme_type).0, summary)),
+        )
+    }
+}
+
+/// [`VideoAnalyzerOutput`] serialized in the nested shape, where the `meme_type` and
+/// `meme_type_desc` fields of each suggestion are merged into a single `meme_type` field in the
+/// form of [`KeyedId`].
+#[derive(Debug, ToSchema)]
+#[schema(value_type = Option<Vec<NestedSuggestion>>)]
+#[repr(transparent)]
+pub(crate) struct NestedVideoAnalyzerOutput(VideoAnalyzerOutput);
+
+/// A [`VideoAnalyzerSuggestion`] in the nested shape.
+#[derive(Serialize, ToSchema)]
+struct NestedSuggestion {
+    start: u32,
+    end: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_frame: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_frame: Option<u64>,
+    meme_type: KeyedId,
+}
+
+impl From<VideoAnalyzerOutput> for NestedVideoAnalyzerOutput {
+    #[inline]
+    fn from(output: VideoAnalyzerOutput) -> Self {
+        Self(output)
+    }
+}
+
+impl Serialize for NestedVideoAnalyzerOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(suggestions) = &self.0.0 else {
+            return serializer.serialize_none();
+        };
+        serializer.collect_seq(suggestions.iter().map(|suggestion| NestedSuggestion {
+            start: suggestion.start,
+            end: suggestion.end,
+            start_frame: suggestion.start_frame,
+            end_frame: suggestion.end_frame,
+            meme_type: KeyedId {
+                id: suggestion.meme_type as u8,
+                key: suggestion.meme_type_desc.0.clone(),
+            },
+        }))
+    }
+}
+
+/// [`VideoAnalyzerOutput`] serialized as a map from `meme_type_desc` to the suggestions of that
+/// type, such as `{"happiness": [...], "anger": [...]}`, for a client rendering a lane per meme
+/// type. The types are in the order of `meme_type`, the types without a suggestion are omitted,
+/// and each group keeps the order documented on [`VideoAnalyzerOutput`]. It's [`None`] if the
+/// inference procedure failed.
+#[derive(Debug, ToSchema)]
+#[schema(value_type = Option<BTreeMap<String, Vec<GroupedSuggestion>>>)]
+#[repr(transparent)]
+pub(crate) struct GroupedVideoAnalyzerOutput(VideoAnalyzerOutput);
+
+/// A [`VideoAnalyzerSuggestion`] in a group of [`GroupedVideoAnalyzerOutput`], whose meme type is
+/// the key of the group.
+#[derive(Serialize, ToSchema)]
+struct GroupedSuggestion {
+    start: u32,
+    end: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_frame: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_frame: Option<u64>,
+}
+
+impl From<VideoAnalyzerOutput> for GroupedVideoAnalyzerOutput {
+    #[inline]
+    fn from(output: VideoAnalyzerOutput) -> Self {
+        Self(output)
+    }
+}
+
+impl Serialize for GroupedVideoAnalyzerOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(suggestions) = &self.0.0 else {
+            return serializer.serialize_none();
+        };
+        let mut groups: [Vec<GroupedSuggestion>; MemeType::ALL.len()] = Default::default();
+        for suggestion in suggestions {
+            groups[suggestion.meme_type as usize].push(GroupedSuggestion {
+                start: suggestion.start,
+                end: suggestion.end,
+                start_frame: suggestion.start_frame,
+                end_frame: suggestion.end_frame,
+            });
+        }
+        serializer.collect_map(
+            MemeType::ALL
+                .iter()
+                .zip(groups)
+                .filter(|(_, group)| !group.is_empty())
+                .map(|(&meme_type, group)| (MemeTypeDesc::new(meme_type).0, group)),
+        )
+    }
+}
 
 impl FromIterator<VideoAnalyzerSuggestion> for VideoAnalyzerOutput {
     fn from_iter<T: IntoIterator<Item = VideoAnalyzerSuggestion>>(iter: T) -> Self {
@@ -236,26 +1216,710 @@ impl FromIterator<VideoAnalyzerSuggestion> for VideoAnalyzerOutput {
     }
 }
 
+impl From<Vec<VideoAnalyzerSuggestion>> for VideoAnalyzerOutput {
+    /// Wraps `suggestions` as they are. They should be in the order documented on
+    /// [`VideoAnalyzerOutput`] for the transforms that depend on it, such as deduplication.
+    #[inline]
+    fn from(suggestions: Vec<VideoAnalyzerSuggestion>) -> Self {
+        Self(Some(suggestions))
+    }
+}
+
+/// Sorts `suggestions` in the order documented on [`VideoAnalyzerOutput`].
+fn sort_suggestions(suggestions: &mut [VideoAnalyzerSuggestion]) {
+    suggestions
+        .sort_by_key(|suggestion| (suggestion.start, suggestion.end, suggestion.meme_type as u8));
+}
+
+/// Builds a [`VideoAnalyzerOutput`] for tests, sorting the suggestions as the ones converted from
+/// an [`InferenceOutput`] are.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct VideoAnalyzerOutputBuilder(Vec<VideoAnalyzerSuggestion>);
+
+#[cfg(test)]
+impl VideoAnalyzerOutputBuilder {
+    /// Adds a suggestion of `meme_type` from `start` to `end` seconds.
+    #[inline]
+    pub(crate) fn suggestion(mut self, start: u32, end: u32, meme_type: MemeType) -> Self {
+        self.0
+            .push(VideoAnalyzerSuggestion::new(start, end, meme_type));
+        self
+    }
+
+    pub(crate) fn build(mut self) -> VideoAnalyzerOutput {
+        sort_suggestions(&mut self.0);
+        VideoAnalyzerOutput::from(self.0)
+    }
+}
+
 impl From<InferenceOutput> for VideoAnalyzerOutput {
     fn from(output: InferenceOutput) -> Self {
-        let suggestions: Vec<VideoAnalyzerSuggestion> = output
+        let mut suggestions: Vec<VideoAnalyzerSuggestion> = output
             .into_inner()
             .into_iter()
             .filter_map(|unit| {
-                let meme_type = match unit.suggestion.as_str() {
-                    "happiness" => MemeType::Happiness,
-                    "love" => MemeType::Love,
-                    "anger" => MemeType::Anger,
-                    "sorrow" => MemeType::Sorrow,
-                    "hate" => MemeType::Hate,
-                    "surprise" => MemeType::Surprise,
-                    _ => return None,
-                };
+                let meme_type = unit.suggestion.parse().ok()?;
                 Some(VideoAnalyzerSuggestion::new(
                     unit.start, unit.end, meme_type,
                 ))
             })
             .collect();
-        Self::from_iter(suggestions)
+        sort_suggestions(&mut suggestions);
+        Self::from(suggestions)
+    }
+}
+
+/// The description of an analysis whose inference output is kept, written to `meta.json` in the
+/// same directory, so that external tools can consume the kept results without the API.
+#[derive(Debug, Serialize)]
+struct KeptOutputMeta<'a> {
+    id: Uuid,
+    mode: VideoAnalyzerModeDesc,
+    quality: AnalysisQuality,
+    /// The frame sampling rate requested of the inference procedure, if any.
+    fps_sample: Option<f64>,
+    /// The name of the inference model, if not the default one.
+    model: Option<&'a str>,
+    /// The file name the video was uploaded with, if known.
+    file_name: Option<&'a str>,
+    /// The video name passed to the inference procedure, rendered from the template.
+    video_name: &'a str,
+    /// The name of the file the inference procedure writes its results to.
+    output_file_name: &'a str,
+    #[serde(with = "time::serde::rfc3339")]
+    started_at: OffsetDateTime,
+}
+
+/// The directory the inference procedure writes its output to.
+enum OutputDir {
+    /// A temporary directory, which is deleted when dropped.
+    Temp(TempDir),
+    /// A directory kept after the analysis.
+    Kept(PathBuf),
+}
+
+impl OutputDir {
+    #[inline]
+    fn path(&self) -> &Path {
+        match self {
+            Self::Temp(dir) => dir.path(),
+            Self::Kept(dir) => dir,
+        }
+    }
+}
+
+/// When the analyzer worked on a task, and how long the task waited for it.
+#[derive(Debug, Copy, Clone)]
+pub struct AnalysisTiming {
+    pub started_at: OffsetDateTime,
+    pub finished_at: OffsetDateTime,
+    /// How long the task waited in the queue before the analyzer picked it up.
+    pub queue_wait: Duration,
+    /// How long the analysis took, from picking up the task to collecting the results.
+    pub inference: Duration,
+}
+
+/// The analysis results of a task along with the warnings reported by the inference procedure.
+#[derive(Debug, Default)]
+pub struct VideoAnalyzerReport {
+    pub output: VideoAnalyzerOutput,
+    pub warnings: Vec<String>,
+    /// The output of the inference procedure as it was written, if the task asked for it and the
+    /// inference procedure succeeded.
+    pub raw_output: Option<serde_json::Value>,
+    /// The last lines of the stderr of the inference procedure with the paths redacted, if the
+    /// task asked for the raw output and the inference procedure failed.
+    pub stderr_tail: Option<Vec<String>>,
+    /// Whether the inference procedure failed, and `output` holds the suggestions salvaged from
+    /// what it wrote before failing, as enabled by [`VideoAnalyzer::partial_results`].
+    pub partial: bool,
+    /// When the analysis ran, filled in by the analyzer once the task is done. It's boxed to keep
+    /// the results small while they are passed around.
+    pub timing: Option<Box<AnalysisTiming>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::TaskState;
+
+    #[test]
+    fn test_deserialize_mode() {
+        let mode = |json: &str| serde_json::from_str::<VideoAnalyzerMode>(json);
+
+        assert!(matches!(mode("0"), Ok(VideoAnalyzerMode::Binary)));
+        assert!(matches!(mode("1"), Ok(VideoAnalyzerMode::Multi)));
+        for json in ["2", "-1", "1.5", r#""multi""#, "null"] {
+            assert_eq!(
+                mode(json).unwrap_err().to_string(),
+                "mode must be 0 (binary) or 1 (multi)",
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_mode_round_trip() {
+        for mode in [VideoAnalyzerMode::Binary, VideoAnalyzerMode::Multi] {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+            assert_eq!((mode as u8).to_string().parse(), Ok(mode));
+        }
+        assert_eq!(VideoAnalyzerMode::Multi.to_string(), "multi");
+        assert_eq!(
+            serde_json::to_value(VideoAnalyzerModeDesc::new(VideoAnalyzerMode::Binary)).unwrap(),
+            "binary"
+        );
+        for s in ["2", "Multi", " multi", ""] {
+            assert_eq!(
+                s.parse::<VideoAnalyzerMode>().unwrap_err(),
+                INVALID_MODE,
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_output_from_inference_output() {
+        let inference_output: InferenceOutput = serde_json::from_str(
+            r#"[
+                {"start": 0, "end": 30, "suggestion": "happiness"},
+                {"start": 30, "end": 60, "suggestion": "boredom"},
+                {"start": 60, "end": 90, "suggestion": "surprise"}
+            ]"#,
+        )
+        .unwrap();
+        let output = VideoAnalyzerOutput::from(inference_output);
+
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Happiness),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Surprise),
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!([
+                {"start": 0, "end": 30, "meme_type": 0, "meme_type_desc": "happiness"},
+                {"start": 60, "end": 90, "meme_type": 5, "meme_type_desc": "surprise"},
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(VideoAnalyzerOutput::default()).unwrap(),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn test_output_order() {
+        let inference_output: InferenceOutput = serde_json::from_str(
+            r#"[
+                {"start": 60, "end": 90, "suggestion": "love"},
+                {"start": 0, "end": 30, "suggestion": "surprise"},
+                {"start": 0, "end": 20, "suggestion": "sorrow"},
+                {"start": 0, "end": 30, "suggestion": "anger"},
+                {"start": 0, "end": 30, "suggestion": "happiness"}
+            ]"#,
+        )
+        .unwrap();
+        let output = VideoAnalyzerOutput::from(inference_output);
+
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 20, MemeType::Sorrow),
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Happiness),
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Surprise),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_output_builder() {
+        let output = VideoAnalyzerOutputBuilder::default()
+            .suggestion(60, 90, MemeType::Love)
+            .suggestion(0, 30, MemeType::Surprise)
+            .suggestion(0, 30, MemeType::Happiness)
+            .build();
+
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Happiness),
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Surprise),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            ])
+        );
+        assert_eq!(
+            VideoAnalyzerOutputBuilder::default().build().0,
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_output_summary() {
+        let output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 5, MemeType::Happiness),
+            VideoAnalyzerSuggestion::new(10, 13, MemeType::Happiness),
+            VideoAnalyzerSuggestion::new(20, 50, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(60, 64, MemeType::Happiness),
+        ]);
+        assert_eq!(
+            serde_json::to_value(output.summary()).unwrap(),
+            serde_json::json!({
+                "happiness": {"count": 3, "total_seconds": 12},
+                "love": {"count": 0, "total_seconds": 0},
+                "anger": {"count": 1, "total_seconds": 30},
+                "sorrow": {"count": 0, "total_seconds": 0},
+                "hate": {"count": 0, "total_seconds": 0},
+                "surprise": {"count": 0, "total_seconds": 0},
+            })
+        );
+
+        let summary = VideoAnalyzerOutput::default().summary();
+        assert_eq!(summary, VideoAnalyzerSummary(None));
+        assert_eq!(
+            serde_json::to_value(summary).unwrap(),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn test_nested_output() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Happiness),
+            VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+        ]);
+        output.set_frame_rate(24.0);
+        assert_eq!(
+            serde_json::to_value(NestedVideoAnalyzerOutput::from(output)).unwrap(),
+            serde_json::json!([
+                {"start": 0, "end": 30, "start_frame": 0, "end_frame": 720, "meme_type": {"id": 0, "key": "happiness"}},
+                {"start": 60, "end": 90, "start_frame": 1440, "end_frame": 2160, "meme_type": {"id": 1, "key": "love"}},
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(NestedVideoAnalyzerOutput::from(
+                VideoAnalyzerOutput::default()
+            ))
+            .unwrap(),
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            serde_json::to_value(VideoAnalyzerMode::Multi.keyed()).unwrap(),
+            serde_json::json!({"id": 1, "key": "multi"})
+        );
+    }
+
+    #[test]
+    fn test_grouped_output() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(10, 20, MemeType::Happiness),
+            VideoAnalyzerSuggestion::new(60, 90, MemeType::Anger),
+        ]);
+        output.set_frame_rate(24.0);
+        let grouped = GroupedVideoAnalyzerOutput::from(output);
+        assert_eq!(
+            serde_json::to_value(&grouped).unwrap(),
+            serde_json::json!({
+                "happiness": [{"start": 10, "end": 20, "start_frame": 240, "end_frame": 480}],
+                "anger": [
+                    {"start": 0, "end": 30, "start_frame": 0, "end_frame": 720},
+                    {"start": 60, "end": 90, "start_frame": 1440, "end_frame": 2160},
+                ],
+            })
+        );
+        // The groups are in the order of `meme_type`, and the empty ones are omitted.
+        let json = serde_json::to_string(&grouped).unwrap();
+        assert!(json.starts_with(r#"{"happiness":"#), "{}", json);
+        assert!(!json.contains("love"));
+
+        assert_eq!(
+            serde_json::to_value(GroupedVideoAnalyzerOutput::from(
+                VideoAnalyzerOutput::from_iter([])
+            ))
+            .unwrap(),
+            serde_json::json!({})
+        );
+        assert_eq!(
+            serde_json::to_value(GroupedVideoAnalyzerOutput::from(
+                VideoAnalyzerOutput::default()
+            ))
+            .unwrap(),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn test_frame_at() {
+        assert_eq!(frame_at(0, 24.0), 0);
+        assert_eq!(frame_at(10, 24.0), 240);
+        assert_eq!(frame_at(10, 30.0), 300);
+        assert_eq!(frame_at(10, 30000.0 / 1001.0), 300);
+        assert_eq!(frame_at(60, 30000.0 / 1001.0), 1798);
+        assert_eq!(frame_at(3600, 30000.0 / 1001.0), 107892);
+    }
+
+    #[test]
+    fn test_set_frame_rate() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+        ]);
+        assert_eq!(
+            serde_json::to_value(&output).unwrap()[0],
+            serde_json::json!({"start": 0, "end": 30, "meme_type": 2, "meme_type_desc": "anger"})
+        );
+
+        output.set_frame_rate(24.0);
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!([
+                {"start": 0, "end": 30, "start_frame": 0, "end_frame": 720, "meme_type": 2, "meme_type_desc": "anger"},
+                {"start": 60, "end": 90, "start_frame": 1440, "end_frame": 2160, "meme_type": 1, "meme_type_desc": "love"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_dedup_output() {
+        let inference_output: InferenceOutput = serde_json::from_str(
+            r#"[
+                {"start": 0, "end": 30, "suggestion": "anger"},
+                {"start": 0, "end": 30, "suggestion": "anger"},
+                {"start": 0, "end": 30, "suggestion": "love"},
+                {"start": 1, "end": 29, "suggestion": "anger"},
+                {"start": 2, "end": 33, "suggestion": "anger"},
+                {"start": 60, "end": 90, "suggestion": "love"},
+                {"start": 60, "end": 90, "suggestion": "love"}
+            ]"#,
+        )
+        .unwrap();
+
+        // Only the exact duplicates are removed without tolerance.
+        let mut output = VideoAnalyzerOutput::from(inference_output);
+        assert_eq!(output.dedup(0), 2);
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Love),
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(1, 29, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(2, 33, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            ])
+        );
+
+        // The near duplicates of different types are kept.
+        assert_eq!(output.dedup(2), 1);
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Love),
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(2, 33, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            ])
+        );
+        assert_eq!(output.dedup(3), 1);
+        assert_eq!(output.0.as_ref().unwrap().len(), 3);
+
+        assert_eq!(VideoAnalyzerOutput::default().dedup(3), 0);
+    }
+
+    #[test]
+    fn test_flatten_output() {
+        let flattened = |suggestions: &[(u32, u32, MemeType)]| {
+            let mut output = VideoAnalyzerOutput::from_iter(suggestions.iter().map(
+                |&(start, end, meme_type)| VideoAnalyzerSuggestion::new(start, end, meme_type),
+            ));
+            output.flatten();
+            output
+                .suggestions()
+                .unwrap()
+                .iter()
+                .map(|s| (s.start, s.end, s.meme_type))
+                .collect::<Vec<_>>()
+        };
+
+        // Nested: the inner suggestion splits the outer one only if it wins.
+        assert_eq!(
+            flattened(&[(0, 30, MemeType::Sorrow), (10, 20, MemeType::Love)]),
+            [
+                (0, 10, MemeType::Sorrow),
+                (10, 20, MemeType::Love),
+                (20, 30, MemeType::Sorrow),
+            ]
+        );
+        assert_eq!(
+            flattened(&[(0, 30, MemeType::Love), (10, 20, MemeType::Sorrow)]),
+            [(0, 30, MemeType::Love)]
+        );
+        // Crossing.
+        assert_eq!(
+            flattened(&[
+                (0, 20, MemeType::Anger),
+                (10, 30, MemeType::Happiness),
+                (25, 40, MemeType::Surprise),
+            ]),
+            [
+                (0, 10, MemeType::Anger),
+                (10, 30, MemeType::Happiness),
+                (30, 40, MemeType::Surprise),
+            ]
+        );
+        // The overlapping and adjacent spans of the same type are merged, and a gap is kept.
+        assert_eq!(
+            flattened(&[
+                (0, 10, MemeType::Hate),
+                (5, 15, MemeType::Hate),
+                (15, 20, MemeType::Hate),
+                (30, 40, MemeType::Love),
+                (35, 35, MemeType::Happiness),
+            ]),
+            [(0, 20, MemeType::Hate), (30, 40, MemeType::Love)]
+        );
+        // A single suggestion is already flat, even if it covers no time.
+        assert_eq!(
+            flattened(&[(0, 0, MemeType::Love)]),
+            [(0, 0, MemeType::Love)]
+        );
+
+        let mut output = VideoAnalyzerOutput::default();
+        output.flatten();
+        assert!(output.is_failed());
+    }
+
+    #[test]
+    fn test_clamp_output_to_duration() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            VideoAnalyzerSuggestion::new(80, 120, MemeType::Hate),
+            VideoAnalyzerSuggestion::new(100, 130, MemeType::Sorrow),
+            VideoAnalyzerSuggestion::new(150, 180, MemeType::Surprise),
+        ]);
+        assert_eq!(output.clamp_to_duration(100), (1, 2));
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+                VideoAnalyzerSuggestion::new(80, 100, MemeType::Hate),
+            ])
+        );
+        assert_eq!(output.clamp_to_duration(100), (0, 0));
+
+        let mut output = VideoAnalyzerOutput::default();
+        assert_eq!(output.clamp_to_duration(100), (0, 0));
+        assert_eq!(output.0, None);
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            VideoAnalyzerSuggestion::new(80, 120, MemeType::Hate),
+        ]);
+        assert_eq!(output.truncate(3), 0);
+        assert_eq!(output.truncate(2), 1);
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+            ])
+        );
+
+        let mut output = VideoAnalyzerOutput::default();
+        assert_eq!(output.truncate(0), 0);
+        assert_eq!(output.0, None);
+    }
+
+    #[test]
+    fn test_collapse_output_to_frame() {
+        let mut output = VideoAnalyzerOutputBuilder::default()
+            .suggestion(0, 30, MemeType::Anger)
+            .suggestion(60, 90, MemeType::Love)
+            .build();
+        assert_eq!(output.collapse_to_frame(), 1);
+        assert_eq!(
+            output.0,
+            Some(vec![VideoAnalyzerSuggestion::new(0, 0, MemeType::Anger)])
+        );
+
+        let mut output = VideoAnalyzerOutput::default();
+        assert_eq!(output.collapse_to_frame(), 0);
+        assert_eq!(output.0, None);
+    }
+
+    #[test]
+    fn test_retain_meme_types() {
+        let mut output = VideoAnalyzerOutputBuilder::default()
+            .suggestion(0, 30, MemeType::Anger)
+            .suggestion(60, 90, MemeType::Hate)
+            .suggestion(80, 120, MemeType::Love)
+            .build();
+        assert_eq!(output.retain_meme_types(&MemeType::ALL), 0);
+        assert_eq!(
+            output.retain_meme_types(&[MemeType::Anger, MemeType::Love]),
+            1
+        );
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(80, 120, MemeType::Love),
+            ])
+        );
+
+        let mut raw_output = serde_json::json!([
+            {"start": 0, "end": 30, "suggestion": "anger"},
+            {"start": 60, "end": 90, "suggestion": "hate"},
+            {"start": 90, "end": 100, "suggestion": "boredom"},
+        ]);
+        retain_raw_meme_types(&mut raw_output, &[MemeType::Anger]);
+        assert_eq!(
+            raw_output,
+            serde_json::json!([
+                {"start": 0, "end": 30, "suggestion": "anger"},
+                {"start": 90, "end": 100, "suggestion": "boredom"},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_queue_len() {
+        let (analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        assert_eq!(analyzer_buf.queue_len(), 0);
+        let _handles: Vec<_> = (0..2)
+            .map(|_| {
+                Task::new("video.mp4", "video", VideoAnalyzerMode::Multi)
+                    .spawn(&analyzer_buf)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(analyzer_buf.queue_len(), 2);
+        assert_eq!(analyzer_buf.queue_depth(), 2);
+
+        let task = analyzer.next_task().unwrap();
+        assert_eq!(analyzer_buf.queue_len(), 1);
+        assert_eq!(analyzer_buf.queue_depth(), 2);
+        drop(task);
+    }
+
+    #[test]
+    fn test_run_stats() {
+        let (analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        let analyzer = analyzer.backend(
+            StubInference::new()
+                .output(VideoAnalyzerOutput::from_iter([
+                    VideoAnalyzerSuggestion::new(10, 20, MemeType::Love),
+                ]))
+                .output(VideoAnalyzerOutput::default()),
+        );
+        let _handles: Vec<_> = (0..2)
+            .map(|_| {
+                Task::new("video.mp4", "video", VideoAnalyzerMode::Multi)
+                    .spawn(&analyzer_buf)
+                    .unwrap()
+            })
+            .collect();
+        std::thread::spawn(move || analyzer.run());
+        for _ in 0..100 {
+            if analyzer_buf.stats().total == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let stats = analyzer_buf.stats();
+        assert_eq!(stats.total, 2);
+        // The second output is a failed one.
+        assert_eq!(stats.success_rate, Some(0.5));
+        assert_eq!(stats.queue_depth, 0);
+    }
+
+    #[test]
+    fn test_run_skips_cancelled_tasks() {
+        let (analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        let analyzer = analyzer.backend(StubInference::new());
+        let task = || Task::new("video.mp4", "video", VideoAnalyzerMode::Multi);
+        drop(task().spawn(&analyzer_buf).unwrap());
+        let handle = task().spawn(&analyzer_buf).unwrap();
+        std::thread::spawn(move || analyzer.run());
+        let report = actix_web::rt::System::new().block_on(handle.recv());
+        assert!(report.unwrap().is_ok());
+        // Only the task with a handle has been analyzed.
+        assert_eq!(analyzer_buf.stats().total, 1);
+        assert_eq!(analyzer_buf.queue_depth(), 0);
+    }
+
+    #[test]
+    fn test_task_state() {
+        let (analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        let handle = Task::new("video.mp4", "video", VideoAnalyzerMode::Multi)
+            .spawn(&analyzer_buf)
+            .unwrap();
+        assert_eq!(handle.state(), TaskState::Queued);
+
+        let task = analyzer.next_task().unwrap();
+        task.set_running();
+        assert_eq!(handle.state(), TaskState::Running);
+        task.send(Ok(VideoAnalyzerReport::default())).unwrap();
+        assert_eq!(handle.state(), TaskState::Done);
+    }
+
+    #[test]
+    fn test_meme_type_round_trip() {
+        for meme_type in MemeType::ALL {
+            assert_eq!(meme_type.to_string().parse(), Ok(meme_type));
+        }
+        assert_eq!(MemeType::Hate.to_string(), "hate");
+        assert!("Hate".parse::<MemeType>().is_err());
+    }
+
+    #[test]
+    fn test_offset_output() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(60, 90, MemeType::Love),
+        ]);
+        output.offset(120);
+
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(120, 150, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(180, 210, MemeType::Love),
+            ])
+        );
+
+        let mut output = VideoAnalyzerOutput::default();
+        output.offset(120);
+        assert_eq!(output.0, None);
+    }
+
+    #[test]
+    fn test_scale_output_times() {
+        let mut output = VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(0, 30, MemeType::Anger),
+            VideoAnalyzerSuggestion::new(60, u32::MAX / 10, MemeType::Love),
+        ]);
+        output.scale_times(1000);
+
+        assert_eq!(
+            output.0,
+            Some(vec![
+                VideoAnalyzerSuggestion::new(0, 30000, MemeType::Anger),
+                VideoAnalyzerSuggestion::new(60000, u32::MAX, MemeType::Love),
+            ])
+        );
     }
 }
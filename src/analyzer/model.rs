@@ -0,0 +1,79 @@
+use super::ReadinessProbe;
+use std::path::{Path, PathBuf};
+
+/// An inference procedure the analyzer can run: the inference script in a directory of the
+/// `streameme_inference` project, the Python interpreter running it, and the arguments given to it
+/// on top of the usual ones.
+///
+/// The analyzer runs the one given to [`VideoAnalyzer::new`](super::VideoAnalyzer::new) by default,
+/// and others can be registered under names with
+/// [`VideoAnalyzer::model`](super::VideoAnalyzer::model), such as an experimental model run
+/// alongside the production one.
+#[derive(Debug, Clone)]
+pub struct InferenceModel {
+    inference_dir: PathBuf,
+    interpreter_path: PathBuf,
+    inference_script_path: PathBuf,
+    args: Vec<String>,
+}
+
+impl InferenceModel {
+    /// Creates an [`InferenceModel`] running `inference.py` in `inference_dir` with
+    /// `.venv/bin/python` in the same directory.
+    #[inline]
+    pub fn new(inference_dir: impl Into<PathBuf>) -> Self {
+        let inference_dir = inference_dir.into();
+        Self {
+            interpreter_path: inference_dir.join(".venv/bin/python"),
+            inference_script_path: inference_dir.join("inference.py"),
+            inference_dir,
+            args: Vec::new(),
+        }
+    }
+
+    /// Sets the path of the Python interpreter running the inference script, such as the one of
+    /// another virtual environment. It defaults to `.venv/bin/python` in the inference directory.
+    #[inline]
+    pub fn interpreter(mut self, path: impl Into<PathBuf>) -> Self {
+        self.interpreter_path = path.into();
+        self
+    }
+
+    /// Adds an argument given to the inference script after the usual ones, such as the name of
+    /// the weights to load.
+    #[inline]
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    #[inline]
+    pub fn inference_dir(&self) -> &Path {
+        &self.inference_dir
+    }
+
+    #[inline]
+    pub fn interpreter_path(&self) -> &Path {
+        &self.interpreter_path
+    }
+
+    #[inline]
+    pub fn inference_script_path(&self) -> &Path {
+        &self.inference_script_path
+    }
+
+    #[inline]
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns a [`ReadinessProbe`] checking the prerequisites of this inference procedure.
+    #[inline]
+    pub(super) fn readiness_probe(&self) -> ReadinessProbe {
+        ReadinessProbe::new(
+            self.inference_dir.clone(),
+            self.interpreter_path.clone(),
+            self.inference_script_path.clone(),
+        )
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::str::FromStr;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// A part of a [`VideoNameTemplate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// `{name}`, the name of the video without its extension.
+    Name,
+    /// `{id}`, the ID of the task.
+    Id,
+    /// `{ts}`, when the analysis starts, such as `20250101T120000Z`.
+    Ts,
+}
+
+/// A template of the video name passed to the inference procedure, which names its output files
+/// after it. The placeholders `{name}`, `{id}` and `{ts}` are replaced with the name of the video
+/// without its extension, the ID of the task, and when the analysis starts in UTC, such as
+/// `20250101T120000Z`. `{{` and `}}` stand for literal braces.
+///
+/// The default template is `{name}`, which passes the name of the video as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoNameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl VideoNameTemplate {
+    /// Renders the video name for the task `id` analyzing the video `name` at `time`.
+    pub fn render(&self, name: &str, id: Uuid, time: OffsetDateTime) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Name => rendered.push_str(name),
+                Segment::Id => rendered.push_str(&id.to_string()),
+                Segment::Ts => {
+                    let time = time.to_offset(time::UtcOffset::UTC);
+                    rendered.push_str(&format!(
+                        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+                        time.year(),
+                        u8::from(time.month()),
+                        time.day(),
+                        time.hour(),
+                        time.minute(),
+                        time.second()
+                    ));
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl Default for VideoNameTemplate {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            template: String::from("{name}"),
+            segments: vec![Segment::Name],
+        }
+    }
+}
+
+/// Parses a template, rejecting unknown placeholders, unbalanced braces, and path separators, which
+/// would make the inference procedure write outside its output directory.
+impl FromStr for VideoNameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(String::from("the template must not be empty"));
+        }
+        if s.contains(['/', '\\', '\0']) {
+            return Err(String::from(
+                "the template must not contain a path separator or a NUL character",
+            ));
+        }
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let Some((placeholder, after)) = rest.split_once('}') else {
+                        return Err(String::from("the template has an unclosed `{`"));
+                    };
+                    let segment = match placeholder {
+                        "name" => Segment::Name,
+                        "id" => Segment::Id,
+                        "ts" => Segment::Ts,
+                        _ => {
+                            return Err(format!(
+                                "unknown placeholder {{{}}}, expected {{name}}, {{id}} or {{ts}}",
+                                placeholder
+                            ));
+                        }
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(segment);
+                    chars = after.chars();
+                }
+                '}' => return Err(String::from("the template has an unmatched `}`")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self {
+            template: String::from(s),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for VideoNameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_render_video_name_template() {
+        let time = datetime!(2025-01-02 03:04:05.678 +09:00);
+        let render = |template: &str| {
+            template
+                .parse::<VideoNameTemplate>()
+                .unwrap()
+                .render("video", Uuid::nil(), time)
+        };
+
+        assert_eq!(render("{name}"), "video");
+        assert_eq!(
+            render("{ts}_{name}-{id}"),
+            "20250101T180405Z_video-00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(render("{{name}}"), "{name}");
+        assert_eq!(render("clip"), "clip");
+        assert_eq!(
+            VideoNameTemplate::default().render("video", Uuid::nil(), time),
+            "video"
+        );
+    }
+
+    #[test]
+    fn test_parse_video_name_template() {
+        for template in [
+            "",
+            "{nam}",
+            "{name",
+            "name}",
+            "{}",
+            "../{name}",
+            "a\\{name}",
+        ] {
+            assert!(
+                template.parse::<VideoNameTemplate>().is_err(),
+                "{:?}",
+                template
+            );
+        }
+        assert_eq!(
+            "{name}_{id}"
+                .parse::<VideoNameTemplate>()
+                .unwrap()
+                .to_string(),
+            "{name}_{id}"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// A failed readiness check, naming the check and telling why it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadinessFailure {
+    pub check: &'static str,
+    /// What failed without the details of the host, such as paths, which can be told to clients.
+    pub summary: &'static str,
+    /// What failed in detail, which should only be logged.
+    pub message: String,
+}
+
+impl ReadinessFailure {
+    fn new(check: &'static str, summary: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            summary,
+            message: message.into(),
+        }
+    }
+}
+
+/// Checks whether the prerequisites of the inference procedure are in place, so that an analysis
+/// could plausibly succeed. Use [`crate::analyzer::VideoAnalyzer::readiness_probe`] to create one.
+#[derive(Debug, Clone)]
+pub struct ReadinessProbe {
+    inference_dir: PathBuf,
+    interpreter_path: PathBuf,
+    inference_script_path: PathBuf,
+    help_timeout: Option<Duration>,
+}
+
+impl ReadinessProbe {
+    #[inline]
+    pub(super) fn new(
+        inference_dir: PathBuf,
+        interpreter_path: PathBuf,
+        inference_script_path: PathBuf,
+    ) -> Self {
+        Self {
+            inference_dir,
+            interpreter_path,
+            inference_script_path,
+            help_timeout: None,
+        }
+    }
+
+    /// Makes the probe also run `python inference.py --help`, which must exit successfully within
+    /// `timeout`. This catches a broken Python environment, such as missing packages, at the cost
+    /// of spawning the interpreter on every check. It is disabled by default.
+    #[inline]
+    pub fn help_timeout(mut self, timeout: Duration) -> Self {
+        self.help_timeout = Some(timeout);
+        self
+    }
+
+    /// Runs the checks in order, and returns the first failure if any.
+    ///
+    /// The checks are `inference_dir` (the inference directory exists), `interpreter` (the Python
+    /// interpreter is an executable file), `inference_script` (the inference script exists), and
+    /// `inference_help` (the inference script runs, if enabled by [`Self::help_timeout`]).
+    pub async fn check(&self) -> Result<(), ReadinessFailure> {
+        self.check_files()?;
+        if let Some(timeout) = self.help_timeout {
+            self.check_help(timeout).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the checks on the files only, which are all but `inference_help`, and returns the
+    /// first failure if any. Unlike [`Self::check`], this never spawns a process, so it suits a
+    /// check at startup.
+    pub fn check_files(&self) -> Result<(), ReadinessFailure> {
+        if !self.inference_dir.is_dir() {
+            return Err(ReadinessFailure::new(
+                "inference_dir",
+                "the inference directory is not a directory",
+                format!("{} is not a directory", self.inference_dir.display()),
+            ));
+        }
+        if !is_executable(&self.interpreter_path) {
+            return Err(ReadinessFailure::new(
+                "interpreter",
+                "the Python interpreter is not executable",
+                format!("{} is not executable", self.interpreter_path.display()),
+            ));
+        }
+        if !self.inference_script_path.is_file() {
+            return Err(ReadinessFailure::new(
+                "inference_script",
+                "the inference script is not a file",
+                format!("{} is not a file", self.inference_script_path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn check_help(&self, timeout: Duration) -> Result<(), ReadinessFailure> {
+        let failure = |message: String| {
+            ReadinessFailure::new(
+                "inference_help",
+                "the inference script failed to run",
+                message,
+            )
+        };
+        let output = Command::new(&self.interpreter_path)
+            .current_dir(&self.inference_dir)
+            .arg(&self.inference_script_path)
+            .arg("--help")
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        match tokio::time::timeout(timeout, output).await {
+            Err(_) => Err(failure(format!(
+                "the inference script didn't exit within {:?}",
+                timeout
+            ))),
+            Ok(Err(e)) => Err(failure(format!(
+                "failed to run the inference script: {}",
+                e
+            ))),
+            Ok(Ok(output)) if !output.status.success() => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(failure(format!(
+                    "the inference script exited with {}: {}",
+                    output.status,
+                    stderr.lines().last().unwrap_or_default()
+                )))
+            }
+            Ok(Ok(_)) => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn write_script(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn probe(dir: &Path) -> ReadinessProbe {
+        ReadinessProbe::new(
+            dir.to_path_buf(),
+            dir.join(".venv/bin/python"),
+            dir.join("inference.py"),
+        )
+    }
+
+    #[actix_web::test]
+    async fn test_readiness_probe() {
+        let dir = TempDir::new().unwrap();
+        let check = |probe: ReadinessProbe| async move { probe.check().await.map_err(|e| e.check) };
+
+        assert_eq!(
+            check(probe(&dir.path().join("missing"))).await,
+            Err("inference_dir")
+        );
+        assert_eq!(check(probe(dir.path())).await, Err("interpreter"));
+        assert_eq!(
+            probe(dir.path()).check_files().map_err(|e| e.check),
+            Err("interpreter")
+        );
+
+        write_script(&dir.path().join(".venv/bin/python"), "#!/bin/sh\nexit 1\n");
+        assert_eq!(check(probe(dir.path())).await, Err("inference_script"));
+
+        fs::write(dir.path().join("inference.py"), "").unwrap();
+        assert_eq!(check(probe(dir.path())).await, Ok(()));
+        assert_eq!(probe(dir.path()).check_files(), Ok(()));
+
+        // The fake interpreter fails to run the script.
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            check(probe(dir.path()).help_timeout(timeout)).await,
+            Err("inference_help")
+        );
+        write_script(&dir.path().join(".venv/bin/python"), "#!/bin/sh\nexit 0\n");
+        assert_eq!(check(probe(dir.path()).help_timeout(timeout)).await, Ok(()));
+    }
+}
@@ -0,0 +1,118 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The number of the latest analyses the rates and the durations are computed from.
+const WINDOW: usize = 100;
+
+/// The outcomes of the analyses, shared between the analyzer and its buffer.
+#[derive(Debug, Default)]
+pub(crate) struct AnalyzerStats {
+    inner: Mutex<RollingStats>,
+}
+
+#[derive(Debug, Default)]
+struct RollingStats {
+    /// The number of the analyses since the analyzer started.
+    total: u64,
+    /// The durations of the latest analyses, and whether they succeeded, oldest first.
+    recent: VecDeque<(Duration, bool)>,
+}
+
+impl AnalyzerStats {
+    /// Counts an analysis that took `duration`, which `succeeded` unless it failed or only had
+    /// partial results.
+    pub fn record(&self, duration: Duration, succeeded: bool) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.total += 1;
+        if inner.recent.len() == WINDOW {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back((duration, succeeded));
+    }
+
+    /// Returns a snapshot of the statistics, where `queue_depth` is the number of the tasks queued
+    /// or being analyzed.
+    pub fn snapshot(&self, queue_depth: usize) -> VideoAnalyzerStats {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let window = inner.recent.len();
+        let mut durations: Vec<Duration> = inner.recent.iter().map(|(d, _)| *d).collect();
+        durations.sort_unstable();
+        let succeeded = inner.recent.iter().filter(|(_, ok)| *ok).count();
+        VideoAnalyzerStats {
+            total: inner.total,
+            window,
+            success_rate: (window > 0).then(|| succeeded as f64 / window as f64),
+            average_duration_ms: (window > 0)
+                .then(|| (durations.iter().sum::<Duration>() / window as u32).as_millis() as u64),
+            // The nearest rank, which is always one of the durations.
+            p95_duration_ms: (window > 0).then(|| {
+                let rank = (window * 95).div_ceil(100);
+                durations[rank - 1].as_millis() as u64
+            }),
+            queue_depth,
+        }
+    }
+}
+
+/// A snapshot of the recent analyses of an analyzer, as returned by
+/// [`VideoAnalyzerBuffer::stats`](super::VideoAnalyzerBuffer::stats).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VideoAnalyzerStats {
+    /// The number of the analyses since the analyzer started.
+    pub total: u64,
+    /// The number of the latest analyses the rates and the durations are computed from, which is
+    /// at most 100.
+    pub window: usize,
+    /// The fraction of the analyses in the window that succeeded, or [`None`] if there is none.
+    pub success_rate: Option<f64>,
+    /// The average duration of the analyses in the window, in milliseconds.
+    pub average_duration_ms: Option<u64>,
+    /// The 95th percentile of the durations of the analyses in the window, in milliseconds.
+    pub p95_duration_ms: Option<u64>,
+    /// The number of the tasks queued or being analyzed.
+    pub queue_depth: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyzer_stats() {
+        let stats = AnalyzerStats::default();
+        assert_eq!(
+            stats.snapshot(0),
+            VideoAnalyzerStats {
+                total: 0,
+                window: 0,
+                success_rate: None,
+                average_duration_ms: None,
+                p95_duration_ms: None,
+                queue_depth: 0,
+            }
+        );
+
+        for secs in 1..=4 {
+            stats.record(Duration::from_secs(secs), secs != 4);
+        }
+        let snapshot = stats.snapshot(2);
+        assert_eq!(snapshot.total, 4);
+        assert_eq!(snapshot.window, 4);
+        assert_eq!(snapshot.success_rate, Some(0.75));
+        assert_eq!(snapshot.average_duration_ms, Some(2500));
+        assert_eq!(snapshot.p95_duration_ms, Some(4000));
+        assert_eq!(snapshot.queue_depth, 2);
+
+        // Only the latest analyses are in the window.
+        for _ in 0..WINDOW {
+            stats.record(Duration::from_millis(10), true);
+        }
+        let snapshot = stats.snapshot(0);
+        assert_eq!(snapshot.total, 104);
+        assert_eq!(snapshot.window, WINDOW);
+        assert_eq!(snapshot.success_rate, Some(1.0));
+        assert_eq!(snapshot.p95_duration_ms, Some(10));
+    }
+}
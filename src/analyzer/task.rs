@@ -1,62 +1,174 @@
-use super::{VideoAnalyzerBuffer, VideoAnalyzerMode, VideoAnalyzerResult};
+use super::{AnalysisQuality, VideoAnalyzerBuffer, VideoAnalyzerMode, VideoAnalyzerResult};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::Instant;
 use tokio::sync::oneshot;
+use uuid::Uuid;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct TaskConfig {
+    id: Option<Uuid>,
     video_path: PathBuf,
     video_name: Option<String>,
+    file_name: Option<String>,
     analyze_mode: Option<VideoAnalyzerMode>,
+    start_sec: Option<u32>,
+    end_sec: Option<u32>,
+    fps_sample: Option<f64>,
+    model: Option<String>,
+    quality: AnalysisQuality,
+    raw_output: bool,
+    image: bool,
 }
 
 impl TaskConfig {
     #[inline]
     pub fn new<P: AsRef<Path> + ?Sized>(path: &P) -> Self {
         Self {
+            id: None,
             video_path: PathBuf::from(path.as_ref()),
             video_name: None,
+            file_name: None,
             analyze_mode: None,
+            start_sec: None,
+            end_sec: None,
+            fps_sample: None,
+            model: None,
+            quality: AnalysisQuality::default(),
+            raw_output: false,
+            image: false,
         }
     }
 
+    /// Sets the ID of the task. The ID is attached to the log records concerning the task, so it
+    /// is typically the correlation ID of the request that creates the task. A random ID is
+    /// generated if this is not set.
+    #[inline]
+    pub fn id(&mut self, id: Uuid) -> &mut Self {
+        self.id = Some(id);
+        self
+    }
+
     #[inline]
     pub fn video_name(&mut self, video_name: &str) -> &mut Self {
         self.video_name = Some(String::from(video_name));
         self
     }
 
+    /// Sets the file name the video was uploaded with, which is recorded along with the kept
+    /// output of the inference procedure.
+    #[inline]
+    pub fn file_name(&mut self, file_name: &str) -> &mut Self {
+        self.file_name = Some(String::from(file_name));
+        self
+    }
+
     #[inline]
     pub fn analyze_mode(&mut self, analyze_mode: VideoAnalyzerMode) -> &mut Self {
         self.analyze_mode = Some(analyze_mode);
         self
     }
 
+    /// Restricts the analysis to the part of the video after `start_sec` seconds. The analysis
+    /// starts from the beginning of the video if this is not set.
+    #[inline]
+    pub fn start_sec(&mut self, start_sec: u32) -> &mut Self {
+        self.start_sec = Some(start_sec);
+        self
+    }
+
+    /// Restricts the analysis to the part of the video before `end_sec` seconds. The analysis
+    /// runs until the end of the video if this is not set.
+    #[inline]
+    pub fn end_sec(&mut self, end_sec: u32) -> &mut Self {
+        self.end_sec = Some(end_sec);
+        self
+    }
+
+    /// Makes the inference procedure sample `fps_sample` frames per second of the video, trading
+    /// accuracy for speed. The inference procedure chooses the rate if this is not set.
+    #[inline]
+    pub fn fps_sample(&mut self, fps_sample: f64) -> &mut Self {
+        self.fps_sample = Some(fps_sample);
+        self
+    }
+
+    /// Makes the task run the inference model registered under `model` with
+    /// [`VideoAnalyzer::model`](super::VideoAnalyzer::model) instead of the default one. The
+    /// analysis fails if no model is registered under the name.
+    #[inline]
+    pub fn model(&mut self, model: &str) -> &mut Self {
+        self.model = Some(String::from(model));
+        self
+    }
+
+    /// Sets how thoroughly the video is analyzed. It defaults to [`AnalysisQuality::Full`].
+    #[inline]
+    pub fn quality(&mut self, quality: AnalysisQuality) -> &mut Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Makes the analyzer keep the output of the inference procedure as it was written, before
+    /// the suggestions are converted and filtered, or the tail of its stderr if it fails. This is
+    /// meant for debugging.
+    #[inline]
+    pub fn raw_output(&mut self) -> &mut Self {
+        self.raw_output = true;
+        self
+    }
+
+    /// Marks the file as a still image rather than a video. The inference procedure is then given
+    /// `--image`, and the analysis results in a single suggestion spanning the whole frame.
+    #[inline]
+    pub fn image(&mut self) -> &mut Self {
+        self.image = true;
+        self
+    }
+
     #[inline]
     pub fn build(&self) -> Task {
         Task {
+            id: self.id.unwrap_or_else(Uuid::new_v4),
             video_path: self.video_path.clone(),
             video_name: self
                 .video_name
                 .as_ref()
                 .map_or(String::from("_anonymous"), |s| s.clone()),
+            file_name: self.file_name.clone(),
             analyze_mode: self.analyze_mode.unwrap_or_default(),
+            start_sec: self.start_sec,
+            end_sec: self.end_sec,
+            fps_sample: self.fps_sample,
+            model: self.model.clone(),
+            quality: self.quality,
+            raw_output: self.raw_output,
+            image: self.image,
         }
     }
 }
 
 /// An analysis task.
 pub struct Task {
+    id: Uuid,
     video_path: PathBuf,
     video_name: String,
+    file_name: Option<String>,
     analyze_mode: VideoAnalyzerMode,
+    start_sec: Option<u32>,
+    end_sec: Option<u32>,
+    fps_sample: Option<f64>,
+    model: Option<String>,
+    quality: AnalysisQuality,
+    raw_output: bool,
+    image: bool,
 }
 
 impl Task {
-    /// Creates a new [`Task`].
-    #[allow(dead_code)]
+    /// Creates a new [`Task`] with a random ID.
     #[inline]
     pub fn new<P: AsRef<Path> + ?Sized>(
         video_path: &P,
@@ -64,9 +176,18 @@ impl Task {
         analyze_mode: VideoAnalyzerMode,
     ) -> Self {
         Self {
+            id: Uuid::new_v4(),
             video_path: PathBuf::from(video_path.as_ref()),
             video_name: String::from(video_name),
+            file_name: None,
             analyze_mode,
+            start_sec: None,
+            end_sec: None,
+            fps_sample: None,
+            model: None,
+            quality: AnalysisQuality::default(),
+            raw_output: false,
+            image: false,
         }
     }
 
@@ -76,43 +197,100 @@ impl Task {
     /// An error is returned when failed to send the task to the analyzer. This can occur if the
     /// analyzer has been deallocated already, implying that the wrapped receiver has also been
     /// deallocated.
+    // The task is handed back in the error so that it isn't lost, and this only happens once the
+    // analyzer is gone, so its size doesn't matter.
+    #[allow(clippy::result_large_err)]
     #[inline]
     pub fn spawn(
         self,
         analyzer: &VideoAnalyzerBuffer,
     ) -> Result<SpawnedTaskHandle, mpsc::SendError<Self>> {
         let (tx, rx) = oneshot::channel();
+        let state = Arc::new(AtomicU8::new(TaskState::Queued as u8));
         let spawned = SpawnedTask {
-            task: self,
+            task: Box::new(self),
             sender: tx,
+            queued_at: Instant::now(),
+            state: Arc::clone(&state),
         };
         spawned
             .spawn(analyzer)
-            .map_err(|e| mpsc::SendError(e.0.task))?;
-        Ok(SpawnedTaskHandle { receiver: rx })
+            .map_err(|e| mpsc::SendError(*e.0.task))?;
+        Ok(SpawnedTaskHandle {
+            receiver: rx,
+            state,
+        })
     }
 
     #[inline]
-    pub(super) fn video_path(&self) -> &Path {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    #[inline]
+    pub fn video_path(&self) -> &Path {
         &self.video_path
     }
 
     #[inline]
-    pub(super) fn video_name(&self) -> &str {
+    pub fn video_name(&self) -> &str {
         &self.video_name
     }
 
     #[inline]
-    pub(super) fn analyze_mode(&self) -> VideoAnalyzerMode {
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    #[inline]
+    pub fn analyze_mode(&self) -> VideoAnalyzerMode {
         self.analyze_mode
     }
+
+    #[inline]
+    pub fn start_sec(&self) -> Option<u32> {
+        self.start_sec
+    }
+
+    #[inline]
+    pub fn end_sec(&self) -> Option<u32> {
+        self.end_sec
+    }
+
+    #[inline]
+    pub fn fps_sample(&self) -> Option<f64> {
+        self.fps_sample
+    }
+
+    #[inline]
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    #[inline]
+    pub fn quality(&self) -> AnalysisQuality {
+        self.quality
+    }
+
+    #[inline]
+    pub fn raw_output(&self) -> bool {
+        self.raw_output
+    }
+
+    #[inline]
+    pub fn image(&self) -> bool {
+        self.image
+    }
 }
 
 /// An analysis task to be sent to the analyzer. It wraps a [`Task`] inside and uses message
 /// passing internally.
 pub(super) struct SpawnedTask {
-    task: Task,
+    /// The task is boxed to keep it small while it's passed to the analyzer.
+    task: Box<Task>,
     sender: oneshot::Sender<VideoAnalyzerResult>,
+    queued_at: Instant,
+    state: Arc<AtomicU8>,
 }
 
 impl SpawnedTask {
@@ -126,18 +304,66 @@ impl SpawnedTask {
         &self.task
     }
 
+    /// Returns when the task was sent to the analyzer.
+    #[inline]
+    pub fn queued_at(&self) -> Instant {
+        self.queued_at
+    }
+
+    /// Returns whether the handle of the task has been dropped, such as when the request waiting
+    /// for it timed out or its client disconnected, so that nobody would receive the results.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Tells the handle of the task that the analyzer has started analyzing it.
+    #[inline]
+    pub fn set_running(&self) {
+        self.state
+            .store(TaskState::Running as u8, Ordering::Relaxed);
+    }
+
+    /// Sends the analysis results to the handle of the task, marking the task done.
     #[inline]
     pub fn send(self, output: VideoAnalyzerResult) -> Result<(), VideoAnalyzerResult> {
+        self.state.store(TaskState::Done as u8, Ordering::Relaxed);
         self.sender.send(output)
     }
 }
 
+/// The state of a spawned task.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TaskState {
+    /// The task is waiting in the queue of the analyzer.
+    Queued = 0,
+    /// The analyzer is analyzing the video.
+    Running = 1,
+    /// The analysis is done, and its results are ready to be received.
+    Done = 2,
+}
+
 /// A handle to the spawned task. This can be used to receive the analysis results.
 pub struct SpawnedTaskHandle {
     receiver: oneshot::Receiver<VideoAnalyzerResult>,
+    state: Arc<AtomicU8>,
 }
 
 impl SpawnedTaskHandle {
+    /// Returns the current state of the task, without waiting for the analysis results.
+    ///
+    /// A task the analyzer dropped without analyzing it, such as when the analyzer stopped, stays
+    /// [`TaskState::Queued`]. [`Self::recv`] fails for such a task.
+    #[inline]
+    pub fn state(&self) -> TaskState {
+        match self.state.load(Ordering::Relaxed) {
+            0 => TaskState::Queued,
+            1 => TaskState::Running,
+            _ => TaskState::Done,
+        }
+    }
+
     /// Receive analysis results from the analyzer.
     ///
     /// # Errors
@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// How often the memory of the inference procedure is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The error of an analysis whose inference procedure was killed for exceeding the memory limit
+/// set by [`VideoAnalyzer::memory_limit`](super::VideoAnalyzer::memory_limit). It is wrapped in
+/// the [`io::Error`] of the analysis, and can be found with [`ResourceExceeded::find`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceExceeded {
+    limit: u64,
+    peak: u64,
+}
+
+impl ResourceExceeded {
+    /// Returns the [`ResourceExceeded`] wrapped in `e`, if `e` is one.
+    pub fn find(e: &io::Error) -> Option<&Self> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    /// Returns the memory limit, in bytes.
+    #[inline]
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Returns the peak resident memory of the inference procedure seen before it was killed, in
+    /// bytes.
+    #[inline]
+    pub fn peak(&self) -> u64 {
+        self.peak
+    }
+}
+
+impl fmt::Display for ResourceExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the inference procedure used {} bytes of memory, exceeding the limit of {} bytes",
+            self.peak, self.limit
+        )
+    }
+}
+
+impl Error for ResourceExceeded {}
+
+/// The output of a process run by [`output`].
+pub(super) struct WatchedOutput {
+    pub(super) status: ExitStatus,
+    pub(super) stderr: Vec<u8>,
+    /// The peak resident memory seen in bytes, or [`None`] if it couldn't be read at all, such as
+    /// on a platform without `/proc`.
+    pub(super) peak_memory: Option<u64>,
+}
+
+/// Runs `command` to completion like [`Command::output`], sampling the resident memory of the
+/// process from `/proc/<pid>/status`. The process is killed as soon as a sample exceeds `limit`
+/// bytes, and a [`ResourceExceeded`] is returned then. Only the memory of the process itself is
+/// counted, not the one of its children.
+pub(super) fn output(command: &mut Command, limit: u64) -> io::Result<WatchedOutput> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // The pipes are drained concurrently, or the process would block on a full pipe.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let mut peak_memory = None;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(memory) = resident_memory(&child) {
+            let peak = peak_memory.map_or(memory, |peak: u64| peak.max(memory));
+            peak_memory = Some(peak);
+            if memory > limit {
+                // The process may have exited right after the sample, which is fine to ignore.
+                let _ = child.kill();
+                child.wait()?;
+                // The readers are left behind, since a child of the process may keep the pipes
+                // open after it's killed.
+                return Err(io::Error::other(ResourceExceeded { limit, peak }));
+            }
+        }
+        thread::sleep(SAMPLE_INTERVAL);
+    };
+    let _ = stdout.join();
+    let stderr = stderr.join().unwrap_or_default();
+    Ok(WatchedOutput {
+        status,
+        stderr,
+        peak_memory,
+    })
+}
+
+/// Reads `pipe` to the end on another thread.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Returns the resident memory of `child` in bytes, or [`None`] if it can't be read.
+fn resident_memory(child: &Child) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{}/status", child.id())).ok()?;
+    parse_vm_rss(&status)
+}
+
+/// Parses the `VmRSS` line of `/proc/<pid>/status`, such as `VmRSS:    1234 kB`, into bytes.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kib = line.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tpython\nVmPeak:\t  20480 kB\nVmRSS:\t   1234 kB\nThreads:\t4\n";
+        assert_eq!(parse_vm_rss(status), Some(1234 * 1024));
+        // A zombie has no memory lines.
+        assert_eq!(parse_vm_rss("Name:\tpython\nState:\tZ (zombie)\n"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_watched_output() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo 'WARN low light' >&2"]);
+        let watched = output(&mut command, u64::MAX).unwrap();
+        assert!(watched.status.success());
+        assert_eq!(watched.stderr, b"WARN low light\n");
+
+        // Any process uses more than a byte.
+        let mut command = Command::new("sleep");
+        command.arg("5");
+        let e = output(&mut command, 1).err().unwrap();
+        let exceeded = ResourceExceeded::find(&e).unwrap();
+        assert_eq!(exceeded.limit(), 1);
+        assert!(exceeded.peak() > 1);
+    }
+}
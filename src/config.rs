@@ -0,0 +1,948 @@
+use actix_web::http;
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use std::fmt;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::thread;
+use streameme_backend::analyzer::{MemeType, VideoNameTemplate};
+use streameme_backend::handlers::{TimestampFormat, TimestampPrecision};
+
+/// The prefix of the environment variables setting the options, such as `STREAMEME_PORT` for
+/// `--port`.
+const ENV_PREFIX: &str = "STREAMEME_";
+
+/// A configuration value kept out of the logs, such as an API key passed to the inference script.
+#[derive(Clone)]
+pub struct Redacted<T>(T);
+
+impl<T> Redacted<T> {
+    #[inline]
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Which cross-origin requests the backend allows.
+#[derive(Debug, Clone)]
+pub enum CorsMode {
+    /// No CORS headers are added, e.g. when a gateway handles CORS.
+    Disabled,
+    AnyOrigin,
+    Origins(Vec<String>),
+}
+
+impl CorsMode {
+    /// Returns the allowed origins, which are empty unless the origins are restricted.
+    pub fn origins(&self) -> &[String] {
+        match self {
+            Self::Origins(origins) => origins,
+            Self::Disabled | Self::AnyOrigin => &[],
+        }
+    }
+}
+
+impl fmt::Display for CorsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => f.write_str("disabled"),
+            Self::AnyOrigin => f.write_str("any_origin"),
+            Self::Origins(origins) => write!(f, "{:?}", origins),
+        }
+    }
+}
+
+/// The configuration of the process, built from the command line and the environment variables.
+/// Every option can be given by an environment variable named after it with [`ENV_PREFIX`], such
+/// as `STREAMEME_PORT` for `--port`, and the command line takes precedence over the environment.
+///
+/// Its [`Display`](fmt::Display) is a summary logged at startup as a single line of `key=value`
+/// pairs, so that a misconfiguration is obvious in the logs. The values of the environment
+/// variables for the inference script are redacted in both the summary and the [`Debug`] output,
+/// since they may hold secrets.
+#[derive(Debug)]
+pub struct Config {
+    pub host: IpAddr,
+    pub port: u16,
+    pub workers: usize,
+    /// The certificate chain and the private key, if TLS is terminated in-process.
+    pub tls: Option<(PathBuf, PathBuf)>,
+    /// Empty if the endpoints are served at the root.
+    pub path_prefix: String,
+    pub ready_at_root: bool,
+    /// 0 disables the timeout.
+    pub client_request_timeout_sec: u64,
+    /// 0 disables keep-alive.
+    pub keep_alive_sec: u64,
+    pub shutdown_timeout_sec: u64,
+    pub upload_size_limit: usize,
+    pub metadata_limit: usize,
+    pub max_concurrent_uploads: Option<usize>,
+    pub max_resumable_uploads: usize,
+    pub max_request_duration_sec: Option<u64>,
+    pub min_free_space_mib: Option<u64>,
+    pub min_duration_sec: Option<f64>,
+    pub max_duration_sec: Option<f64>,
+    pub inference_dir: PathBuf,
+    /// Relative to `inference_dir` until it's resolved at startup.
+    pub python_bin: PathBuf,
+    pub ffprobe_bin: PathBuf,
+    pub output_file_name: String,
+    pub video_name_template: VideoNameTemplate,
+    pub inference_env: Vec<(String, Redacted<String>)>,
+    /// The names and the directories of the other inference models, the directories being
+    /// relative until they're resolved at startup.
+    pub inference_models: Vec<(String, PathBuf)>,
+    /// The extra arguments of the inference models, by their names.
+    pub inference_model_args: Vec<(String, String)>,
+    pub memory_limit_mib: Option<u64>,
+    pub keep_inference_output: Option<PathBuf>,
+    pub dedup_tolerance_sec: u32,
+    pub meme_types: Vec<MemeType>,
+    /// 0 means no limit.
+    pub max_suggestions: usize,
+    pub raw_output_token: Option<Redacted<String>>,
+    pub partial_results: bool,
+    pub stderr_tail_lines: usize,
+    pub timestamp_precision: TimestampPrecision,
+    pub timestamp_format: TimestampFormat,
+    pub remote_url_hosts: Vec<String>,
+    pub remote_url_schemes: Vec<String>,
+    /// The root of the files `/analyze-local` may analyze, and the bearer token it requires.
+    pub analyze_local: Option<(PathBuf, Redacted<String>)>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_token: Option<Redacted<String>>,
+    pub stats_token: Option<Redacted<String>>,
+    pub job_logs_token: Option<Redacted<String>>,
+    /// 0 disables `Idempotency-Key`.
+    pub idempotency_ttl_sec: u64,
+    pub json_log: bool,
+    pub response_compression: bool,
+    pub ready_help_timeout_sec: Option<u64>,
+    pub idle_timeout_sec: Option<u64>,
+    pub cors: CorsMode,
+    pub cors_methods: Vec<http::Method>,
+    pub cors_headers: Vec<String>,
+}
+
+impl Config {
+    /// Builds the configuration from the command line and the environment variables of the
+    /// process, exiting with a usage message if they are invalid.
+    pub fn parse() -> Self {
+        Self::from_matches(&command().get_matches())
+    }
+
+    /// Builds the configuration from the command line `args`, whose first item is the name of the
+    /// program, and the environment variables of the process. This waits for a test changing the
+    /// variables with [`tests::EnvVars`] to finish.
+    #[cfg(test)]
+    fn try_parse_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let _env = tests::ENV_LOCK
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Self::try_parse_from_current_env(args)
+    }
+
+    /// Does the actual work of [`Self::try_parse_from`], for the tests already holding
+    /// [`tests::ENV_LOCK`].
+    #[cfg(test)]
+    fn try_parse_from_current_env<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Ok(Self::from_matches(&command().try_get_matches_from(args)?))
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let cors_origins: Vec<String> = strings(matches, "cors_origin");
+        Self {
+            host: *matches.get_one::<IpAddr>("host").unwrap(),
+            port: *matches.get_one::<u16>("port").unwrap(),
+            // The same as the default of `HttpServer`, which is made explicit to be logged.
+            workers: matches
+                .get_one::<NonZeroUsize>("workers")
+                .copied()
+                .or_else(|| thread::available_parallelism().ok())
+                .map_or(2, NonZeroUsize::get),
+            tls: matches
+                .get_one::<PathBuf>("tls_cert")
+                .zip(matches.get_one::<PathBuf>("tls_key"))
+                .map(|(cert, key)| (cert.clone(), key.clone())),
+            path_prefix: matches
+                .get_one::<String>("path_prefix")
+                .cloned()
+                .unwrap_or_default(),
+            ready_at_root: matches.get_flag("ready_at_root"),
+            client_request_timeout_sec: *matches
+                .get_one::<u64>("client_request_timeout_sec")
+                .unwrap(),
+            keep_alive_sec: *matches.get_one::<u64>("keep_alive_sec").unwrap(),
+            shutdown_timeout_sec: *matches.get_one::<u64>("shutdown_timeout_sec").unwrap(),
+            upload_size_limit: *matches.get_one::<usize>("upload_size_limit").unwrap(),
+            metadata_limit: *matches.get_one::<usize>("metadata_limit").unwrap(),
+            max_concurrent_uploads: matches.get_one::<usize>("max_concurrent_uploads").copied(),
+            max_resumable_uploads: *matches.get_one::<usize>("max_resumable_uploads").unwrap(),
+            max_request_duration_sec: matches.get_one::<u64>("max_request_duration_sec").copied(),
+            min_free_space_mib: matches.get_one::<u64>("min_free_space_mib").copied(),
+            min_duration_sec: matches.get_one::<f64>("min_duration_sec").copied(),
+            max_duration_sec: matches.get_one::<f64>("max_duration_sec").copied(),
+            inference_dir: matches.get_one::<PathBuf>("inference_dir").unwrap().clone(),
+            python_bin: matches.get_one::<PathBuf>("python_bin").unwrap().clone(),
+            ffprobe_bin: matches.get_one::<PathBuf>("ffprobe_bin").unwrap().clone(),
+            output_file_name: matches
+                .get_one::<String>("output_file_name")
+                .unwrap()
+                .clone(),
+            video_name_template: matches
+                .get_one::<VideoNameTemplate>("video_name_template")
+                .unwrap()
+                .clone(),
+            inference_env: matches
+                .get_many::<(String, String)>("inference_env")
+                .into_iter()
+                .flatten()
+                .map(|(key, value)| (key.clone(), Redacted(value.clone())))
+                .collect(),
+            inference_models: matches
+                .get_many::<(String, PathBuf)>("inference_model")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            inference_model_args: matches
+                .get_many::<(String, String)>("inference_model_arg")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            memory_limit_mib: matches.get_one::<u64>("memory_limit_mib").copied(),
+            keep_inference_output: matches.get_one::<PathBuf>("keep_inference_output").cloned(),
+            dedup_tolerance_sec: *matches.get_one::<u32>("dedup_tolerance_sec").unwrap(),
+            meme_types: matches
+                .get_many::<MemeType>("meme_types")
+                .unwrap()
+                .copied()
+                .collect(),
+            max_suggestions: *matches.get_one::<usize>("max_suggestions").unwrap(),
+            raw_output_token: matches
+                .get_one::<String>("raw_output_token")
+                .map(|token| Redacted(token.clone())),
+            partial_results: matches.get_flag("partial_results"),
+            stderr_tail_lines: *matches.get_one::<usize>("stderr_tail_lines").unwrap(),
+            timestamp_precision: matches
+                .get_one::<String>("timestamp_precision")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            timestamp_format: matches
+                .get_one::<String>("timestamp_format")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            remote_url_hosts: strings(matches, "remote_url_host"),
+            remote_url_schemes: strings(matches, "remote_url_schemes"),
+            analyze_local: matches
+                .get_one::<PathBuf>("analyze_local_root")
+                .zip(matches.get_one::<String>("analyze_local_token"))
+                .map(|(root, token)| (root.clone(), Redacted(token.clone()))),
+            audit_log: matches.get_one::<PathBuf>("audit_log").cloned(),
+            audit_token: matches
+                .get_one::<String>("audit_token")
+                .map(|token| Redacted(token.clone())),
+            stats_token: matches
+                .get_one::<String>("stats_token")
+                .map(|token| Redacted(token.clone())),
+            job_logs_token: matches
+                .get_one::<String>("job_logs_token")
+                .map(|token| Redacted(token.clone())),
+            idempotency_ttl_sec: *matches.get_one::<u64>("idempotency_ttl_sec").unwrap(),
+            json_log: matches.get_one::<String>("log_format").unwrap() == "json",
+            response_compression: !matches.get_flag("no_response_compression"),
+            ready_help_timeout_sec: matches.get_one::<u64>("ready_help_timeout_sec").copied(),
+            idle_timeout_sec: matches.get_one::<u64>("idle_timeout_sec").copied(),
+            cors: if matches.get_flag("no_cors") {
+                CorsMode::Disabled
+            } else if cors_origins.is_empty() {
+                CorsMode::AnyOrigin
+            } else {
+                CorsMode::Origins(cors_origins)
+            },
+            cors_methods: matches
+                .get_many::<http::Method>("cors_methods")
+                .unwrap()
+                .cloned()
+                .collect(),
+            cors_headers: strings(matches, "cors_headers"),
+        }
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Formats an optional value, which is `none` if absent.
+        struct Opt<T>(Option<T>);
+
+        impl<T: fmt::Display> fmt::Display for Opt<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match &self.0 {
+                    Some(value) => value.fmt(f),
+                    None => f.write_str("none"),
+                }
+            }
+        }
+
+        write!(
+            f,
+            "host={} port={} tls={} path_prefix={:?} upload_size_limit={} inference_dir={:?} \
+             python_bin={:?} memory_limit_mib={} workers={} client_request_timeout_sec={} \
+             keep_alive_sec={} shutdown_timeout_sec={} idle_timeout_sec={} \
+             ready_help_timeout_sec={} cors={} inference_env=[",
+            self.host,
+            self.port,
+            self.tls.is_some(),
+            self.path_prefix,
+            self.upload_size_limit,
+            self.inference_dir,
+            self.python_bin,
+            Opt(self.memory_limit_mib),
+            self.workers,
+            self.client_request_timeout_sec,
+            self.keep_alive_sec,
+            self.shutdown_timeout_sec,
+            Opt(self.idle_timeout_sec),
+            Opt(self.ready_help_timeout_sec),
+            self.cors,
+        )?;
+        for (i, (key, value)) in self.inference_env.iter().enumerate() {
+            let sep = if i == 0 { "" } else { ", " };
+            write!(f, "{}{}={}", sep, key, value)?;
+        }
+        f.write_str("]")
+    }
+}
+
+/// Returns the values of the option `id`, which are empty if it's absent.
+fn strings(matches: &ArgMatches, id: &str) -> Vec<String> {
+    matches
+        .get_many::<String>(id)
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Parses an environment variable given in the form of `KEY=VALUE`.
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains('\0') && !value.contains('\0') => {
+            Ok((String::from(key), String::from(value)))
+        }
+        _ => Err(format!(
+            "expected KEY=VALUE with a non-empty KEY, got {:?}",
+            s
+        )),
+    }
+}
+
+/// Splits a value of an inference model given in the form of `NAME=VALUE`, where the name is made
+/// of ASCII letters, digits, `-`, `_` and `.`, so that clients can give it as it is.
+fn split_model_name(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value))
+            if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                && !value.is_empty()
+                && !value.contains('\0') =>
+        {
+            Ok((String::from(name), String::from(value)))
+        }
+        _ => Err(format!(
+            "expected NAME=VALUE with a NAME of ASCII letters, digits, '-', '_' and '.', got {:?}",
+            s
+        )),
+    }
+}
+
+/// Parses an inference model given in the form of `NAME=DIR`.
+fn parse_inference_model(s: &str) -> Result<(String, PathBuf), String> {
+    split_model_name(s).map(|(name, dir)| (name, PathBuf::from(dir)))
+}
+
+/// Parses a path prefix, such as `/api/v1`. A leading slash is added and trailing slashes are
+/// removed, so the root becomes an empty prefix.
+fn parse_path_prefix(s: &str) -> Result<String, String> {
+    let prefix = s.trim_end_matches('/');
+    if prefix.contains(['?', '#']) {
+        return Err(format!("expected a path, got {:?}", s));
+    }
+    Ok(match prefix {
+        "" => String::new(),
+        prefix if prefix.starts_with('/') => String::from(prefix),
+        prefix => format!("/{}", prefix),
+    })
+}
+
+/// Builds the command line interface. Every option also reads an environment variable as
+/// documented on [`Config`].
+fn command() -> Command {
+    Command::new("streameme_backend")
+        .arg(
+            Arg::new("port")
+                .help("The port to listen on")
+                .short('p')
+                .long("port")
+                .value_parser(value_parser!(u16))
+                .default_value("9090"),
+        )
+        .arg(
+            Arg::new("host")
+                .help("The address to listen on")
+                .long("host")
+                .value_parser(value_parser!(IpAddr))
+                .default_value("0.0.0.0"),
+        )
+        .arg(
+            Arg::new("workers")
+                .help("The number of worker threads handling the requests [default: the number of available CPUs]")
+                .long("workers")
+                .value_parser(|s: &str| s.parse::<NonZeroUsize>()),
+        )
+        .arg(
+            Arg::new("tls_cert")
+                .help("Serve HTTPS and HTTP/2 with the certificate chain in this PEM file, along with --tls_key [default: plain HTTP]")
+                .long("tls_cert")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls_key"),
+        )
+        .arg(
+            Arg::new("tls_key")
+                .help("The PEM file of the private key of the certificate given by --tls_cert")
+                .long("tls_key")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls_cert"),
+        )
+        .arg(
+            Arg::new("path_prefix")
+                .help("The path prefix to serve the endpoints under, such as /api/v1 [default: none]")
+                .long("path_prefix")
+                .value_parser(parse_path_prefix),
+        )
+        .arg(
+            Arg::new("ready_at_root")
+                .help("Also serve /ready without the path prefix, e.g. for probes bypassing the gateway")
+                .long("ready_at_root")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("client_request_timeout_sec")
+                .help("The time for a client to send the request headers, where 0 disables the timeout. The body of an upload isn't subject to it")
+                .long("client_request_timeout_sec")
+                .value_parser(value_parser!(u64))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("keep_alive_sec")
+                .help("The time to keep an idle connection open, where 0 disables keep-alive")
+                .long("keep_alive_sec")
+                .value_parser(value_parser!(u64))
+                .default_value("75"),
+        )
+        .arg(
+            Arg::new("shutdown_timeout_sec")
+                .help("The time to let the requests in progress finish on SIGINT or SIGTERM before they are cut off")
+                .long("shutdown_timeout_sec")
+                .value_parser(value_parser!(u64))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("inference_dir")
+                .help("The root directory of streameme_inference project")
+                .long("inference_dir")
+                .value_parser(value_parser!(PathBuf))
+                .default_value("../streameme_inference"),
+        )
+        .arg(
+            Arg::new("python_bin")
+                .help("The Python interpreter running the inference script, relative to the inference directory")
+                .long("python_bin")
+                .value_parser(value_parser!(PathBuf))
+                .default_value(".venv/bin/python"),
+        )
+        .arg(
+            Arg::new("ffprobe_bin")
+                .help("The ffprobe executable probing the uploaded videos, looked up in PATH unless it's a path")
+                .long("ffprobe_bin")
+                .value_parser(value_parser!(PathBuf))
+                .default_value("ffprobe"),
+        )
+        .arg(
+            Arg::new("output_file_name")
+                .help("The name of the file the inference script writes its results to")
+                .long("output_file_name")
+                .default_value("suggestions.json"),
+        )
+        .arg(
+            Arg::new("video_name_template")
+                .help("The template of the video name passed to the inference script, with the placeholders {name}, {id} and {ts}")
+                .long("video_name_template")
+                .value_parser(|s: &str| s.parse::<VideoNameTemplate>())
+                .default_value("{name}"),
+        )
+        .arg(
+            Arg::new("inference_env")
+                .help("An environment variable for the inference script, in the form of KEY=VALUE")
+                .long("inference_env")
+                .value_parser(parse_env)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("inference_model")
+                .help("Another inference model clients can select with `model` in the metadata, in the form of NAME=DIR, where DIR is the root directory of its streameme_inference project")
+                .long("inference_model")
+                .value_parser(parse_inference_model)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("inference_model_arg")
+                .help("An extra argument for the inference script of a model given by --inference_model, in the form of NAME=ARG")
+                .long("inference_model_arg")
+                .value_parser(split_model_name)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("memory_limit_mib")
+                .help("Kill the inference script once its resident memory exceeds this many MiB, failing the analysis [default: unlimited]")
+                .long("memory_limit_mib")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("keep_inference_output")
+                .help("Keep the output of the inference script for every analysis in a subdirectory of this directory named after the request ID and the mode [default: discarded]")
+                .long("keep_inference_output")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("dedup_tolerance_sec")
+                .help("Drop a suggestion if another one of the same type starts and ends within this many seconds of it")
+                .long("dedup_tolerance_sec")
+                .value_parser(value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("meme_types")
+                .help("The comma-separated meme types to suggest. The suggestions of the other types are removed from every response")
+                .long("meme_types")
+                .value_delimiter(',')
+                .value_parser(|s: &str| s.parse::<MemeType>())
+                .default_value("happiness,love,anger,sorrow,hate,surprise"),
+        )
+        .arg(
+            Arg::new("max_suggestions")
+                .help("The maximum number of suggestions in a response, where 0 means no limit. The excess ones at the end of the video are dropped")
+                .long("max_suggestions")
+                .value_parser(value_parser!(usize))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("upload_size_limit")
+                .help("The maximum size of an upload in bytes")
+                .long("upload_size_limit")
+                .value_parser(value_parser!(usize))
+                .default_value("2147483648"),
+        )
+        .arg(
+            Arg::new("metadata_limit")
+                .help("The maximum size of the metadata part of an upload in bytes")
+                .long("metadata_limit")
+                .value_parser(value_parser!(usize))
+                .default_value("4096"),
+        )
+        .arg(
+            Arg::new("raw_output_token")
+                .help("Allow the requests with this bearer token to get the output of the inference script as it was written with /upload?raw=true, for debugging [default: disabled]")
+                .long("raw_output_token")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+        )
+        .arg(
+            Arg::new("partial_results")
+                .help("Return the suggestions the inference script wrote before it crashed, marked with analysis_status \"partial\"")
+                .long("partial_results")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stderr_tail_lines")
+                .help("The number of the last lines of the inference script's stderr returned with /upload?raw=true when it fails")
+                .long("stderr_tail_lines")
+                .value_parser(value_parser!(usize))
+                .default_value("20"),
+        )
+        .arg(
+            Arg::new("timestamp_precision")
+                .help("The subsecond precision of the timestamps in responses")
+                .long("timestamp_precision")
+                .value_parser(TimestampPrecision::NAMES)
+                .default_value("millis"),
+        )
+        .arg(
+            Arg::new("timestamp_format")
+                .help("The format of the timestamps in responses, where rfc3339 has a numeric offset and no subsecond digits")
+                .long("timestamp_format")
+                .value_parser(TimestampFormat::NAMES)
+                .default_value("js"),
+        )
+        .arg(
+            Arg::new("max_concurrent_uploads")
+                .help("The maximum number of uploads being handled at the same time [default: unlimited]")
+                .long("max_concurrent_uploads")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("max_resumable_uploads")
+                .help("The maximum number of incomplete resumable uploads, each of which holds a temporary file")
+                .long("max_resumable_uploads")
+                .value_parser(value_parser!(usize))
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("max_request_duration_sec")
+                .help("Answer an upload with a 504 once it has taken this many seconds, from receiving it to the response [default: unlimited]")
+                .long("max_request_duration_sec")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("min_free_space_mib")
+                .help("Reject uploads while the temporary directory has less free space than this many MiB [default: no threshold]")
+                .long("min_free_space_mib")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("min_duration_sec")
+                .help("Reject the videos shorter than this many seconds [default: unlimited]")
+                .long("min_duration_sec")
+                .value_parser(value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("max_duration_sec")
+                .help("Reject the videos longer than this many seconds [default: unlimited]")
+                .long("max_duration_sec")
+                .value_parser(value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("remote_url_host")
+                .help("A host /analyze-url may download videos from, optionally with a port other than the default one of the scheme as host:port, which can be repeated [default: none]")
+                .long("remote_url_host")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("remote_url_schemes")
+                .help("The comma-separated URL schemes /analyze-url may download videos with")
+                .long("remote_url_schemes")
+                .value_delimiter(',')
+                .default_value("https"),
+        )
+        .arg(
+            Arg::new("analyze_local_root")
+                .help("Serve /analyze-local, which analyzes the files under this directory on the host [default: disabled]")
+                .long("analyze_local_root")
+                .value_parser(value_parser!(PathBuf))
+                .requires("analyze_local_token"),
+        )
+        .arg(
+            Arg::new("analyze_local_token")
+                .help("The bearer token /analyze-local requires")
+                .long("analyze_local_token")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .requires("analyze_local_root"),
+        )
+        .arg(
+            Arg::new("audit_log")
+                .help("Append a record of every upload to this file, as a line of JSON [default: disabled]")
+                .long("audit_log")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("audit_token")
+                .help("Serve /audit to the requests with this bearer token [default: disabled]")
+                .long("audit_token")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .requires("audit_log"),
+        )
+        .arg(
+            Arg::new("stats_token")
+                .help("Serve /stats to the requests with this bearer token [default: disabled]")
+                .long("stats_token")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+        )
+        .arg(
+            Arg::new("job_logs_token")
+                .help("Serve /jobs/{id}/logs to the requests with this bearer token [default: disabled]")
+                .long("job_logs_token")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new()),
+        )
+        .arg(
+            Arg::new("idempotency_ttl_sec")
+                .help("Replay the response to an upload with an Idempotency-Key to the uploads from the same client with the same key for this many seconds, where 0 disables it")
+                .long("idempotency_ttl_sec")
+                .value_parser(value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("log_format")
+                .help("The format of the logs, where json writes every log record as a line of JSON")
+                .long("log_format")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new("ready_help_timeout_sec")
+                .help("Make /ready also run the inference script with --help, which must exit within this many seconds [default: disabled]")
+                .long("ready_help_timeout_sec")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("idle_timeout_sec")
+                .help("Report the analyzer idle in /ready after it has had no video to analyze for this many seconds [default: disabled]")
+                .long("idle_timeout_sec")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("cors_origin")
+                .help("An origin allowed to make cross-origin requests, which can be repeated [default: any origin]")
+                .long("cors_origin")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("cors_methods")
+                .help("The comma-separated methods allowed in cross-origin requests")
+                .long("cors_methods")
+                .value_delimiter(',')
+                .value_parser(|s: &str| s.to_uppercase().parse::<http::Method>())
+                .default_value("GET,POST,PATCH,HEAD"),
+        )
+        .arg(
+            Arg::new("cors_headers")
+                .help("The comma-separated request headers allowed in cross-origin requests")
+                .long("cors_headers")
+                .value_delimiter(',')
+                .default_value("content-type,authorization,tus-resumable,upload-length,upload-offset,upload-metadata"),
+        )
+        .arg(
+            Arg::new("no_response_compression")
+                .help("Don't compress the responses, e.g. when a proxy in front of the backend compresses them")
+                .long("no_response_compression")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_cors")
+                .help("Don't add CORS headers, e.g. when a gateway in front of the backend handles CORS")
+                .long("no_cors")
+                .action(ArgAction::SetTrue),
+        )
+        .mut_args(|arg| {
+            let env = format!("{}{}", ENV_PREFIX, arg.get_id().as_str().to_uppercase());
+            // The values may hold secrets, such as the ones of `--inference_env`.
+            arg.env(env).hide_env_values(true)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::Path;
+    use std::sync::{PoisonError, RwLock, RwLockWriteGuard};
+
+    /// Guards the environment variables of the process, which every parse of the configuration
+    /// reads. Setting them while another thread reads them is a data race, so a test changes them
+    /// only while holding it for writing, and the others parse while holding it for reading.
+    pub(super) static ENV_LOCK: RwLock<()> = RwLock::new(());
+
+    /// Sets environment variables while no other test parses the configuration, and removes them
+    /// when dropped, even if the test fails.
+    struct EnvVars {
+        names: Vec<&'static str>,
+        _lock: RwLockWriteGuard<'static, ()>,
+    }
+
+    impl EnvVars {
+        fn lock() -> Self {
+            Self {
+                names: Vec::new(),
+                _lock: ENV_LOCK.write().unwrap_or_else(PoisonError::into_inner),
+            }
+        }
+
+        fn set(&mut self, name: &'static str, value: &str) {
+            // SAFETY: No other thread reads the environment while `ENV_LOCK` is held for writing,
+            // since the tests only parse the configuration through `Config::try_parse_from`.
+            unsafe { env::set_var(name, value) };
+            self.names.push(name);
+        }
+    }
+
+    impl Drop for EnvVars {
+        fn drop(&mut self) {
+            for name in &self.names {
+                // SAFETY: See `EnvVars::set`.
+                unsafe { env::remove_var(name) };
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_display() {
+        let mut config = Config::try_parse_from([
+            "streameme_backend",
+            "--path_prefix",
+            "api/v1/",
+            "--inference_dir",
+            "/opt/inference",
+            "--workers",
+            "4",
+            "--keep_alive_sec",
+            "0",
+            "--idle_timeout_sec",
+            "600",
+            "--cors_origin",
+            "https://streameme.example",
+            "--inference_env",
+            "HF_TOKEN=hf_secret",
+            "--inference_env",
+            "HF_HOME=/data/hf",
+        ])
+        .unwrap();
+        config.python_bin = config.inference_dir.join(&config.python_bin);
+
+        assert_eq!(
+            config.to_string(),
+            "host=0.0.0.0 port=9090 tls=false path_prefix=\"/api/v1\" upload_size_limit=2147483648 \
+             inference_dir=\"/opt/inference\" python_bin=\"/opt/inference/.venv/bin/python\" \
+             memory_limit_mib=none workers=4 client_request_timeout_sec=30 keep_alive_sec=0 shutdown_timeout_sec=30 \
+             idle_timeout_sec=600 ready_help_timeout_sec=none \
+             cors=[\"https://streameme.example\"] \
+             inference_env=[HF_TOKEN=<redacted>, HF_HOME=<redacted>]"
+        );
+        assert!(!format!("{:?}", config).contains("hf_secret"));
+    }
+
+    #[test]
+    fn test_config_tls() {
+        let config = Config::try_parse_from([
+            "streameme_backend",
+            "--tls_cert",
+            "cert.pem",
+            "--tls_key",
+            "key.pem",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.tls,
+            Some((PathBuf::from("cert.pem"), PathBuf::from("key.pem")))
+        );
+        assert!(Config::try_parse_from(["streameme_backend", "--tls_cert", "cert.pem"]).is_err());
+        assert!(Config::try_parse_from(["streameme_backend", "--tls_key", "key.pem"]).is_err());
+    }
+
+    #[test]
+    fn test_config_analyze_local() {
+        let config = Config::try_parse_from([
+            "streameme_backend",
+            "--analyze_local_root",
+            "/data/videos",
+            "--analyze_local_token",
+            "s3cr3t",
+        ])
+        .unwrap();
+        let (root, token) = config.analyze_local.as_ref().unwrap();
+        assert_eq!(root, Path::new("/data/videos"));
+        assert_eq!(token.expose(), "s3cr3t");
+        assert!(!format!("{:?}", config).contains("s3cr3t"));
+        assert!(
+            Config::try_parse_from(["streameme_backend", "--analyze_local_root", "/data"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_config_inference_model() {
+        let config = Config::try_parse_from([
+            "streameme_backend",
+            "--inference_model",
+            "canary=/opt/inference-canary",
+            "--inference_model_arg",
+            "canary=--weights=v2",
+        ])
+        .unwrap();
+        assert_eq!(
+            config.inference_models,
+            vec![(
+                String::from("canary"),
+                PathBuf::from("/opt/inference-canary")
+            )]
+        );
+        assert_eq!(
+            config.inference_model_args,
+            vec![(String::from("canary"), String::from("--weights=v2"))]
+        );
+        for model in [
+            "=/opt/inference",
+            "canary",
+            "canary=",
+            "can ary=/opt/inference",
+        ] {
+            assert!(
+                Config::try_parse_from(["streameme_backend", "--inference_model", model]).is_err(),
+                "{}",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_precedence() {
+        let config = Config::try_parse_from(["streameme_backend"]).unwrap();
+        assert_eq!(config.max_suggestions, 1000);
+        assert_eq!(config.metadata_limit, 4096);
+        assert!(!config.partial_results);
+        assert!(config.response_compression);
+
+        let mut env_vars = EnvVars::lock();
+        env_vars.set("STREAMEME_MAX_SUGGESTIONS", "50");
+        env_vars.set("STREAMEME_METADATA_LIMIT", "8192");
+        env_vars.set("STREAMEME_PARTIAL_RESULTS", "true");
+        let config = Config::try_parse_from_current_env(["streameme_backend"]).unwrap();
+        assert_eq!(config.max_suggestions, 50);
+        assert_eq!(config.metadata_limit, 8192);
+        assert!(config.partial_results);
+        // The others keep their defaults.
+        assert_eq!(config.stderr_tail_lines, 20);
+
+        // The command line takes precedence over the environment.
+        let config =
+            Config::try_parse_from_current_env(["streameme_backend", "--max_suggestions", "20"])
+                .unwrap();
+        assert_eq!(config.max_suggestions, 20);
+        assert_eq!(config.metadata_limit, 8192);
+
+        env_vars.set("STREAMEME_MAX_SUGGESTIONS", "many");
+        assert!(Config::try_parse_from_current_env(["streameme_backend"]).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::handlers::timestamp::{Timestamp, TimestampPrecision};
+use crate::handlers::upload::REQUEST_ID_HEADER;
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use log;
+use serde::Serialize;
+use std::time::Instant;
+
+/// The target of the access log records emitted by [`json_access_log`], so that they can be told
+/// apart from the other log records.
+pub const ACCESS_LOG_TARGET: &str = "access";
+
+/// An access log record.
+#[derive(Debug, Serialize)]
+struct AccessLogRecord<'a> {
+    time: Timestamp,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    duration_ms: f64,
+    /// The correlation ID of the request, if the endpoint assigns one.
+    request_id: Option<&'a str>,
+    /// The declared size of the request body, which is roughly the size of the upload.
+    upload_size: Option<u64>,
+}
+
+/// Logs every request as a line of JSON at info level, with target [`ACCESS_LOG_TARGET`]. This is
+/// an alternative to [`actix_web::middleware::Logger`] for log pipelines ingesting JSON.
+pub async fn json_access_log(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started_at = Instant::now();
+    let method = req.method().clone();
+    let path = String::from(req.path());
+    let upload_size = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    let res = next.call(req).await?;
+    let record = AccessLogRecord {
+        time: Timestamp::now(TimestampPrecision::Millis),
+        method: method.as_str(),
+        path: &path,
+        status: res.status().as_u16(),
+        duration_ms: started_at.elapsed().as_secs_f64() * 1000.0,
+        request_id: res
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok()),
+        upload_size,
+    };
+    // Serializing a struct of plain fields never fails.
+    log::info!(
+        target: ACCESS_LOG_TARGET,
+        "{}",
+        serde_json::to_string(&record).unwrap()
+    );
+    Ok(res)
+}
@@ -0,0 +1,359 @@
+use crate::handlers::timestamp::{Timestamp, TimestampPrecision};
+use crate::handlers::utils::{self, ErrorBody};
+use actix_web::http::header;
+use actix_web::web::{self, Bytes, Data, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, get};
+use futures_util::stream;
+use log;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use uuid::Uuid;
+
+/// A record of an upload in the audit log.
+#[derive(Debug, Serialize)]
+pub(super) struct AuditRecord {
+    time: Timestamp,
+    /// The correlation ID of the upload, which is also returned in the `X-Request-Id` header.
+    id: Uuid,
+    /// The address of the peer, which is the proxy's if the backend is behind a reverse proxy.
+    client_ip: Option<IpAddr>,
+    file_name: Option<String>,
+    size: usize,
+    mode: String,
+    /// The status of the response to the upload.
+    status: u16,
+}
+
+impl AuditRecord {
+    #[inline]
+    pub(super) fn new(
+        id: Uuid,
+        req: &HttpRequest,
+        file_name: Option<&str>,
+        size: usize,
+        mode: String,
+        res: &HttpResponse,
+    ) -> Self {
+        Self {
+            time: Timestamp::now(TimestampPrecision::Millis),
+            id,
+            client_ip: req.peer_addr().map(|addr| addr.ip()),
+            file_name: file_name.map(String::from),
+            size,
+            mode,
+            status: res.status().as_u16(),
+        }
+    }
+
+    /// Appends this record to the [`AuditLog`] registered as app data, if any.
+    pub(super) fn submit(self, req: &HttpRequest) {
+        if let Some(audit_log) = req.app_data::<Data<AuditLog>>() {
+            audit_log.append(self);
+        }
+    }
+}
+
+/// An append-only log of the uploads, written as lines of JSON to a file. It should be registered
+/// as app data wrapped in [`actix_web::web::Data`] to be enabled.
+///
+/// The records are written by a dedicated thread, so a slow disk never blocks the request path.
+/// The records still queued are written before the log is dropped.
+///
+/// `GET /audit` is only served if a bearer token is set with [`AuditLog::token`].
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    token: Option<String>,
+    sender: Option<mpsc::Sender<AuditRecord>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, creating the file if it doesn't exist.
+    ///
+    /// # Errors
+    /// An error is returned if the file can't be opened for appending.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let (tx, rx) = mpsc::channel();
+        let writer_path = path.clone();
+        let writer = thread::spawn(move || write_records(&writer_path, file, rx));
+        Ok(Self {
+            path,
+            token: None,
+            sender: Some(tx),
+            writer: Some(writer),
+        })
+    }
+
+    /// Serves the records to the requests to `GET /audit` carrying the bearer token `token`.
+    #[inline]
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn append(&self, record: AuditRecord) {
+        // The writer only stops when the sender is dropped.
+        if let Some(sender) = &self.sender
+            && sender.send(record).is_err()
+        {
+            log::error!(
+                "failed to queue an audit record for {}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_records(path: &Path, file: File, records: mpsc::Receiver<AuditRecord>) {
+    let mut file = BufWriter::new(file);
+    while let Ok(record) = records.recv() {
+        // Serializing a struct of plain fields never fails.
+        let line = serde_json::to_string(&record).unwrap();
+        // Every record is flushed, so that the log is complete even if the process is killed.
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            log::error!(
+                "[{}] failed to write the audit record to {}: {}",
+                record.id,
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// The time range of the records `GET /audit` responds with. Both ends are in RFC 3339, and `from`
+/// is inclusive while `to` is exclusive.
+#[derive(Debug, Deserialize)]
+struct AuditRange {
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    to: Option<OffsetDateTime>,
+}
+
+impl AuditRange {
+    fn contains(&self, line: &str) -> bool {
+        #[derive(Deserialize)]
+        struct RecordTime {
+            #[serde(with = "time::serde::rfc3339")]
+            time: OffsetDateTime,
+        }
+
+        // The lines that aren't records, such as the ones written by hand, are left out.
+        let Ok(RecordTime { time }) = serde_json::from_str(line) else {
+            return false;
+        };
+        self.from.is_none_or(|from| from <= time) && self.to.is_none_or(|to| time < to)
+    }
+}
+
+/// Responds with the records of the audit log whose `time` falls in the range given by the `from`
+/// and `to` query parameters, as lines of JSON. The file is streamed rather than read into memory,
+/// since the log is never truncated by the backend. This requires the bearer token of the
+/// [`AuditLog`], since the records tell who uploaded what.
+#[get("/audit")]
+async fn audit(req: HttpRequest) -> HttpResponse {
+    let Some(audit_log) = req.app_data::<Data<AuditLog>>() else {
+        return HttpResponse::NotFound().finish();
+    };
+    let Some(token) = &audit_log.token else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !utils::has_bearer_token(&req, token) {
+        return utils::unauthorized();
+    }
+    let range = match web::Query::<AuditRange>::from_query(req.query_string()) {
+        Ok(range) => range.into_inner(),
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ErrorBody::new(
+                "invalid_time_range",
+                format!("from and to must be RFC 3339 timestamps: {}", e),
+            ));
+        }
+    };
+    if let (Some(from), Some(to)) = (range.from, range.to)
+        && from > to
+    {
+        return HttpResponse::BadRequest().json(ErrorBody::new(
+            "invalid_time_range",
+            "from must not be later than to",
+        ));
+    }
+    let file = match tokio::fs::File::open(&audit_log.path).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!(
+                "failed to open the audit log {}: {}",
+                audit_log.path.display(),
+                e
+            );
+            return HttpResponse::InternalServerError().json(ErrorBody::new(
+                "audit_log_unavailable",
+                "the audit log can't be read",
+            ));
+        }
+    };
+
+    let lines = BufReader::new(file).lines();
+    let records = stream::try_unfold((lines, range), |(mut lines, range)| async move {
+        while let Some(line) = lines.next_line().await? {
+            if range.contains(&line) {
+                return Ok(Some((Bytes::from(line + "\n"), (lines, range))));
+            }
+        }
+        Ok::<_, io::Error>(None)
+    });
+    HttpResponse::Ok()
+        .content_type("application/jsonl")
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .streaming(records)
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(audit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_log() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        std::fs::write(&path, "{}\n").unwrap();
+
+        let audit_log = AuditLog::open(&path).unwrap();
+        let req = TestRequest::default()
+            .peer_addr("192.0.2.1:50000".parse().unwrap())
+            .to_http_request();
+        let res = HttpResponse::Ok().finish();
+        for file_name in [Some("video.mp4"), None] {
+            audit_log.append(AuditRecord::new(
+                Uuid::nil(),
+                &req,
+                file_name,
+                1024,
+                String::from("multi"),
+                &res,
+            ));
+        }
+        drop(audit_log);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{}");
+        let record: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(record["id"], Uuid::nil().to_string());
+        assert_eq!(record["client_ip"], "192.0.2.1");
+        assert_eq!(record["file_name"], "video.mp4");
+        assert_eq!(record["size"], 1024);
+        assert_eq!(record["mode"], "multi");
+        assert_eq!(record["status"], 200);
+        let record: serde_json::Value = serde_json::from_str(lines[2]).unwrap();
+        assert_eq!(record["file_name"], serde_json::Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn test_audit() {
+        use actix_web::{App, test as actix_test};
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        let records = [
+            r#"{"time":"2025-09-22T00:00:00.000Z","id":"a"}"#,
+            r#"{"time":"2025-09-23T00:00:00.000Z","id":"b"}"#,
+            "{}",
+            r#"{"time":"2025-09-24T00:00:00.000Z","id":"c"}"#,
+        ];
+        std::fs::write(&path, records.join("\n") + "\n").unwrap();
+
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(Data::new(AuditLog::open(&path).unwrap()))
+                .configure(config),
+        )
+        .await;
+        let req = actix_test::TestRequest::get().uri("/audit").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(Data::new(AuditLog::open(&path).unwrap().token("s3cr3t")))
+                .configure(config),
+        )
+        .await;
+        let get = |uri: &str, token: &str| {
+            actix_test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .to_request()
+        };
+        let req = actix_test::TestRequest::get().uri("/audit").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), 401);
+        let res = actix_test::call_service(&app, get("/audit", "wrong")).await;
+        assert_eq!(res.status(), 401);
+
+        for (uri, expected) in [
+            ("/audit", &["a", "b", "c"][..]),
+            ("/audit?from=2025-09-23T00:00:00Z", &["b", "c"]),
+            ("/audit?to=2025-09-23T00:00:00Z", &["a"]),
+            (
+                "/audit?from=2025-09-22T12:00:00%2B09:00&to=2025-09-24T00:00:00Z",
+                &["b"],
+            ),
+            ("/audit?from=2025-10-01T00:00:00Z", &[]),
+        ] {
+            let res = actix_test::call_service(&app, get(uri, "s3cr3t")).await;
+            assert_eq!(res.status(), 200, "{}", uri);
+            assert_eq!(
+                res.headers().get(header::CACHE_CONTROL).unwrap(),
+                "no-store"
+            );
+            let body = actix_test::read_body(res).await;
+            let ids: Vec<String> = std::str::from_utf8(&body)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                    record["id"].as_str().unwrap().to_owned()
+                })
+                .collect();
+            assert_eq!(ids, expected, "{}", uri);
+        }
+
+        for uri in [
+            "/audit?from=yesterday",
+            "/audit?from=2025-09-24T00:00:00Z&to=2025-09-23T00:00:00Z",
+        ] {
+            let res = actix_test::call_service(&app, get(uri, "s3cr3t")).await;
+            assert_eq!(res.status(), 400, "{}", uri);
+            let body: serde_json::Value = actix_test::read_body_json(res).await;
+            assert_eq!(body["code"], "invalid_time_range");
+        }
+    }
+}
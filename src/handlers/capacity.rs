@@ -0,0 +1,108 @@
+use crate::analyzer::VideoAnalyzerBuffer;
+use crate::handlers::upload::UploadConfig;
+use actix_web::http::header;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpResponse, get};
+use serde::Serialize;
+
+/// The number of the videos the analyzer analyzes at the same time. It runs the tasks one after
+/// another in a single thread.
+const ANALYZER_WORKERS: usize = 1;
+
+#[derive(Debug, Serialize)]
+struct CapacityBody {
+    /// Whether an upload made now would be accepted rather than rejected for the load.
+    accepting: bool,
+    /// The number of the videos queued or being analyzed.
+    queue_depth: usize,
+    /// How many seconds a video queued now would wait before its analysis starts, if known.
+    estimated_wait_sec: Option<u64>,
+    analyzer_workers: usize,
+    /// The maximum number of uploads being handled at the same time, if limited.
+    max_concurrent_uploads: Option<usize>,
+    /// How many more uploads could be handled now, if limited.
+    available_upload_slots: Option<usize>,
+    storage_low: bool,
+}
+
+/// Tells whether the backend would accept an upload now, and how loaded its analyzer is, so that a
+/// client can decide to upload a large video now or later. The queue itself is unbounded, so an
+/// upload is only turned away for the limits of [`UploadConfig`].
+#[get("/capacity")]
+async fn capacity(
+    config: web::Data<UploadConfig>,
+    analyzer: web::Data<VideoAnalyzerBuffer>,
+) -> HttpResponse {
+    let upload_slots = config.upload_slots();
+    let storage_low = config.is_storage_low();
+    let body = CapacityBody {
+        accepting: upload_slots.is_none_or(|(_, available)| available > 0) && !storage_low,
+        queue_depth: analyzer.queue_depth(),
+        // Rounded up, as in the `X-Estimated-Wait` header of the uploads.
+        estimated_wait_sec: analyzer
+            .estimated_wait()
+            .map(|wait| wait.as_millis().div_ceil(1000) as u64),
+        analyzer_workers: ANALYZER_WORKERS,
+        max_concurrent_uploads: upload_slots.map(|(max, _)| max),
+        available_upload_slots: upload_slots.map(|(_, available)| available),
+        storage_low,
+    };
+    HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(body)
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(capacity);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::VideoAnalyzer;
+    use actix_web::{App, test as actix_test};
+    use std::path::PathBuf;
+
+    #[actix_web::test]
+    async fn test_capacity() {
+        let (_analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        let analyzer_buf = web::Data::new(analyzer_buf);
+        let get_capacity = async |config: UploadConfig| -> serde_json::Value {
+            let app = actix_test::init_service(
+                App::new()
+                    .app_data(web::Data::clone(&analyzer_buf))
+                    .app_data(web::Data::new(config))
+                    .configure(super::config),
+            )
+            .await;
+            let req = actix_test::TestRequest::get().uri("/capacity").to_request();
+            actix_test::call_and_read_body_json(&app, req).await
+        };
+
+        assert_eq!(
+            get_capacity(UploadConfig::new(1024)).await,
+            serde_json::json!({
+                "accepting": true,
+                "queue_depth": 0,
+                "estimated_wait_sec": null,
+                "analyzer_workers": 1,
+                "max_concurrent_uploads": null,
+                "available_upload_slots": null,
+                "storage_low": false,
+            })
+        );
+
+        let body = get_capacity(UploadConfig::new(1024).max_concurrent_uploads(4)).await;
+        assert_eq!(body["accepting"], true);
+        assert_eq!(body["max_concurrent_uploads"], 4);
+        assert_eq!(body["available_upload_slots"], 4);
+
+        let body = get_capacity(UploadConfig::new(1024).max_concurrent_uploads(0)).await;
+        assert_eq!(body["accepting"], false);
+        assert_eq!(body["available_upload_slots"], 0);
+
+        let body = get_capacity(UploadConfig::new(1024).min_free_space(".", u64::MAX)).await;
+        assert_eq!(body["accepting"], false);
+        assert_eq!(body["storage_low"], true);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::handlers::utils::ErrorBody;
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{Error, ErrorInternalServerError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use log;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The request header carrying the key a client chooses to identify an upload across its retries.
+const IDEMPOTENCY_KEY_HEADER: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// The response header marking a response replayed for a repeated key.
+const IDEMPOTENT_REPLAYED_HEADER: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// The maximum length of an idempotency key.
+const MAX_KEY_LEN: usize = 255;
+
+/// The default maximum number of the keys kept at once.
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// The response to a request, kept to be replayed to its retries.
+#[derive(Debug)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    completed_at: Instant,
+}
+
+#[derive(Debug)]
+enum IdempotencyEntry {
+    /// The request with the key is being handled.
+    InFlight,
+    Completed(StoredResponse),
+}
+
+/// A key as given by a client, scoped to the client, so that the clients choosing the same key,
+/// such as `1`, don't get the responses to each other.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ScopedKey {
+    /// The hash of the peer address and the `Authorization` header of the client.
+    client: u64,
+    key: String,
+}
+
+/// A key, and the hash of the request it was given with, which its retries must match.
+#[derive(Debug)]
+struct KeyedRequest {
+    entry: IdempotencyEntry,
+    fingerprint: u64,
+}
+
+/// The idempotency keys of the recent uploads, and their responses. This should be registered as
+/// app data wrapped in [`actix_web::web::Data`], and the `Idempotency-Key` header is ignored
+/// otherwise.
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    ttl: Duration,
+    max_entries: usize,
+    hasher: RandomState,
+    entries: Mutex<HashMap<ScopedKey, KeyedRequest>>,
+}
+
+impl IdempotencyStore {
+    /// Creates an [`IdempotencyStore`] replaying the response to a key for `ttl` after it's
+    /// produced.
+    #[inline]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            hasher: RandomState::new(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the maximum number of the keys kept at once, each of which holds a whole response. The
+    /// oldest response is dropped before its TTL expires to make room for a new key. It defaults
+    /// to 10000.
+    #[inline]
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Drops the responses kept for longer than the TTL. A key in flight is never dropped.
+    fn prune_expired(entries: &mut HashMap<ScopedKey, KeyedRequest>, ttl: Duration) {
+        entries.retain(|_, keyed| match &keyed.entry {
+            IdempotencyEntry::InFlight => true,
+            IdempotencyEntry::Completed(res) => res.completed_at.elapsed() <= ttl,
+        });
+    }
+
+    /// Drops the oldest responses until there is room for a new key. The keys in flight aren't
+    /// dropped, since their number is bounded by the concurrent requests.
+    fn make_room(&self, entries: &mut HashMap<ScopedKey, KeyedRequest>) {
+        while entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .filter_map(|(key, keyed)| match &keyed.entry {
+                    IdempotencyEntry::InFlight => None,
+                    IdempotencyEntry::Completed(res) => Some((key, res.completed_at)),
+                })
+                .min_by_key(|(_, completed_at)| *completed_at)
+                .map(|(key, _)| key.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    /// Returns the hash of the client sending `req`, which scopes its keys.
+    fn client_of(&self, req: &ServiceRequest) -> u64 {
+        let authorization = req.headers().get(header::AUTHORIZATION);
+        self.hasher
+            .hash_one((req.peer_addr().map(|addr| addr.ip()), authorization))
+    }
+
+    /// Returns the hash of what identifies `req` without reading its body, which a retry must
+    /// repeat.
+    fn fingerprint_of(&self, req: &ServiceRequest) -> u64 {
+        self.hasher.hash_one((
+            req.method().as_str(),
+            req.path(),
+            req.query_string(),
+            req.headers().get(header::CONTENT_LENGTH),
+        ))
+    }
+}
+
+/// Removes a key in flight from the store unless its response is stored, such as when the request
+/// fails or is dropped, so that a retry is handled again.
+struct InFlightKey {
+    store: web::Data<IdempotencyStore>,
+    key: Option<ScopedKey>,
+}
+
+impl InFlightKey {
+    fn complete(mut self, res: StoredResponse) {
+        if let Some(key) = self.key.take() {
+            let mut entries = self.store.entries.lock().unwrap();
+            if let Some(keyed) = entries.get_mut(&key) {
+                keyed.entry = IdempotencyEntry::Completed(res);
+            }
+        }
+    }
+}
+
+impl Drop for InFlightKey {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+/// Makes the retries of an upload carrying an `Idempotency-Key` header safe. The first request
+/// with a key is handled as usual, and its response is replayed to the later requests from the same
+/// client with the same key until [`IdempotencyStore`]'s TTL expires, without reading their bodies
+/// or analyzing the video again. A request whose key is still in flight is rejected with a 409,
+/// and one reusing a key for another request, such as with another query or body length, with a
+/// 422.
+///
+/// The responses with a server error aren't stored, since a retry may succeed.
+pub(super) async fn deduplicate_uploads(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let store = req.app_data::<web::Data<IdempotencyStore>>().cloned();
+    let key = req.headers().get(IDEMPOTENCY_KEY_HEADER).cloned();
+    let (Some(store), Some(key)) = (store, key) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_owned(),
+        _ => {
+            let res = HttpResponse::BadRequest().json(ErrorBody::new(
+                "invalid_idempotency_key",
+                format!(
+                    "Idempotency-Key must be 1 to {} visible ASCII characters",
+                    MAX_KEY_LEN
+                ),
+            ));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    };
+
+    let key = ScopedKey {
+        client: store.client_of(&req),
+        key,
+    };
+    let fingerprint = store.fingerprint_of(&req);
+    {
+        let mut entries = store.entries.lock().unwrap();
+        IdempotencyStore::prune_expired(&mut entries, store.ttl);
+        if entries
+            .get(&key)
+            .is_some_and(|keyed| keyed.fingerprint != fingerprint)
+        {
+            log::info!(
+                "upload rejected, idempotency key {:?} reused for another request",
+                key.key
+            );
+            let res = HttpResponse::UnprocessableEntity().json(ErrorBody::new(
+                "idempotency_key_mismatch",
+                "the Idempotency-Key was used for another request",
+            ));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+        match entries.get(&key).map(|keyed| &keyed.entry) {
+            Some(IdempotencyEntry::InFlight) => {
+                log::info!("upload rejected, idempotency key {:?} in flight", key.key);
+                let res = HttpResponse::Conflict().json(ErrorBody::new(
+                    "idempotency_key_in_use",
+                    "an upload with the same Idempotency-Key is in progress",
+                ));
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+            Some(IdempotencyEntry::Completed(stored)) => {
+                log::info!("replaying the response to idempotency key {:?}", key.key);
+                let mut res = HttpResponseBuilder::new(stored.status);
+                for (name, value) in &stored.headers {
+                    res.append_header((name.clone(), value.clone()));
+                }
+                let res = res
+                    .insert_header((IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true")))
+                    .body(stored.body.clone());
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+            None => {
+                store.make_room(&mut entries);
+                entries.insert(
+                    key.clone(),
+                    KeyedRequest {
+                        entry: IdempotencyEntry::InFlight,
+                        fingerprint,
+                    },
+                );
+            }
+        }
+    }
+
+    let in_flight = InFlightKey {
+        store,
+        key: Some(key),
+    };
+    let res = next.call(req).await?;
+    if res.status().is_server_error() {
+        return Ok(res.map_into_left_body());
+    }
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = body::to_bytes(body)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.into()))?;
+    in_flight.complete(StoredResponse {
+        status: res.status(),
+        headers: res.headers().clone(),
+        body: body.clone(),
+        completed_at: Instant::now(),
+    });
+    let res: HttpResponse<BoxBody> = res.set_body(body).map_into_boxed_body();
+    Ok(ServiceResponse::new(req, res).map_into_right_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, HttpRequest, test as actix_test};
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns `key` scoped to the client of the test requests without `Authorization`.
+    fn scoped(store: &IdempotencyStore, key: &str) -> ScopedKey {
+        ScopedKey {
+            client: store
+                .hasher
+                .hash_one((None::<IpAddr>, None::<&HeaderValue>)),
+            key: key.to_owned(),
+        }
+    }
+
+    fn in_flight() -> KeyedRequest {
+        KeyedRequest {
+            entry: IdempotencyEntry::InFlight,
+            fingerprint: 0,
+        }
+    }
+
+    /// Counts the requests reaching it, and responds with the count, or with a 500 if the request
+    /// asks for it.
+    async fn count(req: HttpRequest, calls: web::Data<AtomicUsize>) -> HttpResponse {
+        let calls = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        if req.query_string() == "fail" {
+            return HttpResponse::InternalServerError().finish();
+        }
+        HttpResponse::Ok().body(calls.to_string())
+    }
+
+    #[actix_web::test]
+    async fn test_deduplicate_uploads() {
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let store = web::Data::new(IdempotencyStore::new(Duration::from_millis(200)));
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::clone(&calls))
+                .app_data(web::Data::clone(&store))
+                .route(
+                    "/upload",
+                    web::post().to(count).wrap(from_fn(deduplicate_uploads)),
+                ),
+        )
+        .await;
+        let post = |uri: &str, key: Option<&str>| {
+            let mut req = actix_test::TestRequest::post().uri(uri);
+            if let Some(key) = key {
+                req = req.insert_header((IDEMPOTENCY_KEY_HEADER, key));
+            }
+            req.to_request()
+        };
+        let post_as = |token: &str, key: &str| {
+            actix_test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        // The response to a key is replayed.
+        for _ in 0..2 {
+            let res = actix_test::call_service(&app, post("/upload", Some("a"))).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(actix_test::read_body(res).await, "1");
+        }
+        let res = actix_test::call_service(&app, post("/upload", Some("a"))).await;
+        assert_eq!(
+            res.headers().get(IDEMPOTENT_REPLAYED_HEADER).unwrap(),
+            "true"
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // The requests with another key or without one are handled.
+        let res = actix_test::call_service(&app, post("/upload", Some("b"))).await;
+        assert_eq!(actix_test::read_body(res).await, "2");
+        let res = actix_test::call_service(&app, post("/upload", None)).await;
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        assert_eq!(actix_test::read_body(res).await, "3");
+
+        // The keys of the other clients are apart.
+        let res = actix_test::call_service(&app, post_as("alice", "a")).await;
+        assert_eq!(actix_test::read_body(res).await, "4");
+        let res = actix_test::call_service(&app, post_as("bob", "a")).await;
+        assert_eq!(actix_test::read_body(res).await, "5");
+        let res = actix_test::call_service(&app, post_as("alice", "a")).await;
+        assert_eq!(actix_test::read_body(res).await, "4");
+
+        // A key reused for another request is rejected.
+        let res = actix_test::call_service(&app, post("/upload?other", Some("a"))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // A server error isn't stored.
+        let res = actix_test::call_service(&app, post("/upload?fail", Some("c"))).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let res = actix_test::call_service(&app, post("/upload", Some("c"))).await;
+        assert_eq!(actix_test::read_body(res).await, "7");
+
+        // A key in flight is rejected.
+        let mut in_flight_request = in_flight();
+        in_flight_request.fingerprint = store.fingerprint_of(
+            &actix_test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((IDEMPOTENCY_KEY_HEADER, "d"))
+                .to_srv_request(),
+        );
+        store
+            .entries
+            .lock()
+            .unwrap()
+            .insert(scoped(&store, "d"), in_flight_request);
+        let res = actix_test::call_service(&app, post("/upload", Some("d"))).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
+
+        let res = actix_test::call_service(&app, post("/upload", Some(""))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // The response expires after the TTL.
+        actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+        let res = actix_test::call_service(&app, post("/upload", Some("a"))).await;
+        assert_eq!(actix_test::read_body(res).await, "8");
+        let entries = store.entries.lock().unwrap();
+        assert!(entries.contains_key(&scoped(&store, "d")));
+        assert!(!entries.contains_key(&scoped(&store, "b")));
+    }
+
+    #[actix_web::test]
+    async fn test_max_entries() {
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let store = web::Data::new(IdempotencyStore::new(Duration::from_secs(60)).max_entries(2));
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::clone(&calls))
+                .app_data(web::Data::clone(&store))
+                .route(
+                    "/upload",
+                    web::post().to(count).wrap(from_fn(deduplicate_uploads)),
+                ),
+        )
+        .await;
+        for key in ["a", "b", "c"] {
+            let req = actix_test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+                .to_request();
+            actix_test::call_service(&app, req).await;
+        }
+
+        // The oldest response is dropped to make room.
+        let entries = store.entries.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.contains_key(&scoped(&store, "a")));
+        assert!(entries.contains_key(&scoped(&store, "c")));
+    }
+
+    #[test]
+    fn test_in_flight_key_dropped() {
+        let store = web::Data::new(IdempotencyStore::new(Duration::from_secs(60)));
+        store
+            .entries
+            .lock()
+            .unwrap()
+            .insert(scoped(&store, "a"), in_flight());
+        drop(InFlightKey {
+            store: web::Data::clone(&store),
+            key: Some(scoped(&store, "a")),
+        });
+        assert!(store.entries.lock().unwrap().is_empty());
+    }
+}
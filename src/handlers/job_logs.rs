@@ -0,0 +1,353 @@
+use crate::handlers::utils::{self, ErrorBody};
+use actix_web::http::header;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, get};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// The number of the latest jobs whose log records are kept.
+const MAX_JOBS: usize = 1000;
+
+/// The number of the latest log records kept per job.
+const MAX_RECORDS_PER_JOB: usize = 200;
+
+/// The maximum length of a kept log message in bytes. The end of a longer one is kept, since it's
+/// where the dumped stderr of a failed inference procedure tells what went wrong.
+const MAX_MESSAGE_LEN: usize = 16 * 1024;
+
+/// The maximum total size of the kept log records in bytes. The oldest jobs are dropped beyond it.
+const MAX_TOTAL_LEN: usize = 8 * 1024 * 1024;
+
+/// The length of the correlation ID prefixing the message of a job, such as
+/// `[0b7f5c6e-2f4a-4d6b-9a43-3c1e8f2d5a10] `.
+const ID_PREFIX_LEN: usize = 39;
+
+#[derive(Debug, Clone, Serialize)]
+struct JobLogRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    time: OffsetDateTime,
+    level: &'static str,
+    target: String,
+    message: String,
+}
+
+impl JobLogRecord {
+    /// Returns the size of the record counted against [`MAX_TOTAL_LEN`].
+    #[inline]
+    fn len(&self) -> usize {
+        self.target.len() + self.message.len()
+    }
+}
+
+#[derive(Debug, Default)]
+struct JobLogBuffer {
+    records: HashMap<Uuid, VecDeque<JobLogRecord>>,
+    /// The jobs in the order of their first records, oldest first.
+    jobs: VecDeque<Uuid>,
+    /// The total size of the records.
+    len: usize,
+}
+
+impl JobLogBuffer {
+    /// Drops the records of the oldest job.
+    fn pop_oldest(&mut self) {
+        if let Some(oldest) = self.jobs.pop_front()
+            && let Some(records) = self.records.remove(&oldest)
+        {
+            self.len -= records.iter().map(JobLogRecord::len).sum::<usize>();
+        }
+    }
+}
+
+/// The latest log records of the jobs, keyed by the correlation IDs prefixing them, such as the
+/// `X-Request-Id` of an upload, so that `GET /jobs/{id}/logs` can tell why a job failed without
+/// access to the host. This should be registered as app data wrapped in [`actix_web::web::Data`],
+/// and fed with the records of the logger, and the endpoint isn't served otherwise.
+///
+/// Only the records of the latest 1000 jobs, and the latest 200 of each, are kept, and the oldest
+/// jobs are dropped while the records take more than 8 MiB in total.
+#[derive(Debug)]
+pub struct JobLogs {
+    token: String,
+    buffer: Mutex<JobLogBuffer>,
+}
+
+impl JobLogs {
+    /// Creates an empty [`JobLogs`] served to the requests carrying the bearer token `token`.
+    #[inline]
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            buffer: Mutex::new(JobLogBuffer::default()),
+        }
+    }
+
+    /// Keeps `record` if its message starts with the correlation ID of a job, such as
+    /// `[0b7f5c6e-2f4a-4d6b-9a43-3c1e8f2d5a10] starting inference procedure`. The ID is stripped
+    /// from the kept message.
+    pub fn capture(&self, record: &log::Record) {
+        // Most of the records, such as the access log, aren't about a job, so only the prefix is
+        // formatted until the record turns out to be.
+        let Some(id) = job_id(record.args()) else {
+            return;
+        };
+        let message = record.args().to_string();
+        let message = &message[ID_PREFIX_LEN..];
+        let message = match message.len() {
+            len if len > MAX_MESSAGE_LEN => {
+                let mut start = len - MAX_MESSAGE_LEN;
+                while !message.is_char_boundary(start) {
+                    start += 1;
+                }
+                format!("...{}", &message[start..])
+            }
+            _ => message.to_owned(),
+        };
+        let record = JobLogRecord {
+            time: OffsetDateTime::now_utc(),
+            level: record.level().as_str(),
+            target: record.target().to_owned(),
+            message,
+        };
+
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        if !buffer.records.contains_key(&id) {
+            if buffer.jobs.len() == MAX_JOBS {
+                buffer.pop_oldest();
+            }
+            buffer.jobs.push_back(id);
+        }
+        buffer.len += record.len();
+        let records = buffer.records.entry(id).or_default();
+        let dropped = match records.len() {
+            MAX_RECORDS_PER_JOB => records.pop_front().map_or(0, |dropped| dropped.len()),
+            _ => 0,
+        };
+        records.push_back(record);
+        buffer.len -= dropped;
+        // A single job never exceeds the limit, since its records are limited too.
+        while buffer.len > MAX_TOTAL_LEN && buffer.jobs.front() != Some(&id) {
+            buffer.pop_oldest();
+        }
+    }
+
+    /// Returns the kept log records of the job `id`, oldest first.
+    fn records(&self, id: Uuid) -> Option<Vec<JobLogRecord>> {
+        let buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        buffer
+            .records
+            .get(&id)
+            .map(|records| records.iter().cloned().collect())
+    }
+}
+
+/// Responds with the kept log records of a job, including the stderr of its inference procedure
+/// if it failed. This requires the bearer token of the [`JobLogs`], since the records tell the
+/// details of the host.
+#[get("/jobs/{id}/logs")]
+async fn job_logs(req: HttpRequest, id: web::Path<Uuid>) -> HttpResponse {
+    #[derive(Serialize)]
+    struct JobLogsBody {
+        id: Uuid,
+        records: Vec<JobLogRecord>,
+    }
+
+    let Some(logs) = req.app_data::<web::Data<JobLogs>>() else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !utils::has_bearer_token(&req, &logs.token) {
+        return utils::unauthorized();
+    }
+    let id = id.into_inner();
+    let Some(records) = logs.records(id) else {
+        return HttpResponse::NotFound().json(ErrorBody::new(
+            "job_not_found",
+            format!("no log records are kept for the job {}", id),
+        ));
+    };
+    HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(JobLogsBody { id, records })
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(job_logs);
+}
+
+/// Returns the correlation ID prefixing `message`, formatting no more of it than the prefix.
+fn job_id(message: &fmt::Arguments) -> Option<Uuid> {
+    struct Prefix {
+        buf: [u8; ID_PREFIX_LEN],
+        len: usize,
+    }
+
+    impl Write for Prefix {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let n = s.len().min(ID_PREFIX_LEN - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+            self.len += n;
+            // Stops the formatting once the prefix is complete.
+            match self.len {
+                ID_PREFIX_LEN => Err(fmt::Error),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    let mut prefix = Prefix {
+        buf: [0; ID_PREFIX_LEN],
+        len: 0,
+    };
+    let _ = prefix.write_fmt(*message);
+    if prefix.len < ID_PREFIX_LEN {
+        return None;
+    }
+    let id = prefix.buf.strip_prefix(b"[")?.strip_suffix(b"] ")?;
+    Uuid::try_parse_ascii(id).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, test as actix_test};
+
+    fn capture(logs: &JobLogs, level: log::Level, message: std::fmt::Arguments) {
+        logs.capture(
+            &log::Record::builder()
+                .args(message)
+                .level(level)
+                .target("streameme_backend::analyzer")
+                .build(),
+        );
+    }
+
+    #[test]
+    fn test_capture() {
+        let logs = JobLogs::new("s3cr3t");
+        let id = Uuid::new_v4();
+        capture(
+            &logs,
+            log::Level::Info,
+            format_args!("[{}] file received", id),
+        );
+        capture(&logs, log::Level::Info, format_args!("server started"));
+        capture(&logs, log::Level::Info, format_args!("[not-an-id] nothing"));
+        capture(&logs, log::Level::Info, format_args!("[{}]nothing", id));
+        let stderr = "x".repeat(MAX_MESSAGE_LEN) + "OSError: model not found";
+        capture(
+            &logs,
+            log::Level::Error,
+            format_args!("[{}] {}", id, stderr),
+        );
+
+        let records = logs.records(id).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].level, "INFO");
+        assert_eq!(records[0].message, "file received");
+        assert_eq!(records[1].level, "ERROR");
+        assert_eq!(records[1].message.len(), MAX_MESSAGE_LEN + 3);
+        assert!(records[1].message.starts_with("...x"));
+        assert!(records[1].message.ends_with("OSError: model not found"));
+        assert_eq!(logs.buffer.lock().unwrap().jobs.len(), 1);
+
+        // Only the latest records of the latest jobs are kept.
+        for i in 0..MAX_RECORDS_PER_JOB {
+            capture(&logs, log::Level::Debug, format_args!("[{}] {}", id, i));
+        }
+        let records = logs.records(id).unwrap();
+        assert_eq!(records.len(), MAX_RECORDS_PER_JOB);
+        assert_eq!(records[0].message, "0");
+        for _ in 0..MAX_JOBS {
+            capture(
+                &logs,
+                log::Level::Info,
+                format_args!("[{}] ", Uuid::new_v4()),
+            );
+        }
+        assert!(logs.records(id).is_none());
+        let buffer = logs.buffer.lock().unwrap();
+        assert_eq!(buffer.records.len(), MAX_JOBS);
+        assert_eq!(buffer.len, buffer_len(&buffer));
+    }
+
+    fn buffer_len(buffer: &JobLogBuffer) -> usize {
+        buffer
+            .records
+            .values()
+            .flatten()
+            .map(JobLogRecord::len)
+            .sum()
+    }
+
+    #[test]
+    fn test_capture_total_len() {
+        let logs = JobLogs::new("s3cr3t");
+        let message = "x".repeat(MAX_MESSAGE_LEN);
+        let ids: Vec<Uuid> = (0..=MAX_TOTAL_LEN / MAX_MESSAGE_LEN)
+            .map(|_| Uuid::new_v4())
+            .collect();
+        for id in &ids {
+            capture(
+                &logs,
+                log::Level::Error,
+                format_args!("[{}] {}", id, message),
+            );
+        }
+
+        // The oldest jobs are dropped to keep the records within the limit.
+        assert!(logs.records(ids[0]).is_none());
+        assert!(logs.records(*ids.last().unwrap()).is_some());
+        let buffer = logs.buffer.lock().unwrap();
+        assert!(buffer.len <= MAX_TOTAL_LEN);
+        assert_eq!(buffer.len, buffer_len(&buffer));
+        assert_eq!(buffer.jobs.len(), buffer.records.len());
+    }
+
+    #[actix_web::test]
+    async fn test_job_logs() {
+        let app = actix_test::init_service(App::new().configure(config)).await;
+        let id = Uuid::new_v4();
+        let uri = format!("/jobs/{}/logs", id);
+        let req = actix_test::TestRequest::get().uri(&uri).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+
+        let logs = web::Data::new(JobLogs::new("s3cr3t"));
+        capture(
+            &logs,
+            log::Level::Warn,
+            format_args!("[{}] frame rate too low", id),
+        );
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::clone(&logs))
+                .configure(config),
+        )
+        .await;
+        let get = |uri: &str, token: &str| {
+            actix_test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let res = actix_test::call_service(&app, get(&uri, "wrong")).await;
+        assert_eq!(res.status(), 401);
+        let other = format!("/jobs/{}/logs", Uuid::new_v4());
+        let res = actix_test::call_service(&app, get(&other, "s3cr3t")).await;
+        assert_eq!(res.status(), 404);
+
+        let res = actix_test::call_service(&app, get(&uri, "s3cr3t")).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["id"], id.to_string());
+        assert_eq!(body["records"].as_array().unwrap().len(), 1);
+        assert_eq!(body["records"][0]["level"], "WARN");
+        assert_eq!(body["records"][0]["target"], "streameme_backend::analyzer");
+        assert_eq!(body["records"][0]["message"], "frame rate too low");
+    }
+}
@@ -0,0 +1,243 @@
+use crate::analyzer::VideoAnalyzerBuffer;
+use crate::handlers::upload::{
+    self, UploadConfig, UploadFormMetadata, UploadQuery, limit_concurrent_uploads,
+    limit_request_duration,
+};
+use crate::handlers::utils::{self, ErrorBody};
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::middleware::from_fn;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, post};
+use log;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tempfile::{NamedTempFile, TempPath};
+use uuid::Uuid;
+
+/// Configuration of the `/analyze-local` endpoint, which analyzes a file already on the host of
+/// the backend, such as for a batch tool running next to it. This should be registered as app data
+/// wrapped in [`actix_web::web::Data`], and the endpoint isn't served otherwise.
+///
+/// Only the files under the allowed root can be analyzed, and only by the requests carrying the
+/// bearer token.
+#[derive(Debug, Clone)]
+pub struct LocalUploadConfig {
+    root: PathBuf,
+    dir: PathBuf,
+    token: String,
+}
+
+impl LocalUploadConfig {
+    /// Creates a [`LocalUploadConfig`] allowing the files under `root` to be analyzed by the
+    /// requests carrying `token`. A link to the file being analyzed is created under `dir`, and
+    /// removed after the analysis, so the file itself is never moved or removed.
+    #[inline]
+    pub fn new(
+        root: impl Into<PathBuf>,
+        dir: impl Into<PathBuf>,
+        token: impl Into<String>,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            dir: dir.into(),
+            token: token.into(),
+        }
+    }
+
+    /// Resolves `path`, which is either relative to the root or absolute, to the file it names.
+    fn resolve(&self, path: &Path) -> Result<PathBuf, LocalPathError> {
+        // `..` is rejected before touching the file system, so that the response can't tell
+        // whether a file exists outside the root.
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(LocalPathError::NotAllowed);
+        }
+        let root = fs::canonicalize(&self.root).map_err(LocalPathError::Io)?;
+        let path = root.join(path);
+        if !path.starts_with(&root) {
+            return Err(LocalPathError::NotAllowed);
+        }
+        let resolved = fs::canonicalize(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => LocalPathError::NotFound,
+            _ => LocalPathError::Io(e),
+        })?;
+        // A symbolic link under the root may still point outside of it.
+        if !resolved.starts_with(&root) {
+            return Err(LocalPathError::NotAllowed);
+        }
+        if !resolved.is_file() {
+            return Err(LocalPathError::NotFound);
+        }
+        Ok(resolved)
+    }
+}
+
+/// Why the path of a local file can't be analyzed.
+#[derive(Debug)]
+enum LocalPathError {
+    /// The path is outside the allowed root.
+    NotAllowed,
+    /// No regular file exists at the path.
+    NotFound,
+    Io(io::Error),
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalyzeLocalRequest {
+    /// The path of the video, relative to the allowed root or absolute. Its extension tells its
+    /// format.
+    path: PathBuf,
+    #[serde(flatten)]
+    metadata: UploadFormMetadata,
+}
+
+/// Creates a symbolic link at `link` to the file at `path`.
+#[cfg(unix)]
+fn link(path: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(path, link)
+}
+
+/// Creates a hard link at `link` to the file at `path`, since a symbolic link may require a
+/// privilege here, or copies the file if it's on another volume.
+#[cfg(not(unix))]
+fn link(path: &Path, link: &Path) -> io::Result<()> {
+    fs::hard_link(path, link).or_else(|_| fs::copy(path, link).map(|_| ()))
+}
+
+/// Wraps the file at `path` in a [`TempFile`] for the analysis, through a link created under `dir`
+/// that is removed once the [`TempFile`] is dropped.
+fn link_file(path: &Path, dir: &Path, id: Uuid) -> io::Result<TempFile> {
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned());
+    let link = dir.join(match path.extension() {
+        Some(extension) => format!("local-{}.{}", id, extension.to_string_lossy()),
+        None => format!("local-{}", id),
+    });
+    self::link(path, &link)?;
+    let link = TempPath::from_path(link);
+    let file = File::open(&link)?;
+    let size = file.metadata()?.len() as usize;
+    Ok(TempFile {
+        file: NamedTempFile::from_parts(file, link),
+        content_type: None,
+        file_name,
+        size,
+    })
+}
+
+#[post(
+    "/analyze-local",
+    wrap = "from_fn(limit_concurrent_uploads)",
+    wrap = "from_fn(limit_request_duration)"
+)]
+async fn analyze_local(
+    req: HttpRequest,
+    config: web::Data<UploadConfig>,
+    analyzer: web::Data<VideoAnalyzerBuffer>,
+    query: web::Query<UploadQuery>,
+    body: web::Json<AnalyzeLocalRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let Some(local) = req.app_data::<web::Data<LocalUploadConfig>>() else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    if !utils::has_bearer_token(&req, &local.token) {
+        return Ok(utils::unauthorized());
+    }
+    let id = Uuid::new_v4();
+    let body = body.into_inner();
+    let path = match local.resolve(&body.path) {
+        Ok(path) => path,
+        Err(LocalPathError::NotAllowed) => {
+            log::info!("[{}] local path rejected: {}", id, body.path.display());
+            return Ok(HttpResponse::Forbidden().json(ErrorBody::new(
+                "path_not_allowed",
+                "path must be within the root allowed on this server",
+            )));
+        }
+        Err(LocalPathError::NotFound) => {
+            return Ok(HttpResponse::NotFound().json(ErrorBody::new(
+                "file_not_found",
+                format!("no file exists at {}", body.path.display()),
+            )));
+        }
+        Err(LocalPathError::Io(e)) => return Err(e.into()),
+    };
+
+    log::info!("[{}] analyzing local file {}", id, path.display());
+    let file = link_file(&path, &local.dir, id)?;
+    Ok(upload::analyze_video(id, &req, &config, &analyzer, &file, body.metadata, *query).await)
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.app_data(utils::json_config()).service(analyze_local);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("videos");
+        fs::create_dir_all(root.join("batch")).unwrap();
+        fs::write(root.join("batch/video.mp4"), "").unwrap();
+        fs::write(dir.path().join("secret.mp4"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("secret.mp4"), root.join("escape.mp4")).unwrap();
+        let local = LocalUploadConfig::new(&root, dir.path(), "s3cr3t");
+        let resolve = |path: &str| local.resolve(Path::new(path));
+        let canonical_root = fs::canonicalize(&root).unwrap();
+
+        assert_eq!(
+            resolve("batch/video.mp4").unwrap(),
+            canonical_root.join("batch/video.mp4")
+        );
+        assert_eq!(
+            resolve(canonical_root.join("batch/video.mp4").to_str().unwrap()).unwrap(),
+            canonical_root.join("batch/video.mp4")
+        );
+        assert!(matches!(
+            resolve("batch/other.mp4"),
+            Err(LocalPathError::NotFound)
+        ));
+        assert!(matches!(resolve("batch"), Err(LocalPathError::NotFound)));
+        for path in [
+            "../secret.mp4",
+            "batch/../../secret.mp4",
+            #[cfg(unix)]
+            "escape.mp4",
+            "/etc/passwd",
+        ] {
+            assert!(
+                matches!(resolve(path), Err(LocalPathError::NotAllowed)),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_link_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("video.mp4");
+        fs::write(&path, "video").unwrap();
+        let link_dir = TempDir::new().unwrap();
+
+        let file = link_file(&path, link_dir.path(), Uuid::nil()).unwrap();
+        assert_eq!(file.file_name.as_deref(), Some("video.mp4"));
+        assert_eq!(file.size, 5);
+        assert_eq!(fs::read(file.file.path()).unwrap(), b"video");
+        assert_eq!(
+            file.file.path().extension(),
+            Some(std::ffi::OsStr::new("mp4"))
+        );
+        drop(file);
+        // Only the link is removed.
+        assert_eq!(fs::read_dir(link_dir.path()).unwrap().count(), 0);
+        assert!(path.exists());
+    }
+}
@@ -1,8 +1,52 @@
+mod access_log;
+mod audit;
+mod capacity;
+mod idempotency;
+mod job_logs;
+mod local;
+mod openapi;
+mod ready;
+mod remote;
+mod stats;
+mod timestamp;
+mod tus;
 mod upload;
 mod utils;
+mod validate;
 
 use actix_web::web::ServiceConfig;
 
+pub use access_log::{ACCESS_LOG_TARGET, json_access_log};
+pub use audit::AuditLog;
+pub use idempotency::IdempotencyStore;
+pub use job_logs::JobLogs;
+pub use local::LocalUploadConfig;
+pub use remote::RemoteUploadConfig;
+pub use stats::StatsToken;
+pub use timestamp::{TimestampFormat, TimestampPrecision};
+pub use tus::TusStore;
+pub use upload::UploadConfig;
+
+/// Configures all the endpoints.
 pub fn config(cfg: &mut ServiceConfig) {
-    cfg.configure(upload::config);
+    cfg.configure(api_config).configure(probe_config);
+}
+
+/// Configures the endpoints serving clients, which are all but the readiness probe.
+pub fn api_config(cfg: &mut ServiceConfig) {
+    cfg.configure(upload::config)
+        .configure(openapi::config)
+        .configure(tus::config)
+        .configure(remote::config)
+        .configure(local::config)
+        .configure(stats::config)
+        .configure(capacity::config)
+        .configure(job_logs::config)
+        .configure(audit::config)
+        .configure(validate::config);
+}
+
+/// Configures the readiness probe, `GET /ready`.
+pub fn probe_config(cfg: &mut ServiceConfig) {
+    cfg.configure(ready::config);
 }
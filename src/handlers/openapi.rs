@@ -0,0 +1,75 @@
+use crate::handlers::upload;
+use actix_web::web::ServiceConfig;
+use actix_web::{HttpRequest, HttpResponse, get};
+use utoipa::OpenApi;
+use utoipa::openapi::Server;
+
+/// The OpenAPI document of `POST /upload`, derived from the types its requests are parsed into and
+/// its responses are serialized from, so that it can't drift from what the backend does.
+#[derive(OpenApi)]
+#[openapi(
+    paths(upload::upload_video),
+    components(schemas(upload::MultiModeUploadResponse))
+)]
+struct ApiDoc;
+
+/// Serves the OpenAPI document. The paths in it are relative to the path prefix the endpoints are
+/// served under, which is given as the server URL if there is one.
+#[get("/openapi.json")]
+async fn openapi_doc(req: HttpRequest) -> HttpResponse {
+    let mut doc = ApiDoc::openapi();
+    let prefix = req.path().strip_suffix("/openapi.json").unwrap_or_default();
+    if !prefix.is_empty() {
+        doc.servers = Some(vec![Server::new(prefix)]);
+    }
+    HttpResponse::Ok().json(doc)
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(openapi_doc);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, test as actix_test, web};
+
+    #[actix_web::test]
+    async fn test_openapi() {
+        let app =
+            actix_test::init_service(App::new().service(web::scope("/api").configure(config)))
+                .await;
+        let req = actix_test::TestRequest::get()
+            .uri("/api/openapi.json")
+            .to_request();
+        let doc: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(doc["servers"][0]["url"], "/api");
+        let upload = &doc["paths"]["/upload"]["post"];
+        let form = &upload["requestBody"]["content"]["multipart/form-data"]["schema"];
+        assert_eq!(form["$ref"], "#/components/schemas/UploadForm");
+        assert_eq!(
+            upload["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/UploadResponse"
+        );
+        let schemas = &doc["components"]["schemas"];
+        for schema in [
+            "UploadForm",
+            "UploadFormMetadata",
+            "UploadResponse",
+            "MultiModeUploadResponse",
+            "VideoAnalyzerSuggestion",
+            "ErrorBody",
+        ] {
+            assert!(schemas[schema].is_object(), "{} is missing", schema);
+        }
+        assert_eq!(
+            schemas["VideoAnalyzerSuggestion"]["properties"]["meme_type"]["$ref"],
+            "#/components/schemas/MemeType"
+        );
+        assert_eq!(
+            schemas["MemeType"]["enum"],
+            serde_json::json!([0, 1, 2, 3, 4, 5])
+        );
+    }
+}
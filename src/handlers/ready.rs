@@ -0,0 +1,48 @@
+use crate::analyzer::{ReadinessProbe, VideoAnalyzerBuffer};
+use crate::handlers::utils::ErrorBody;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, get};
+use log;
+use serde::Serialize;
+
+/// Tells whether an analysis could plausibly succeed, by checking the prerequisites of the
+/// inference procedure with the [`ReadinessProbe`] registered as app data. This responds with a
+/// 503 naming the failed check otherwise, so that no traffic is routed to a backend whose inference
+/// environment is broken. The details of the failure, such as the paths, are only logged, since
+/// this endpoint requires no authentication.
+///
+/// A ready backend also tells whether its analyzer is idle, for autoscalers releasing idle
+/// instances.
+#[get("/ready")]
+async fn ready(req: HttpRequest, probe: web::Data<ReadinessProbe>) -> HttpResponse {
+    #[derive(Serialize)]
+    struct NotReadyBody {
+        #[serde(flatten)]
+        error: ErrorBody,
+        check: &'static str,
+    }
+
+    match probe.check().await {
+        Ok(()) => {
+            let idle = req
+                .app_data::<web::Data<VideoAnalyzerBuffer>>()
+                .is_some_and(|analyzer| analyzer.is_idle());
+            HttpResponse::Ok().json(serde_json::json!({ "ready": true, "idle": idle }))
+        }
+        Err(failure) => {
+            log::warn!(
+                "readiness check {} failed: {}",
+                failure.check,
+                failure.message
+            );
+            HttpResponse::ServiceUnavailable().json(NotReadyBody {
+                error: ErrorBody::new("not_ready", failure.summary),
+                check: failure.check,
+            })
+        }
+    }
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(ready);
+}
@@ -0,0 +1,344 @@
+//! Analysis of a video the backend downloads by itself from a URL, such as one in object storage.
+//!
+//! `POST /analyze-url` takes the URL along with the same metadata as `POST /upload`, downloads the
+//! video into a temporary file, and analyzes it like an uploaded one. Only the URLs on the allowed
+//! schemes, hosts and ports are downloaded, so that the backend can't be used to reach internal
+//! services.
+
+use crate::analyzer::VideoAnalyzerBuffer;
+use crate::handlers::upload::{
+    self, UploadConfig, UploadFormMetadata, UploadQuery, limit_concurrent_uploads,
+    limit_request_duration, reject_when_storage_low,
+};
+use crate::handlers::utils::{self, ErrorBody};
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::http::{StatusCode, Uri, header};
+use actix_web::middleware::from_fn;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, post};
+use futures_util::StreamExt;
+use log;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// How long a download may take by default, from sending the request to receiving the last byte.
+const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Configuration of the `/analyze-url` endpoint. This should be registered as app data wrapped in
+/// [`actix_web::web::Data`].
+///
+/// To prevent the backend from being used to reach internal services, a video is only downloaded
+/// from a URL whose scheme, host and port are all allowed. No host is allowed by default, so the
+/// endpoint rejects every URL until some hosts are allowed with [`Self::allowed_host`].
+#[derive(Debug, Clone)]
+pub struct RemoteUploadConfig {
+    dir: PathBuf,
+    allowed_schemes: Vec<String>,
+    /// The allowed hosts, and their allowed ports if not the default ones of the schemes.
+    allowed_hosts: Vec<(String, Option<u16>)>,
+    download_timeout: Duration,
+}
+
+impl RemoteUploadConfig {
+    /// Creates a [`RemoteUploadConfig`] downloading videos into files under `dir`. Only `https`
+    /// URLs are allowed by default.
+    #[inline]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            allowed_schemes: vec![String::from("https")],
+            allowed_hosts: Vec::new(),
+            download_timeout: DEFAULT_DOWNLOAD_TIMEOUT,
+        }
+    }
+
+    /// Replaces the URL schemes videos can be downloaded with.
+    #[inline]
+    pub fn allowed_schemes<S: Into<String>>(
+        mut self,
+        schemes: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.allowed_schemes = schemes
+            .into_iter()
+            .map(|scheme| scheme.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Allows videos to be downloaded from `host`, which must match the host of a URL exactly,
+    /// ignoring case. Subdomains aren't included. Only the default port of the scheme of a URL is
+    /// allowed, unless another one is given in the form of `host:port`, such as
+    /// `storage.example.com:9000`.
+    #[inline]
+    pub fn allowed_host(mut self, host: impl Into<String>) -> Self {
+        let host = host.into().to_ascii_lowercase();
+        // The colons of a bare IPv6 address aren't followed by a port.
+        let host_port = host
+            .rsplit_once(':')
+            .filter(|(host, _)| !host.contains(':') || host.ends_with(']'))
+            .and_then(|(host, port)| Some((host.to_owned(), Some(port.parse().ok()?))));
+        self.allowed_hosts.push(host_port.unwrap_or((host, None)));
+        self
+    }
+
+    /// Sets how long a download may take, from sending the request to receiving the last byte.
+    /// It defaults to 10 minutes.
+    #[inline]
+    pub fn download_timeout(mut self, download_timeout: Duration) -> Self {
+        self.download_timeout = download_timeout;
+        self
+    }
+
+    /// Checks that a video may be downloaded from `url`, and returns the reason if not.
+    fn check_url(&self, url: &Uri) -> Result<(), String> {
+        let scheme = url.scheme_str().unwrap_or_default().to_ascii_lowercase();
+        if !self.allowed_schemes.contains(&scheme) {
+            return Err(format!(
+                "the scheme of the URL must be one of: {}",
+                self.allowed_schemes.join(", ")
+            ));
+        }
+        let host = url.host().unwrap_or_default().to_ascii_lowercase();
+        let default_port = default_port(&scheme);
+        let port = url.port_u16().or(default_port);
+        let allowed = self
+            .allowed_hosts
+            .iter()
+            .any(|(allowed_host, allowed_port)| {
+                *allowed_host == host && port.is_some() && allowed_port.or(default_port) == port
+            });
+        if !allowed {
+            let authority = match url.port_u16() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host,
+            };
+            return Err(format!(
+                "downloading from {:?} is not allowed on this server",
+                authority
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the port a URL with `scheme` connects to if it has none.
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalyzeUrlRequest {
+    url: String,
+    /// The file name of the video, whose extension tells its format. The last segment of the URL
+    /// path is used if not given.
+    file_name: Option<String>,
+    #[serde(flatten)]
+    metadata: UploadFormMetadata,
+}
+
+/// Returns the last segment of the path of `url`, if it's not empty.
+fn file_name_of(url: &Uri) -> Option<String> {
+    url.path()
+        .rsplit('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+}
+
+/// Why a download failed.
+enum DownloadError {
+    /// The video exceeds the size limit.
+    TooLarge,
+    /// The remote server couldn't be reached or didn't respond with the video.
+    Failed(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Downloads the video at `url` into a temporary file under the configured directory, aborting as
+/// soon as it exceeds `size_limit` bytes. Redirects aren't followed, since they could lead to a
+/// host that isn't allowed.
+async fn download(
+    remote: &RemoteUploadConfig,
+    url: Uri,
+    size_limit: usize,
+) -> Result<(NamedTempFile, Option<mime::Mime>, usize), DownloadError> {
+    let client = awc::Client::builder()
+        .max_redirects(0)
+        .timeout(remote.download_timeout)
+        .finish();
+    let mut res = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| DownloadError::Failed(format!("failed to request the video: {}", e)))?;
+    if !res.status().is_success() {
+        return Err(DownloadError::Failed(format!(
+            "the remote server responded with {}",
+            res.status()
+        )));
+    }
+    let content_length = res
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|content_length| content_length > size_limit) {
+        return Err(DownloadError::TooLarge);
+    }
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+
+    let file = NamedTempFile::new_in(&remote.dir)?;
+    let mut writer = tokio::fs::File::from_std(file.reopen()?);
+    let mut size = 0;
+    while let Some(chunk) = res.next().await {
+        let chunk = chunk
+            .map_err(|e| DownloadError::Failed(format!("failed to download the video: {}", e)))?;
+        size += chunk.len();
+        if size > size_limit {
+            return Err(DownloadError::TooLarge);
+        }
+        writer.write_all(&chunk).await?;
+    }
+    writer.flush().await?;
+    Ok((file, content_type, size))
+}
+
+#[post(
+    "/analyze-url",
+    wrap = "from_fn(reject_when_storage_low)",
+    wrap = "from_fn(limit_concurrent_uploads)",
+    wrap = "from_fn(limit_request_duration)"
+)]
+async fn analyze_url(
+    req: HttpRequest,
+    config: web::Data<UploadConfig>,
+    remote: web::Data<RemoteUploadConfig>,
+    analyzer: web::Data<VideoAnalyzerBuffer>,
+    query: web::Query<UploadQuery>,
+    body: web::Json<AnalyzeUrlRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let id = Uuid::new_v4();
+    let body = body.into_inner();
+    let Ok(url) = body.url.parse::<Uri>() else {
+        return Ok(HttpResponse::BadRequest()
+            .json(ErrorBody::new("invalid_url", "url must be an absolute URL")));
+    };
+    if let Err(reason) = remote.check_url(&url) {
+        log::info!("[{}] URL rejected: {}", id, reason);
+        return Ok(HttpResponse::Forbidden().json(ErrorBody::new("url_not_allowed", reason)));
+    }
+
+    // The query string is left out, since it may carry credentials such as a presigned signature.
+    log::info!(
+        "[{}] downloading video from {}{}",
+        id,
+        url.host().unwrap_or_default(),
+        url.path()
+    );
+    let file_name = body.file_name.or_else(|| file_name_of(&url));
+    let (file, content_type, size) = match download(&remote, url, config.size_limit()).await {
+        Ok(downloaded) => downloaded,
+        Err(DownloadError::TooLarge) => {
+            log::info!("[{}] download aborted for exceeding the size limit", id);
+            return Ok(upload::payload_too_large(config.size_limit()));
+        }
+        Err(DownloadError::Failed(reason)) => {
+            log::info!("[{}] {}", id, reason);
+            return Ok(HttpResponse::build(StatusCode::BAD_GATEWAY)
+                .json(ErrorBody::new("download_failed", reason)));
+        }
+        Err(DownloadError::Io(e)) => return Err(e.into()),
+    };
+
+    let file = TempFile {
+        file,
+        content_type,
+        file_name,
+        size,
+    };
+    Ok(upload::analyze_video(id, &req, &config, &analyzer, &file, body.metadata, *query).await)
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.app_data(utils::json_config()).service(analyze_url);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_url() {
+        let remote = RemoteUploadConfig::new(".").allowed_host("Videos.Example.com");
+        let check = |url: &str| remote.check_url(&url.parse().unwrap()).is_ok();
+
+        assert!(check("https://videos.example.com/a/video.mp4"));
+        assert!(check("HTTPS://VIDEOS.EXAMPLE.COM/video.mp4"));
+        assert!(!check("http://videos.example.com/video.mp4"));
+        assert!(!check("https://example.com/video.mp4"));
+        assert!(!check("https://videos.example.com.evil.test/video.mp4"));
+        assert!(!check("https://127.0.0.1/video.mp4"));
+        assert!(!check("/video.mp4"));
+
+        // Only the default port of the scheme is allowed, unless another one is given.
+        assert!(check("https://videos.example.com:443/video.mp4"));
+        assert!(!check("https://videos.example.com:22/video.mp4"));
+        assert!(!check("https://videos.example.com:8443/video.mp4"));
+
+        let remote = remote.allowed_schemes(["http", "https"]);
+        assert!(
+            remote
+                .check_url(&"http://videos.example.com/".parse().unwrap())
+                .is_ok()
+        );
+
+        let remote = RemoteUploadConfig::new(".")
+            .allowed_schemes(["http", "https", "ftp"])
+            .allowed_host("storage.example.com:9000")
+            .allowed_host("::1");
+        let check = |url: &str| remote.check_url(&url.parse().unwrap()).is_ok();
+        assert!(check("http://storage.example.com:9000/video.mp4"));
+        assert!(check("https://storage.example.com:9000/video.mp4"));
+        assert!(!check("https://storage.example.com/video.mp4"));
+        // A scheme without a default port needs one given.
+        assert!(check("ftp://storage.example.com:9000/video.mp4"));
+        assert!(!check("ftp://storage.example.com/video.mp4"));
+        assert_eq!(
+            remote.allowed_hosts,
+            [
+                (String::from("storage.example.com"), Some(9000)),
+                (String::from("::1"), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_name_of() {
+        let file_name = |url: &str| file_name_of(&url.parse().unwrap());
+
+        assert_eq!(
+            file_name("https://example.com/a/video.mp4?token=1"),
+            Some(String::from("video.mp4"))
+        );
+        assert_eq!(file_name("https://example.com/a/"), None);
+        assert_eq!(file_name("https://example.com"), None);
+    }
+}
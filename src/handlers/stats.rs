@@ -0,0 +1,92 @@
+use crate::analyzer::VideoAnalyzerBuffer;
+use crate::handlers::utils;
+use actix_web::http::header;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, get};
+
+/// The bearer token `GET /stats` requires. The endpoint is only served if one is registered as app
+/// data, since the backend has no other authentication.
+pub struct StatsToken(String);
+
+impl StatsToken {
+    #[inline]
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+}
+
+/// Responds with the number of the analyses since startup, the success rate and the average and
+/// the 95th percentile durations of the latest analyses, and the current queue depth, as counted
+/// by the analyzer. This is a quick operational snapshot for a backend without a metrics stack.
+#[get("/stats")]
+async fn stats(req: HttpRequest, analyzer: web::Data<VideoAnalyzerBuffer>) -> HttpResponse {
+    let Some(token) = req.app_data::<web::Data<StatsToken>>() else {
+        return HttpResponse::NotFound().finish();
+    };
+    if !utils::has_bearer_token(&req, &token.0) {
+        return utils::unauthorized();
+    }
+    HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .json(analyzer.stats())
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(stats);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::VideoAnalyzer;
+    use actix_web::{App, test as actix_test};
+    use std::path::PathBuf;
+
+    #[actix_web::test]
+    async fn test_stats() {
+        let (_analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        let analyzer_buf = web::Data::new(analyzer_buf);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::clone(&analyzer_buf))
+                .configure(config),
+        )
+        .await;
+        let req = actix_test::TestRequest::get().uri("/stats").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(analyzer_buf)
+                .app_data(web::Data::new(StatsToken::new("s3cr3t")))
+                .configure(config),
+        )
+        .await;
+        for authorization in [None, Some("Bearer wrong"), Some("s3cr3t")] {
+            let mut req = actix_test::TestRequest::get().uri("/stats");
+            if let Some(authorization) = authorization {
+                req = req.insert_header((header::AUTHORIZATION, authorization));
+            }
+            let res = actix_test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), 401, "{:?}", authorization);
+        }
+
+        let req = actix_test::TestRequest::get()
+            .uri("/stats")
+            .insert_header((header::AUTHORIZATION, "Bearer s3cr3t"))
+            .to_request();
+        let body: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "total": 0,
+                "window": 0,
+                "success_rate": null,
+                "average_duration_ms": null,
+                "p95_duration_ms": null,
+                "queue_depth": 0,
+            })
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Serialize, Serializer};
+use std::str::FromStr;
+use time::OffsetDateTime;
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, SchemaFormat, Type};
+use utoipa::openapi::{RefOr, Schema};
+use utoipa::{PartialSchema, ToSchema};
+
+// Makes `OffsetDateTime` serialized to formats that can be parsed by JS Date, differing only in the
+// number of subsecond digits.
+// Reference: https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/Date#date_time_string_format
+time::serde::format_description!(
+    js_format_seconds,
+    OffsetDateTime,
+    "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+);
+time::serde::format_description!(
+    js_format,
+    OffsetDateTime,
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+);
+time::serde::format_description!(
+    js_format_micros,
+    OffsetDateTime,
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:6]Z"
+);
+// Makes `OffsetDateTime` serialized to RFC 3339 with a numeric offset and no subsecond digits, for
+// the consumers rejecting the formats above.
+time::serde::format_description!(
+    rfc3339_offset,
+    OffsetDateTime,
+    "[year]-[month]-[day]T[hour]:[minute]:[second][offset_hour sign:mandatory]:[offset_minute]"
+);
+
+/// The subsecond precision of the timestamps in responses.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    Seconds,
+    #[default]
+    Millis,
+    Micros,
+}
+
+impl TimestampPrecision {
+    pub const NAMES: [&str; 3] = ["seconds", "millis", "micros"];
+}
+
+impl FromStr for TimestampPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seconds" => Ok(Self::Seconds),
+            "millis" => Ok(Self::Millis),
+            "micros" => Ok(Self::Micros),
+            _ => Err(format!(
+                "timestamp precision must be one of: {}",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// The format of the timestamps in responses.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// The format that can be parsed by JS Date, such as `2025-09-22T00:21:22.626Z`, with the
+    /// subsecond digits given by [`TimestampPrecision`].
+    #[default]
+    Js,
+    /// RFC 3339 with a numeric offset and no subsecond digits, such as
+    /// `2025-09-22T00:21:22+00:00`. [`TimestampPrecision`] doesn't apply to it.
+    Rfc3339,
+}
+
+impl TimestampFormat {
+    pub const NAMES: [&str; 2] = ["js", "rfc3339"];
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "js" => Ok(Self::Js),
+            "rfc3339" => Ok(Self::Rfc3339),
+            _ => Err(format!(
+                "timestamp format must be one of: {}",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// A UTC timestamp serialized with the given precision in a format that can be parsed by JS Date,
+/// unless another [`TimestampFormat`] is chosen.
+#[derive(Debug)]
+pub struct Timestamp {
+    time: OffsetDateTime,
+    precision: TimestampPrecision,
+    format: TimestampFormat,
+}
+
+impl Timestamp {
+    /// Creates a [`Timestamp`] of the current time.
+    #[inline]
+    pub fn now(precision: TimestampPrecision) -> Self {
+        Self::at(OffsetDateTime::now_utc(), precision)
+    }
+
+    /// Creates a [`Timestamp`] of `time`, which is converted to UTC.
+    #[inline]
+    pub fn at(time: OffsetDateTime, precision: TimestampPrecision) -> Self {
+        Self {
+            time: time.to_offset(time::UtcOffset::UTC),
+            precision,
+            format: TimestampFormat::default(),
+        }
+    }
+
+    /// Sets the format the timestamp is serialized in.
+    #[inline]
+    pub fn format(mut self, format: TimestampFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use TimestampPrecision::*;
+        if self.format == TimestampFormat::Rfc3339 {
+            return rfc3339_offset::serialize(&self.time, serializer);
+        }
+        match self.precision {
+            Seconds => js_format_seconds::serialize(&self.time, serializer),
+            Millis => js_format::serialize(&self.time, serializer),
+            Micros => js_format_micros::serialize(&self.time, serializer),
+        }
+    }
+}
+
+/// Describes a timestamp as a date-time string, which holds in every format and precision.
+impl PartialSchema for Timestamp {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+            .examples(["2025-09-22T00:21:22.626Z"])
+            .into()
+    }
+}
+
+impl ToSchema for Timestamp {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_serialize_timestamp() {
+        let time = datetime!(2025-09-22 00:21:22.626123456 UTC);
+        let serialize = |precision| {
+            serde_json::to_string(&Timestamp {
+                time,
+                precision,
+                format: TimestampFormat::Js,
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            serialize(TimestampPrecision::Seconds),
+            r#""2025-09-22T00:21:22Z""#
+        );
+        assert_eq!(
+            serialize(TimestampPrecision::Millis),
+            r#""2025-09-22T00:21:22.626Z""#
+        );
+        assert_eq!(
+            serialize(TimestampPrecision::Micros),
+            r#""2025-09-22T00:21:22.626123Z""#
+        );
+    }
+
+    #[test]
+    fn test_serialize_rfc3339_timestamp() {
+        let time = datetime!(2025-09-22 00:21:22.626123456 UTC);
+        let timestamp = Timestamp {
+            time,
+            precision: TimestampPrecision::Micros,
+            format: TimestampFormat::Rfc3339,
+        };
+        let serialized = serde_json::to_value(&timestamp).unwrap();
+
+        assert_eq!(serialized, "2025-09-22T00:21:22+00:00");
+        let parsed = OffsetDateTime::parse(
+            serialized.as_str().unwrap(),
+            &time::format_description::well_known::Rfc3339,
+        )
+        .unwrap();
+        assert_eq!(parsed, time.replace_nanosecond(0).unwrap());
+    }
+}
@@ -0,0 +1,551 @@
+//! Resumable uploads following the core protocol and the creation extension of
+//! [tus 1.0.0](https://tus.io/protocols/resumable-upload).
+//!
+//! An upload is created by `POST /uploads`, whose body is then appended by `PATCH /uploads/{id}`
+//! requests, and `HEAD /uploads/{id}` tells how much has been received so far. The video is
+//! analyzed once the last byte arrives, and the `PATCH` request completing the upload gets the same
+//! response as `POST /upload`.
+
+use crate::analyzer::VideoAnalyzerBuffer;
+use crate::handlers::upload::{
+    self, UploadConfig, UploadFormMetadata, UploadQuery, limit_concurrent_uploads,
+    reject_when_storage_low,
+};
+use crate::handlers::utils::{ErrorBody, Rejection};
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::http::header::{self, HeaderMap, HeaderName};
+use actix_web::middleware::from_fn;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder, head, options, patch, post};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures_util::StreamExt;
+use log;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/// How long an incomplete upload is kept without receiving any data.
+const UPLOAD_EXPIRATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The default maximum number of incomplete uploads.
+const DEFAULT_MAX_UPLOADS: usize = 100;
+
+/// An incomplete resumable upload.
+#[derive(Debug)]
+struct TusUpload {
+    file: NamedTempFile,
+    length: u64,
+    offset: u64,
+    file_name: String,
+    metadata: UploadFormMetadata,
+    updated_at: Instant,
+}
+
+/// An upload shared between the requests addressing it. It's taken out once complete, so the
+/// requests still holding it find it gone.
+type TusEntry = Arc<tokio::sync::Mutex<Option<TusUpload>>>;
+
+/// The incomplete resumable uploads. This should be registered as app data wrapped in
+/// [`actix_web::web::Data`].
+#[derive(Debug)]
+pub struct TusStore {
+    dir: PathBuf,
+    max_uploads: usize,
+    expiration: Duration,
+    uploads: Mutex<HashMap<Uuid, TusEntry>>,
+}
+
+impl TusStore {
+    /// Creates a [`TusStore`] keeping the received data in files under `dir`.
+    #[inline]
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_uploads: DEFAULT_MAX_UPLOADS,
+            expiration: UPLOAD_EXPIRATION,
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the maximum number of incomplete uploads, each of which holds a temporary file until
+    /// it completes or expires. An upload created beyond it is rejected with a 503. It defaults to
+    /// 100.
+    #[inline]
+    pub fn max_uploads(mut self, max_uploads: usize) -> Self {
+        self.max_uploads = max_uploads;
+        self
+    }
+
+    /// Returns the upload `id`, dropping the expired uploads first.
+    fn get(&self, id: Uuid) -> Option<TusEntry> {
+        let mut uploads = self.uploads.lock().unwrap();
+        Self::prune_expired(&mut uploads, self.expiration);
+        uploads.get(&id).cloned()
+    }
+
+    /// Adds `upload` as `id`, dropping the expired uploads first. It isn't added if there are
+    /// already [`Self::max_uploads`] uploads, in which case `false` is returned.
+    fn insert(&self, id: Uuid, upload: TusUpload) -> bool {
+        // The uploads are counted and added under the same lock, so that concurrent requests can't
+        // exceed the maximum together.
+        let mut uploads = self.uploads.lock().unwrap();
+        Self::prune_expired(&mut uploads, self.expiration);
+        if uploads.len() >= self.max_uploads {
+            return false;
+        }
+        uploads.insert(id, Arc::new(tokio::sync::Mutex::new(Some(upload))));
+        true
+    }
+
+    /// Drops the uploads that haven't received data for `expiration`, deleting their files. An
+    /// upload being appended is never dropped. This runs on every request to the uploads, so that
+    /// the abandoned ones don't hold their files until another one is created.
+    fn prune_expired(uploads: &mut HashMap<Uuid, TusEntry>, expiration: Duration) {
+        uploads.retain(|id, upload| {
+            let Ok(upload) = upload.try_lock() else {
+                return true;
+            };
+            let expired = upload
+                .as_ref()
+                .is_none_or(|upload| upload.updated_at.elapsed() > expiration);
+            if expired {
+                log::info!("[{}] dropping expired resumable upload", id);
+            }
+            !expired
+        });
+    }
+}
+
+fn tus_response(mut builder: HttpResponseBuilder) -> HttpResponseBuilder {
+    builder.insert_header((TUS_RESUMABLE, TUS_VERSION));
+    builder
+}
+
+fn tus_error(builder: HttpResponseBuilder, code: &'static str, message: &str) -> HttpResponse {
+    tus_response(builder).json(ErrorBody::new(code, message))
+}
+
+fn parse_header<T: std::str::FromStr>(headers: &HeaderMap, name: HeaderName) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Checks that the client speaks the supported version of the protocol.
+fn check_tus_resumable(req: &HttpRequest) -> Result<(), HttpResponse> {
+    match req.headers().get(TUS_RESUMABLE) {
+        Some(version) if version == TUS_VERSION => Ok(()),
+        _ => Err(tus_response(HttpResponse::PreconditionFailed())
+            .insert_header((TUS_VERSION_HEADER, TUS_VERSION))
+            .json(ErrorBody::new(
+                "unsupported_tus_version",
+                format!("Tus-Resumable must be {}", TUS_VERSION),
+            ))),
+    }
+}
+
+/// Parses the `Upload-Metadata` header, which is a comma-separated list of keys, each optionally
+/// followed by a space and a Base64-encoded value.
+fn parse_upload_metadata(value: &str) -> Option<HashMap<&str, Vec<u8>>> {
+    let mut pairs = HashMap::new();
+    for pair in value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => (key, BASE64.decode(value.trim()).ok()?),
+            None => (pair, Vec::new()),
+        };
+        pairs.insert(key, value);
+    }
+    Some(pairs)
+}
+
+/// Tells the client the capabilities of the server.
+#[options("/uploads")]
+async fn discover(config: web::Data<UploadConfig>) -> HttpResponse {
+    tus_response(HttpResponse::NoContent())
+        .insert_header((TUS_VERSION_HEADER, TUS_VERSION))
+        .insert_header((TUS_EXTENSION, "creation"))
+        .insert_header((TUS_MAX_SIZE, config.size_limit()))
+        .finish()
+}
+
+/// Creates an upload. The `Upload-Metadata` header must contain the `filename` of the video and
+/// the `metadata`, which is the JSON also sent as the `metadata` part of `POST /upload`.
+#[post("/uploads", wrap = "from_fn(reject_when_storage_low)")]
+async fn create_upload(
+    req: HttpRequest,
+    config: web::Data<UploadConfig>,
+    store: web::Data<TusStore>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(res) = check_tus_resumable(&req) {
+        return Ok(res);
+    }
+    let headers = req.headers();
+
+    let Some(length) = parse_header::<u64>(headers, UPLOAD_LENGTH) else {
+        return Ok(tus_error(
+            HttpResponse::BadRequest(),
+            "invalid_upload_length",
+            "Upload-Length must be a non-negative integer",
+        ));
+    };
+    if length > config.size_limit() as u64 {
+        return Ok(tus_error(
+            HttpResponse::PayloadTooLarge(),
+            "upload_too_large",
+            &format!(
+                "the upload exceeds the size limit of {} bytes",
+                config.size_limit()
+            ),
+        ));
+    }
+
+    let upload_metadata = headers
+        .get(UPLOAD_METADATA)
+        .map(|value| value.to_str().ok().and_then(parse_upload_metadata));
+    let upload_metadata = match upload_metadata {
+        Some(Some(upload_metadata)) => upload_metadata,
+        Some(None) => {
+            return Ok(tus_error(
+                HttpResponse::BadRequest(),
+                "malformed_upload_metadata",
+                "Upload-Metadata must be a list of keys and Base64-encoded values",
+            ));
+        }
+        None => HashMap::new(),
+    };
+    let Some(file_name) = upload_metadata
+        .get("filename")
+        .and_then(|name| String::from_utf8(name.clone()).ok())
+        .filter(|name| !name.is_empty())
+    else {
+        return Ok(tus_error(
+            HttpResponse::BadRequest(),
+            Rejection::MissingFileName.code(),
+            "file name is missing",
+        ));
+    };
+    // The metadata is checked before any data is received, so that an upload isn't rejected for
+    // it only after the last byte.
+    let Some(metadata) = upload_metadata.get("metadata") else {
+        return Ok(tus_error(
+            HttpResponse::BadRequest(),
+            "missing_field",
+            "metadata is missing",
+        ));
+    };
+    let metadata = match serde_json::from_slice::<UploadFormMetadata>(metadata) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return Ok(tus_error(
+                HttpResponse::BadRequest(),
+                "malformed_metadata",
+                &format!("metadata is malformed: {}", e),
+            ));
+        }
+    };
+
+    let id = Uuid::new_v4();
+    let upload = TusUpload {
+        file: NamedTempFile::new_in(&store.dir)?,
+        length,
+        offset: 0,
+        file_name: file_name.clone(),
+        metadata,
+        updated_at: Instant::now(),
+    };
+    if !store.insert(id, upload) {
+        log::info!("resumable upload rejected, too many incomplete uploads");
+        return Ok(tus_error(
+            HttpResponse::ServiceUnavailable(),
+            "too_many_uploads",
+            "too many incomplete uploads, please retry later",
+        ));
+    }
+    log::info!(
+        "[{}] resumable upload created: \"{}\", length: {} bytes",
+        id,
+        file_name,
+        length
+    );
+
+    let location = format!("{}/{}", req.path().trim_end_matches('/'), id);
+    Ok(tus_response(HttpResponse::Created())
+        .insert_header((header::LOCATION, location))
+        .finish())
+}
+
+/// Tells how many bytes of the upload have been received.
+#[head("/uploads/{id}")]
+async fn query_upload(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    store: web::Data<TusStore>,
+) -> HttpResponse {
+    if let Err(res) = check_tus_resumable(&req) {
+        return res;
+    }
+    let Some(upload) = store.get(*id) else {
+        return tus_response(HttpResponse::NotFound()).finish();
+    };
+    let upload = upload.lock().await;
+    let Some(upload) = upload.as_ref() else {
+        return tus_response(HttpResponse::NotFound()).finish();
+    };
+    tus_response(HttpResponse::Ok())
+        .insert_header((UPLOAD_OFFSET, upload.offset))
+        .insert_header((UPLOAD_LENGTH, upload.length))
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .finish()
+}
+
+/// Appends the request body to the upload at the offset given by `Upload-Offset`. The video is
+/// analyzed once the upload is complete.
+#[patch(
+    "/uploads/{id}",
+    wrap = "from_fn(reject_when_storage_low)",
+    wrap = "from_fn(limit_concurrent_uploads)"
+)]
+async fn append_upload(
+    req: HttpRequest,
+    id: web::Path<Uuid>,
+    mut payload: web::Payload,
+    config: web::Data<UploadConfig>,
+    store: web::Data<TusStore>,
+    analyzer: web::Data<VideoAnalyzerBuffer>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = id.into_inner();
+    if let Err(res) = check_tus_resumable(&req) {
+        return Ok(res);
+    }
+    if req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|v| v.as_bytes())
+        != Some(OFFSET_OCTET_STREAM.as_bytes())
+    {
+        return Ok(tus_error(
+            HttpResponse::UnsupportedMediaType(),
+            "invalid_content_type",
+            &format!("Content-Type must be {}", OFFSET_OCTET_STREAM),
+        ));
+    }
+    let Some(offset) = parse_header::<u64>(req.headers(), UPLOAD_OFFSET) else {
+        return Ok(tus_error(
+            HttpResponse::BadRequest(),
+            "invalid_upload_offset",
+            "Upload-Offset must be a non-negative integer",
+        ));
+    };
+    let Some(entry) = store.get(id) else {
+        return Ok(tus_response(HttpResponse::NotFound()).finish());
+    };
+    let Ok(mut entry) = entry.try_lock() else {
+        return Ok(tus_error(
+            HttpResponse::Conflict(),
+            "upload_in_progress",
+            "the upload is being appended by another request",
+        ));
+    };
+    let Some(upload) = entry.as_mut() else {
+        return Ok(tus_response(HttpResponse::NotFound()).finish());
+    };
+    if offset != upload.offset {
+        return Ok(tus_error(
+            HttpResponse::Conflict(),
+            "offset_mismatch",
+            &format!("Upload-Offset must be {}", upload.offset),
+        ));
+    }
+
+    // Appends the received chunks one by one, so the bytes received before a broken connection are
+    // kept and the client can resume from there.
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(upload.file.path())
+        .await?;
+    let mut result = Ok(());
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                result = Err(actix_web::Error::from(e));
+                break;
+            }
+        };
+        if upload.offset + chunk.len() as u64 > upload.length {
+            result = Err(actix_web::Error::from(io::Error::other(
+                "the upload exceeds its declared length",
+            )));
+            break;
+        }
+        if let Err(e) = file.write_all(&chunk).await {
+            result = Err(e.into());
+            break;
+        }
+        upload.offset += chunk.len() as u64;
+    }
+    file.flush().await?;
+    upload.updated_at = Instant::now();
+    log::debug!(
+        "[{}] resumable upload appended, offset: {} / {} bytes",
+        id,
+        upload.offset,
+        upload.length
+    );
+    if let Err(e) = result {
+        log::info!("[{}] appending resumable upload interrupted: {}", id, e);
+        return Ok(tus_response(HttpResponse::BadRequest())
+            .insert_header((UPLOAD_OFFSET, upload.offset))
+            .json(ErrorBody::new("append_interrupted", e.to_string())));
+    }
+    if upload.offset < upload.length {
+        return Ok(tus_response(HttpResponse::NoContent())
+            .insert_header((UPLOAD_OFFSET, upload.offset))
+            .finish());
+    }
+
+    // The upload is complete, so it's no longer resumable and is analyzed like `POST /upload`.
+    store.uploads.lock().unwrap().remove(&id);
+    let upload = entry.take().unwrap();
+    drop(entry);
+    let file = TempFile {
+        file: upload.file,
+        content_type: None,
+        file_name: Some(upload.file_name),
+        size: upload.length as usize,
+    };
+    let query = UploadQuery::default();
+    let mut res =
+        upload::analyze_video(id, &req, &config, &analyzer, &file, upload.metadata, query).await;
+    res.headers_mut()
+        .insert(TUS_RESUMABLE, header::HeaderValue::from_static(TUS_VERSION));
+    res.headers_mut()
+        .insert(UPLOAD_OFFSET, upload.length.into());
+    Ok(res)
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(discover)
+        .service(create_upload)
+        .service(query_upload)
+        .service(append_upload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, test as actix_test};
+
+    #[test]
+    fn test_parse_upload_metadata() {
+        let metadata = parse_upload_metadata(
+            "filename dmlkZW8ubXA0, metadata eyJtb2RlIjoxfQ==,is_confidential",
+        )
+        .unwrap();
+        assert_eq!(metadata["filename"], b"video.mp4");
+        assert_eq!(metadata["metadata"], br#"{"mode":1}"#);
+        assert_eq!(metadata["is_confidential"], b"");
+
+        assert!(parse_upload_metadata("filename !!!").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_max_uploads() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let store = TusStore::new(tmp_dir.path().to_path_buf()).max_uploads(1);
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(UploadConfig::new(1024)))
+                .app_data(web::Data::new(store))
+                .configure(config),
+        )
+        .await;
+        let create = || {
+            actix_test::TestRequest::post()
+                .uri("/uploads")
+                .insert_header((TUS_RESUMABLE, TUS_VERSION))
+                .insert_header((UPLOAD_LENGTH, 16))
+                .insert_header((
+                    UPLOAD_METADATA,
+                    "filename dmlkZW8ubXA0,metadata eyJtb2RlIjoxfQ==",
+                ))
+                .to_request()
+        };
+
+        let res = actix_test::call_service(&app, create()).await;
+        assert_eq!(res.status(), 201);
+        let res = actix_test::call_service(&app, create()).await;
+        assert_eq!(res.status(), 503);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "too_many_uploads");
+    }
+
+    fn upload(dir: &std::path::Path, updated_at: Instant) -> TusUpload {
+        TusUpload {
+            file: NamedTempFile::new_in(dir).unwrap(),
+            length: 16,
+            offset: 0,
+            file_name: String::from("video.mp4"),
+            metadata: serde_json::from_str(r#"{"mode":1}"#).unwrap(),
+            updated_at,
+        }
+    }
+
+    #[test]
+    fn test_insert_concurrently() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let store = TusStore::new(tmp_dir.path().to_path_buf()).max_uploads(4);
+        let inserted = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..16)
+                .map(|_| {
+                    scope.spawn(|| {
+                        store.insert(Uuid::new_v4(), upload(tmp_dir.path(), Instant::now()))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|&inserted| inserted)
+                .count()
+        });
+        assert_eq!(inserted, 4);
+        assert_eq!(store.uploads.lock().unwrap().len(), 4);
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn test_prune_expired() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut store = TusStore::new(tmp_dir.path().to_path_buf());
+        store.expiration = Duration::from_secs(10);
+        let expired = Uuid::new_v4();
+        let updated_at = Instant::now() - Duration::from_secs(60);
+        assert!(store.insert(expired, upload(tmp_dir.path(), updated_at)));
+        let fresh = Uuid::new_v4();
+        assert!(store.insert(fresh, upload(tmp_dir.path(), Instant::now())));
+
+        // Looking up any upload drops the expired ones along with their files.
+        assert!(store.get(fresh).is_some());
+        assert!(store.get(expired).is_none());
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+    }
+}
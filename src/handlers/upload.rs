@@ -1,123 +1,1940 @@
 use crate::analyzer::task::TaskConfig;
 use crate::analyzer::{
-    VideoAnalyzerBuffer, VideoAnalyzerMode, VideoAnalyzerModeDesc, VideoAnalyzerOutput,
+    AnalysisQuality, AnalysisTiming, GroupedVideoAnalyzerOutput, KeyedId,
+    NestedVideoAnalyzerOutput, ResourceExceeded, VideoAnalyzerBuffer, VideoAnalyzerMode,
+    VideoAnalyzerModeDesc, VideoAnalyzerOutput, VideoAnalyzerReport, VideoAnalyzerSummary,
 };
-use crate::handlers::utils;
-use actix_multipart::form::{MultipartForm, json::Json as MpJson, tempfile::TempFile};
-use actix_web::error::Error;
+use crate::handlers::audit::AuditRecord;
+use crate::handlers::idempotency::deduplicate_uploads;
+use crate::handlers::timestamp::{Timestamp, TimestampFormat, TimestampPrecision};
+use crate::handlers::utils::{
+    self, DurationLimits, ErrorBody, NonVideoContentType, Rejection, VideoFile, VideoValidation,
+};
+use actix_multipart::form::json::{Json as MpJson, JsonFieldError};
+use actix_multipart::form::{
+    FieldReader, Limits, MultipartForm, MultipartFormConfig, tempfile::TempFile,
+};
+use actix_multipart::{Field, MultipartError};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Decompress, Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::{Error, InternalError, PayloadError};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, ContentEncoding, HeaderName, HeaderValue};
+use actix_web::middleware::{Next, from_fn};
 use actix_web::web::{self, ServiceConfig};
-use actix_web::{HttpResponse, Responder, post};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, ResponseError, post};
+use futures_util::Stream;
+use futures_util::future::LocalBoxFuture;
 use log;
 use mime;
-use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use time::OffsetDateTime;
+use tokio::sync::Semaphore;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+/// The response header carrying the correlation ID of an upload request. The same ID prefixes the
+/// log records of the request and its analysis task.
+pub(super) const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// The response header telling how many analysis tasks are queued or running when an upload is
+/// answered, so that clients can throttle themselves.
+const QUEUE_DEPTH_HEADER: HeaderName = HeaderName::from_static("x-queue-depth");
+
+/// The response header telling how many seconds a task queued now would wait before its analysis
+/// starts, estimated from the recent analyses. It's absent until an analysis has completed.
+const ESTIMATED_WAIT_HEADER: HeaderName = HeaderName::from_static("x-estimated-wait");
+
+/// The content type of MessagePack, which isn't registered with IANA. `application/x-msgpack` is
+/// also accepted.
+const MSGPACK: &str = "application/msgpack";
+
+/// The highest frame sampling rate `fps_sample` may request, which is beyond the frame rate of
+/// virtually every video.
+const MAX_FPS_SAMPLE: f64 = 120.0;
+
+/// The default size limit of the `metadata` part of an upload in bytes.
+const DEFAULT_METADATA_LIMIT: usize = 4 * 1024;
+
+/// Configuration of the `/upload` endpoint. This should be registered as app data wrapped in
+/// [`actix_web::web::Data`], along with the [`MultipartFormConfig`] derived from it.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    size_limit: usize,
+    timestamp_precision: TimestampPrecision,
+    timestamp_format: TimestampFormat,
+    /// The maximum number of uploads being handled at the same time, and their slots.
+    upload_slots: Option<(usize, Arc<Semaphore>)>,
+    ffprobe_bin: PathBuf,
+    duration_limits: DurationLimits,
+    min_free_space: Option<(PathBuf, u64)>,
+    /// The bearer token `?raw=true` requires, if the raw output is available.
+    raw_output_token: Option<String>,
+    max_suggestions: Option<usize>,
+    metadata_limit: usize,
+    max_request_duration: Option<Duration>,
+    /// The names of the inference models the uploads may select, besides the default one.
+    models: Vec<String>,
+}
+
+impl UploadConfig {
+    /// Creates an [`UploadConfig`] accepting uploads of at most `size_limit` bytes.
+    #[inline]
+    pub fn new(size_limit: usize) -> Self {
+        Self {
+            size_limit,
+            timestamp_precision: TimestampPrecision::default(),
+            timestamp_format: TimestampFormat::default(),
+            upload_slots: None,
+            ffprobe_bin: PathBuf::from("ffprobe"),
+            duration_limits: DurationLimits::default(),
+            min_free_space: None,
+            raw_output_token: None,
+            max_suggestions: None,
+            metadata_limit: DEFAULT_METADATA_LIMIT,
+            max_request_duration: None,
+            models: Vec::new(),
+        }
+    }
+
+    /// Limits the number of uploads being handled at the same time to `max_concurrent_uploads`.
+    /// An upload is counted from the moment it is accepted until its response is produced, and
+    /// the excess ones are rejected with a 503 before their bodies are read. There is no limit by
+    /// default.
+    #[inline]
+    pub fn max_concurrent_uploads(mut self, max_concurrent_uploads: usize) -> Self {
+        self.upload_slots = Some((
+            max_concurrent_uploads,
+            Arc::new(Semaphore::new(max_concurrent_uploads)),
+        ));
+        self
+    }
+
+    /// Limits the time an upload may take from the moment it is accepted, through receiving the
+    /// body, waiting in the queue and the analysis, until its response is produced, to
+    /// `max_request_duration`. An upload taking longer is answered with a 504, and its analysis
+    /// is cancelled unless it has already started. There is no limit by default.
+    #[inline]
+    pub fn max_request_duration(mut self, max_request_duration: Duration) -> Self {
+        self.max_request_duration = Some(max_request_duration);
+        self
+    }
+
+    /// Rejects uploads with a 507 while the volume containing `dir`, which should be where the
+    /// uploads are stored, has less than `min_free_space` bytes available. The free space is
+    /// checked before the body is read. There is no threshold by default.
+    #[inline]
+    pub fn min_free_space(mut self, dir: impl Into<PathBuf>, min_free_space: u64) -> Self {
+        self.min_free_space = Some((dir.into(), min_free_space));
+        self
+    }
+
+    /// Sets the `ffprobe` executable probing the uploaded videos for their durations and frame
+    /// rates. It defaults to `ffprobe`, which is looked up in `PATH`.
+    #[inline]
+    pub fn ffprobe_bin(mut self, ffprobe_bin: impl Into<PathBuf>) -> Self {
+        self.ffprobe_bin = ffprobe_bin.into();
+        self
+    }
+
+    /// Rejects the videos lasting less than `min_duration_sec` seconds with a 422. There is no
+    /// limit by default. The duration is detected by `ffprobe`, so this has no effect if `ffprobe`
+    /// isn't installed.
+    #[inline]
+    pub fn min_duration_sec(mut self, min_duration_sec: f64) -> Self {
+        self.duration_limits.min_sec = Some(min_duration_sec);
+        self
+    }
+
+    /// Rejects the videos lasting more than `max_duration_sec` seconds with a 422, before they are
+    /// queued for analysis. There is no limit by default. The duration is detected by `ffprobe`, so
+    /// this has no effect if `ffprobe` isn't installed.
+    #[inline]
+    pub fn max_duration_sec(mut self, max_duration_sec: f64) -> Self {
+        self.duration_limits.max_sec = Some(max_duration_sec);
+        self
+    }
+
+    /// Sets the subsecond precision of `analyze_time` in the responses. It defaults to
+    /// milliseconds.
+    #[inline]
+    pub fn timestamp_precision(mut self, timestamp_precision: TimestampPrecision) -> Self {
+        self.timestamp_precision = timestamp_precision;
+        self
+    }
+
+    /// Limits the number of suggestions in a response to `max_suggestions`. The excess ones, which
+    /// are the latest in the video, are dropped after the duplicates are removed, and the response
+    /// is marked as `truncated`. There is no limit by default.
+    #[inline]
+    pub fn max_suggestions(mut self, max_suggestions: usize) -> Self {
+        self.max_suggestions = Some(max_suggestions);
+        self
+    }
+
+    /// Limits the size of the `metadata` part of an upload to `metadata_limit` bytes. A larger one
+    /// is answered with a 400 as soon as it exceeds the limit. It defaults to 4 KiB, which is
+    /// plenty for the fields the metadata can have.
+    #[inline]
+    pub fn metadata_limit(mut self, metadata_limit: usize) -> Self {
+        self.metadata_limit = metadata_limit;
+        self
+    }
+
+    /// Allows the requests carrying the bearer token `token` to ask for the output of the
+    /// inference procedure as it was written, before the suggestions are converted and filtered,
+    /// with `?raw=true`. This exposes internal details of the inference procedure, so such requests
+    /// are answered with a 403 unless a token is set, and with a 401 without the token.
+    #[inline]
+    pub fn raw_output_token(mut self, token: impl Into<String>) -> Self {
+        self.raw_output_token = Some(token.into());
+        self
+    }
+
+    /// Allows the uploads to select the inference model registered under `name` with
+    /// [`VideoAnalyzer::model`](crate::analyzer::VideoAnalyzer::model), by giving it as `model` in
+    /// the metadata. An upload selecting another model is answered with a 400, and the ones
+    /// selecting none are analyzed by the default model.
+    #[inline]
+    pub fn allowed_model(mut self, name: impl Into<String>) -> Self {
+        self.models.push(name.into());
+        self
+    }
+
+    /// Sets the format of `analyze_time` in the responses. It defaults to [`TimestampFormat::Js`].
+    #[inline]
+    pub fn timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    /// Returns the maximum size of an upload in bytes.
+    #[inline]
+    pub(super) fn size_limit(&self) -> usize {
+        self.size_limit
+    }
+
+    /// Returns the `ffprobe` executable probing the uploaded videos.
+    #[inline]
+    pub(super) fn ffprobe_path(&self) -> &Path {
+        &self.ffprobe_bin
+    }
+
+    /// Returns the range of video durations accepted for analysis.
+    #[inline]
+    pub(super) fn duration_limits(&self) -> &DurationLimits {
+        &self.duration_limits
+    }
+
+    /// Returns the maximum number of uploads being handled at the same time, and how many more
+    /// could be accepted now, if there is a limit.
+    #[inline]
+    pub(super) fn upload_slots(&self) -> Option<(usize, usize)> {
+        self.upload_slots
+            .as_ref()
+            .map(|(max, slots)| (*max, slots.available_permits()))
+    }
+
+    /// Returns whether the storage for uploads has less free space than
+    /// [`Self::min_free_space`] requires. It isn't considered low if the free space can't be
+    /// queried, since an upload may well succeed then.
+    pub(super) fn is_storage_low(&self) -> bool {
+        let Some((dir, min_free_space)) = &self.min_free_space else {
+            return false;
+        };
+        match fs2::available_space(dir) {
+            Ok(free_space) if free_space < *min_free_space => {
+                log::warn!(
+                    "free space of {} is {} bytes, below the threshold of {} bytes",
+                    dir.display(),
+                    free_space,
+                    min_free_space
+                );
+                true
+            }
+            Ok(free_space) => {
+                log::debug!("free space of {}: {} bytes", dir.display(), free_space);
+                false
+            }
+            Err(e) => {
+                log::warn!("failed to query free space of {}: {}", dir.display(), e);
+                false
+            }
+        }
+    }
+
+    /// Returns a [`Timestamp`] of `time` in the precision and the format for the responses.
+    #[inline]
+    fn timestamp(&self, time: OffsetDateTime) -> Timestamp {
+        Timestamp::at(time, self.timestamp_precision).format(self.timestamp_format)
+    }
+
+    /// Returns the [`MultipartFormConfig`] enforcing this configuration while the upload is being
+    /// streamed to disk.
+    ///
+    /// The size limit is checked against every received chunk, so an upload is aborted as soon as
+    /// it exceeds the limit, and the partially written temporary file is deleted right away. The
+    /// client gets a 413 in that case.
+    ///
+    /// A missing, duplicate or unknown form field, or a malformed or oversized `metadata` field is
+    /// answered with a 400 carrying an [`ErrorBody`] that names the problem, and a file part
+    /// declared to be of a non-video type is answered with a 415 (see [`VideoFile`]).
+    pub fn multipart_form_config(&self) -> MultipartFormConfig {
+        let size_limit = self.size_limit;
+        MultipartFormConfig::default()
+            .total_limit(size_limit)
+            // The metadata is the only part held in memory.
+            .memory_limit(self.metadata_limit)
+            .error_handler(move |err, _req: &HttpRequest| {
+                let res = match &err {
+                    MultipartError::Payload(PayloadError::Overflow) => {
+                        log::info!("upload aborted for exceeding the size limit");
+                        payload_too_large(size_limit)
+                    }
+                    MultipartError::MissingField(name) => HttpResponse::BadRequest().json(
+                        ErrorBody::new("missing_field", format!("{} is missing", name)),
+                    ),
+                    MultipartError::DuplicateField(name) => HttpResponse::BadRequest().json(
+                        ErrorBody::new("duplicate_field", format!("{} is given twice", name)),
+                    ),
+                    MultipartError::UnknownField(name) => {
+                        HttpResponse::BadRequest().json(ErrorBody::new(
+                            "unknown_field",
+                            format!("{} is not a field of the form", name),
+                        ))
+                    }
+                    MultipartError::Field { source, .. }
+                        if let Some(e) = source.as_error::<MetadataTooLarge>() =>
+                    {
+                        log::info!("upload aborted for {}", e);
+                        HttpResponse::BadRequest()
+                            .json(ErrorBody::new("metadata_too_large", e.to_string()))
+                    }
+                    MultipartError::Field { name, source } if name == "metadata" => {
+                        HttpResponse::BadRequest().json(ErrorBody::new(
+                            "malformed_metadata",
+                            metadata_error_message(source),
+                        ))
+                    }
+                    MultipartError::Field { source, .. }
+                        if let Some(e) = source.as_error::<NonVideoContentType>() =>
+                    {
+                        log::info!("upload rejected before reading the file: {}", e);
+                        HttpResponse::UnsupportedMediaType().json(ErrorBody::new(
+                            Rejection::UnsupportedFormat.code(),
+                            e.to_string(),
+                        ))
+                    }
+                    _ => return err.into(),
+                };
+                InternalError::from_response(err, res).into()
+            })
+    }
+}
+
+fn metadata_error_message(err: &Error) -> String {
+    match err.as_error::<JsonFieldError>() {
+        Some(JsonFieldError::Deserialize(e)) => format!("metadata is malformed: {}", e),
+        Some(JsonFieldError::ContentType) => {
+            String::from("metadata must be of type application/json")
+        }
+        _ => format!("metadata is malformed: {}", err),
+    }
+}
+
+pub(super) fn payload_too_large(size_limit: usize) -> HttpResponse {
+    HttpResponse::PayloadTooLarge().body(format!(
+        "the upload exceeds the size limit of {} bytes",
+        size_limit
+    ))
+}
+
+/// Rejects an upload upfront if its declared `Content-Length` already exceeds the size limit, so
+/// the client doesn't have to stream the whole body before being cut off.
+pub(super) async fn reject_oversized_upload(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let size_limit = req
+        .app_data::<web::Data<UploadConfig>>()
+        .map(|config| config.size_limit);
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    if let (Some(size_limit), Some(content_length)) = (size_limit, content_length)
+        && content_length > size_limit
+    {
+        log::info!(
+            "upload rejected upfront, declared content length: {} bytes",
+            content_length
+        );
+        let res = payload_too_large(size_limit);
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Decompresses the body of an upload sent with `Content-Encoding: gzip` before the multipart form
+/// is parsed. The size limit applies to the decompressed body, since it's what the form counts, so
+/// a small body inflating into a huge one is aborted with a 413 as usual. Any other encoding is
+/// rejected with a 415 before the body is read.
+pub(super) async fn decompress_upload(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let encoding = req.headers().get(header::CONTENT_ENCODING).map(|value| {
+        value
+            .to_str()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    });
+    match encoding.as_deref() {
+        None | Some("identity") => {}
+        Some("gzip") => {
+            let payload = Decompress::new(req.take_payload(), ContentEncoding::Gzip);
+            req.set_payload(Payload::from(
+                Box::pin(payload) as Pin<Box<dyn Stream<Item = _>>>
+            ));
+        }
+        Some(encoding) => {
+            log::info!(
+                "upload rejected, unsupported content encoding: {}",
+                encoding
+            );
+            let res = HttpResponse::UnsupportedMediaType().json(ErrorBody::new(
+                "unsupported_content_encoding",
+                "the upload must be sent as it is, or compressed with gzip",
+            ));
+            return Ok(req.into_response(res).map_into_right_body());
+        }
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Rejects an upload with a 503 if there are already as many uploads being handled as allowed by
+/// [`UploadConfig::max_concurrent_uploads`]. This happens before the body is read, so the rejected
+/// upload doesn't consume any temporary space.
+pub(super) async fn limit_concurrent_uploads(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let upload_slots = req
+        .app_data::<web::Data<UploadConfig>>()
+        .and_then(|config| config.upload_slots.as_ref().map(|(_, slots)| slots.clone()));
+    let Some(upload_slots) = upload_slots else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    // The permit is held until the response is produced.
+    let Ok(_permit) = upload_slots.try_acquire_owned() else {
+        log::info!("upload rejected, too many uploads in progress");
+        let res = HttpResponse::ServiceUnavailable().json(ErrorBody::new(
+            "too_many_uploads",
+            "too many uploads in progress, please retry later",
+        ));
+        return Ok(req.into_response(res).map_into_right_body());
+    };
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Answers an upload with a 504 once it has taken [`UploadConfig::max_request_duration`], wherever
+/// it's stuck. The handler is dropped then, which drops the handles of its analysis tasks, so that
+/// the analyzer skips the ones still queued.
+pub(super) async fn limit_request_duration(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    #[derive(Serialize)]
+    struct TimeoutBody {
+        #[serde(flatten)]
+        error: ErrorBody,
+        elapsed_ms: u64,
+    }
+
+    let max_request_duration = req
+        .app_data::<web::Data<UploadConfig>>()
+        .and_then(|config| config.max_request_duration);
+    let Some(max_request_duration) = max_request_duration else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
 
-// Makes `OffsetDateTime` serialized to a format that can be parsed by JS Date.
-// Reference: https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/Date#date_time_string_format
-time::serde::format_description!(
-    js_format,
-    OffsetDateTime,
-    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
-);
+    let started_at = Instant::now();
+    let http_req = req.request().clone();
+    match actix_web::rt::time::timeout(max_request_duration, next.call(req)).await {
+        Ok(res) => res.map(ServiceResponse::map_into_left_body),
+        Err(_) => {
+            let elapsed = started_at.elapsed();
+            log::warn!(
+                "upload timed out after {} ms, exceeding the limit of {} ms",
+                elapsed.as_millis(),
+                max_request_duration.as_millis()
+            );
+            let res = HttpResponse::GatewayTimeout().json(TimeoutBody {
+                error: ErrorBody::new(
+                    "request_timeout",
+                    format!(
+                        "the upload took longer than {} seconds",
+                        max_request_duration.as_secs_f64()
+                    ),
+                ),
+                elapsed_ms: elapsed.as_millis() as u64,
+            });
+            Ok(ServiceResponse::new(http_req, res).map_into_right_body())
+        }
+    }
+}
 
-const SUPPORTED_VIDEO_FORMATS: [&str; 3] = ["mp4", "avi", "mov"];
+/// Rejects an upload with a 507 if the storage for uploads is running out of space, as set by
+/// [`UploadConfig::min_free_space`]. This happens before the body is read, so the upload doesn't
+/// fail halfway.
+pub(super) async fn reject_when_storage_low(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let storage_low = req
+        .app_data::<web::Data<UploadConfig>>()
+        .is_some_and(|config| config.is_storage_low());
+    if storage_low {
+        log::warn!("upload rejected, the storage is running out of space");
+        let res = HttpResponse::InsufficientStorage().json(ErrorBody::new(
+            "insufficient_storage",
+            "the server is running out of storage, please retry later",
+        ));
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
 
 #[derive(Debug, Deserialize)]
-struct UploadFormMetadata {
+#[serde(try_from = "RawUploadFormMetadata")]
+pub(super) struct UploadFormMetadata {
+    /// The mode to analyze the video in, which is the first of `modes` if they are given.
     mode: VideoAnalyzerMode,
+    /// The modes to analyze the video in, if given as `modes` instead of `mode`. The response then
+    /// carries the results of each mode separately.
+    modes: Option<Vec<VideoAnalyzerMode>>,
+    start_sec: Option<u32>,
+    end_sec: Option<u32>,
+    /// The number of frames per second the inference procedure samples, which it chooses if not
+    /// given.
+    fps_sample: Option<f64>,
+    /// The name of the inference model to run instead of the default one.
+    model: Option<String>,
+    quality: AnalysisQuality,
+    response_shape: ResponseShape,
+}
+
+/// [`UploadFormMetadata`] as it is written, where either `mode` or `modes` must be given.
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(
+    as = UploadFormMetadata,
+    description = "The `metadata` part of an upload, where either `mode` or `modes` must be given."
+)]
+struct RawUploadFormMetadata {
+    mode: Option<VideoAnalyzerMode>,
+    modes: Option<Vec<VideoAnalyzerMode>>,
+    start_sec: Option<u32>,
+    end_sec: Option<u32>,
+    #[schema(exclusive_minimum = 0.0, maximum = 120.0)]
+    fps_sample: Option<f64>,
+    model: Option<String>,
+    #[serde(default)]
+    quality: AnalysisQuality,
+    #[serde(default)]
+    response_shape: ResponseShape,
+}
+
+impl TryFrom<RawUploadFormMetadata> for UploadFormMetadata {
+    type Error = &'static str;
+
+    fn try_from(raw: RawUploadFormMetadata) -> Result<Self, Self::Error> {
+        let (mode, modes) = match (raw.mode, raw.modes) {
+            (Some(_), Some(_)) => return Err("mode and modes can't be given together"),
+            (Some(mode), None) => (mode, None),
+            (None, Some(modes)) => {
+                let Some(&mode) = modes.first() else {
+                    return Err("modes must not be empty");
+                };
+                if modes
+                    .iter()
+                    .enumerate()
+                    .any(|(i, mode)| modes[..i].contains(mode))
+                {
+                    return Err("modes must not repeat a mode");
+                }
+                (mode, Some(modes))
+            }
+            // The same message as the one for a missing field, since `mode` is the usual field.
+            (None, None) => return Err("missing field `mode`"),
+        };
+        if let Some(fps_sample) = raw.fps_sample
+            && !(fps_sample > 0.0 && fps_sample <= MAX_FPS_SAMPLE)
+        {
+            return Err("fps_sample must be greater than 0 and at most 120");
+        }
+        Ok(Self {
+            mode,
+            modes,
+            start_sec: raw.start_sec,
+            end_sec: raw.end_sec,
+            fps_sample: raw.fps_sample,
+            model: raw.model,
+            quality: raw.quality,
+            response_shape: raw.response_shape,
+        })
+    }
+}
+
+impl UploadFormMetadata {
+    /// Returns the modes to analyze the video in, in the requested order.
+    #[inline]
+    fn modes(&self) -> &[VideoAnalyzerMode] {
+        self.modes
+            .as_deref()
+            .unwrap_or(std::slice::from_ref(&self.mode))
+    }
+
+    /// Checks that the requested time range is not empty and fits within the video, whose
+    /// duration is `duration_sec` seconds if known.
+    fn check_time_range(&self, duration_sec: Option<f64>) -> Result<(), String> {
+        if let (Some(start_sec), Some(end_sec)) = (self.start_sec, self.end_sec)
+            && start_sec >= end_sec
+        {
+            return Err(String::from("start_sec must be less than end_sec"));
+        }
+        if let Some(duration_sec) = duration_sec {
+            if let Some(start_sec) = self.start_sec
+                && f64::from(start_sec) >= duration_sec
+            {
+                return Err(format!(
+                    "start_sec must be less than the video duration ({:.3} seconds)",
+                    duration_sec
+                ));
+            }
+            if let Some(end_sec) = self.end_sec
+                && f64::from(end_sec) > duration_sec
+            {
+                return Err(format!(
+                    "end_sec must not exceed the video duration ({:.3} seconds)",
+                    duration_sec
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, MultipartForm)]
+/// The query string of `/upload`.
+#[derive(Debug, Default, Copy, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct UploadQuery {
+    /// Whether to include the output of the inference procedure as it was written.
+    #[serde(default)]
+    pub(super) raw: bool,
+    /// The unit of the boundaries of the suggestions in the response.
+    #[serde(default)]
+    pub(super) unit: TimeUnit,
+    /// Whether to partition the timeline into non-overlapping suggestions, where the meme type
+    /// with the lowest numeric value wins wherever suggestions overlap.
+    #[serde(default)]
+    pub(super) flatten: bool,
+    /// How to group the suggestions in the response, which are a single list by default.
+    #[serde(default)]
+    pub(super) group_by: Option<GroupBy>,
+}
+
+/// How the suggestions in the response are grouped, requested with `?group_by=`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum GroupBy {
+    /// A list of suggestions per meme type, keyed by `meme_type_desc`.
+    Type,
+}
+
+/// The unit of the boundaries of the suggestions in the response, requested with `?unit=`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum TimeUnit {
+    /// Seconds, which the inference procedure reports the boundaries in.
+    #[default]
+    S,
+    /// Milliseconds, for clients that seek a player in milliseconds.
+    Ms,
+}
+
+impl TimeUnit {
+    /// Returns the number of this unit in a second.
+    #[inline]
+    fn per_second(self) -> u32 {
+        match self {
+            Self::S => 1,
+            Self::Ms => 1000,
+        }
+    }
+}
+
+/// The form of an upload, which consists of exactly one `file` part and one `metadata` part. A
+/// duplicate or unknown part is rejected before the rest of the body is read.
+#[derive(Debug, MultipartForm, ToSchema)]
+#[multipart(deny_unknown_fields, duplicate_field = "deny")]
 struct UploadForm {
-    file: TempFile,
-    metadata: MpJson<UploadFormMetadata>,
+    /// The video or the still image, whose file name must have a supported extension.
+    #[schema(value_type = String, format = Binary)]
+    file: VideoFile,
+    #[schema(value_type = RawUploadFormMetadata)]
+    metadata: LimitedJson<UploadFormMetadata>,
+}
+
+/// The error of a `metadata` part exceeding [`UploadConfig::metadata_limit`].
+#[derive(Debug)]
+struct MetadataTooLarge(usize);
+
+impl fmt::Display for MetadataTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "metadata exceeds the size limit of {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for MetadataTooLarge {}
+
+impl ResponseError for MetadataTooLarge {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+/// A JSON part of a multipart form, which is an [`MpJson`] failing with [`MetadataTooLarge`] when
+/// it exceeds the metadata limit of the [`UploadConfig`] registered as app data.
+///
+/// The multipart form reports every exceeded limit as the same overflow error, which can't tell an
+/// oversized metadata part from an oversized upload. So the remaining total limit is lowered to
+/// the metadata limit while the part is read, and an overflow is attributed to the metadata if
+/// its limit is the lower one.
+#[derive(Debug)]
+#[repr(transparent)]
+struct LimitedJson<T: DeserializeOwned>(MpJson<T>);
+
+impl<'t, T: DeserializeOwned + 'static> FieldReader<'t> for LimitedJson<T> {
+    type Future = LocalBoxFuture<'t, Result<Self, MultipartError>>;
+
+    fn read_field(req: &'t HttpRequest, field: Field, limits: &'t mut Limits) -> Self::Future {
+        Box::pin(async move {
+            let Some(metadata_limit) = req
+                .app_data::<web::Data<UploadConfig>>()
+                .map(|config| config.metadata_limit)
+                .filter(|&metadata_limit| metadata_limit < limits.total_limit_remaining)
+            else {
+                return MpJson::read_field(req, field, limits)
+                    .await
+                    .map(LimitedJson);
+            };
+
+            let name = String::from(field.name().unwrap_or_default());
+            let total_limit_remaining = limits.total_limit_remaining;
+            limits.total_limit_remaining = metadata_limit;
+            let res = MpJson::read_field(req, field, limits).await;
+            let consumed = metadata_limit - limits.total_limit_remaining;
+            limits.total_limit_remaining = total_limit_remaining - consumed;
+            match res {
+                Err(MultipartError::Payload(PayloadError::Overflow)) => {
+                    Err(MultipartError::Field {
+                        name,
+                        source: MetadataTooLarge(metadata_limit).into(),
+                    })
+                }
+                res => res.map(LimitedJson),
+            }
+        })
+    }
+}
+
+/// The shape of the analysis results in an [`UploadResponse`], chosen by `response_shape` of the
+/// upload metadata.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum ResponseShape {
+    /// Enum values are serialized as parallel number and string fields, such as `meme_type` and
+    /// `meme_type_desc`.
+    #[default]
+    Flat,
+    /// Enum values are serialized as single objects in the form of `{"id": 0, "key": "happiness"}`.
+    Nested,
+}
+
+/// The analysis results in an [`UploadResponse`], in either of the [`ResponseShape`]s.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+enum AnalysisResults {
+    Flat {
+        analyze_mode: VideoAnalyzerModeDesc,
+        suggestions: VideoAnalyzerOutput,
+    },
+    Nested {
+        analyze_mode: KeyedId,
+        suggestions: NestedVideoAnalyzerOutput,
+    },
+    /// The flat shape with the suggestions grouped by `?group_by=type`.
+    FlatGrouped {
+        analyze_mode: VideoAnalyzerModeDesc,
+        suggestions: GroupedVideoAnalyzerOutput,
+    },
+    /// The nested shape with the suggestions grouped by `?group_by=type`.
+    NestedGrouped {
+        analyze_mode: KeyedId,
+        suggestions: GroupedVideoAnalyzerOutput,
+    },
+}
+
+/// When an analysis ran, and how long it waited for the analyzer.
+#[derive(Debug, Serialize, ToSchema)]
+struct AnalysisTimes {
+    analysis_started_at: Timestamp,
+    analysis_finished_at: Timestamp,
+    /// How long the task waited in the queue of the analyzer, in milliseconds.
+    queue_wait_ms: u64,
+    /// How long the analysis took, in milliseconds.
+    inference_ms: u64,
+}
+
+impl AnalysisTimes {
+    fn new(config: &UploadConfig, timing: &AnalysisTiming) -> Self {
+        Self {
+            analysis_started_at: config.timestamp(timing.started_at),
+            analysis_finished_at: config.timestamp(timing.finished_at),
+            queue_wait_ms: timing.queue_wait.as_millis() as u64,
+            inference_ms: timing.inference.as_millis() as u64,
+        }
+    }
+}
+
+/// When an upload was received and analyzed, for telling where the time of a request went.
+#[derive(Debug, Serialize, ToSchema)]
+struct UploadTiming {
+    analyze_time: Timestamp,
+    /// When the whole video was received, which is after the download for `/analyze-url`.
+    upload_received_at: Timestamp,
+    /// The times of the analysis, which are given per mode instead if several modes are requested.
+    #[serde(flatten)]
+    analysis: Option<AnalysisTimes>,
+}
+
+impl UploadTiming {
+    /// Creates an [`UploadTiming`] of an upload received at `received_at` and analyzed as told by
+    /// `analysis`, with the timestamps in the precision and the format set by `config`.
+    fn new(
+        config: &UploadConfig,
+        received_at: OffsetDateTime,
+        analysis: Option<&AnalysisTiming>,
+    ) -> Self {
+        Self {
+            analyze_time: config.timestamp(OffsetDateTime::now_utc()),
+            upload_received_at: config.timestamp(received_at),
+            analysis: analysis.map(|analysis| AnalysisTimes::new(config, analysis)),
+        }
+    }
+}
+
+/// The results of analyzing a video in a mode.
+#[derive(Debug, Serialize, ToSchema)]
+struct ModeResults {
+    #[serde(flatten)]
+    results: AnalysisResults,
+    summary: VideoAnalyzerSummary,
+    /// The unit of `start` and `end` of the suggestions. The frame indices and the summary are
+    /// unaffected by it.
+    time_unit: TimeUnit,
+    /// Whether some suggestions were dropped for exceeding the limit on the number of suggestions.
+    truncated: bool,
+    warnings: Vec<String>,
+    /// The output of the inference procedure as it was written, if requested with `?raw=true` and
+    /// the bearer token of [`UploadConfig::raw_output_token`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_output: Option<serde_json::Value>,
+    /// The last lines of the stderr of the inference procedure, if it failed and the raw output was
+    /// requested as above. It's never given to a request without the token, since it tells the
+    /// details of the host.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inference_stderr_tail: Option<Vec<String>>,
+}
+
+impl ModeResults {
+    fn new(
+        analyze_mode: VideoAnalyzerMode,
+        shape: ResponseShape,
+        mut report: VideoAnalyzerReport,
+        truncated: bool,
+        query: UploadQuery,
+    ) -> Self {
+        // The summary is in seconds regardless of the unit.
+        let summary = report.output.summary();
+        report.output.scale_times(query.unit.per_second());
+        let results = match (shape, query.group_by) {
+            (ResponseShape::Flat, None) => AnalysisResults::Flat {
+                analyze_mode: VideoAnalyzerModeDesc::new(analyze_mode),
+                suggestions: report.output,
+            },
+            (ResponseShape::Nested, None) => AnalysisResults::Nested {
+                analyze_mode: analyze_mode.keyed(),
+                suggestions: NestedVideoAnalyzerOutput::from(report.output),
+            },
+            (ResponseShape::Flat, Some(GroupBy::Type)) => AnalysisResults::FlatGrouped {
+                analyze_mode: VideoAnalyzerModeDesc::new(analyze_mode),
+                suggestions: GroupedVideoAnalyzerOutput::from(report.output),
+            },
+            (ResponseShape::Nested, Some(GroupBy::Type)) => AnalysisResults::NestedGrouped {
+                analyze_mode: analyze_mode.keyed(),
+                suggestions: GroupedVideoAnalyzerOutput::from(report.output),
+            },
+        };
+        Self {
+            results,
+            summary,
+            time_unit: query.unit,
+            truncated,
+            warnings: report.warnings,
+            raw_output: report.raw_output,
+            inference_stderr_tail: report.stderr_tail,
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+/// The response to an upload requesting a single mode with `mode`.
+#[derive(Debug, Serialize, ToSchema)]
 struct UploadResponse {
+    /// The correlation ID of the upload, which is also returned in the `X-Request-Id` header.
+    id: Uuid,
     file_name: String,
-    #[serde(with = "js_format")]
-    analyze_time: OffsetDateTime,
-    analyze_mode: VideoAnalyzerModeDesc,
-    suggestions: VideoAnalyzerOutput,
+    #[serde(flatten)]
+    timing: UploadTiming,
+    /// How thoroughly the video was analyzed, since a preview gives approximate results.
+    quality: AnalysisQuality,
+    /// The number of frames per second sampled by the analysis, or `null` if the inference
+    /// procedure chose it.
+    fps_sample: Option<f64>,
+    /// The name of the inference model that analyzed the video, or `null` for the default one.
+    model: Option<String>,
+    /// Whether the suggestions are complete, partial, or missing for a crash.
+    analysis_status: AnalysisStatus,
+    #[serde(flatten)]
+    results: ModeResults,
 }
 
 impl UploadResponse {
     fn new(
+        id: Uuid,
         file_name: &str,
-        analyze_mode: VideoAnalyzerMode,
-        suggestions: VideoAnalyzerOutput,
+        mdata: &UploadFormMetadata,
+        report: VideoAnalyzerReport,
+        truncated: bool,
+        timing: UploadTiming,
+        query: UploadQuery,
     ) -> Self {
         Self {
+            id,
             file_name: file_name.to_owned(),
-            analyze_time: OffsetDateTime::now_utc(),
-            analyze_mode: VideoAnalyzerModeDesc::new(analyze_mode),
-            suggestions,
+            timing,
+            quality: mdata.quality,
+            fps_sample: mdata.fps_sample,
+            model: mdata.model.clone(),
+            analysis_status: AnalysisStatus::of(&report),
+            results: ModeResults::new(mdata.mode, mdata.response_shape, report, truncated, query),
+        }
+    }
+}
+
+/// Whether the analysis in a mode produced suggestions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum AnalysisStatus {
+    Ok,
+    /// The inference procedure crashed, but some suggestions were salvaged from what it wrote.
+    Partial,
+    /// The inference procedure crashed, or the analyzer failed to run it.
+    Failed,
+}
+
+impl AnalysisStatus {
+    fn of(report: &VideoAnalyzerReport) -> Self {
+        if report.output.is_failed() {
+            Self::Failed
+        } else if report.partial {
+            Self::Partial
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+/// The outcome of the analysis in one of the modes requested with `modes`.
+#[derive(Debug, Serialize, ToSchema)]
+struct ModeAnalysis {
+    #[serde(skip)]
+    mode: VideoAnalyzerMode,
+    status: AnalysisStatus,
+    /// Why the analyzer failed, if it did. A crash of the inference procedure is told by
+    /// `suggestions` being `null` instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    #[serde(flatten)]
+    timing: Option<AnalysisTimes>,
+    #[serde(flatten)]
+    results: Option<ModeResults>,
+}
+
+impl ModeAnalysis {
+    fn new(
+        config: &UploadConfig,
+        mode: VideoAnalyzerMode,
+        shape: ResponseShape,
+        report: VideoAnalyzerReport,
+        truncated: bool,
+        query: UploadQuery,
+    ) -> Self {
+        Self {
+            mode,
+            status: AnalysisStatus::of(&report),
+            error: None,
+            timing: report
+                .timing
+                .as_deref()
+                .map(|timing| AnalysisTimes::new(config, timing)),
+            results: Some(ModeResults::new(mode, shape, report, truncated, query)),
+        }
+    }
+
+    fn failed(mode: VideoAnalyzerMode, error: &'static str) -> Self {
+        Self {
+            mode,
+            status: AnalysisStatus::Failed,
+            error: Some(error),
+            timing: None,
+            results: None,
         }
     }
 }
 
-#[post("/upload")]
-async fn upload_video(
+/// The response to an upload requesting several modes with `modes`. It's successful as long as
+/// the video is accepted, and tells the outcome of each mode in `analyses`.
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct MultiModeUploadResponse {
+    /// The correlation ID of the upload, which is also returned in the `X-Request-Id` header.
+    id: Uuid,
+    file_name: String,
+    #[serde(flatten)]
+    timing: UploadTiming,
+    quality: AnalysisQuality,
+    fps_sample: Option<f64>,
+    model: Option<String>,
+    /// The outcomes of the modes, serialized as a map from the names of the modes in the requested
+    /// order, such as `{"binary": {...}, "multi": {...}}`.
+    #[serde(serialize_with = "serialize_analyses")]
+    #[schema(value_type = BTreeMap<String, ModeAnalysis>)]
+    analyses: Vec<ModeAnalysis>,
+}
+
+fn serialize_analyses<S: Serializer>(
+    analyses: &[ModeAnalysis],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        analyses
+            .iter()
+            .map(|analysis| (analysis.mode.to_string(), analysis)),
+    )
+}
+
+/// The encoding of a successful upload response, chosen by the `Accept` header of the request.
+/// Error responses are always encoded in JSON.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+enum ResponseFormat {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    /// Returns the format the client prefers. JSON is chosen unless MessagePack is ranked above it,
+    /// including when `Accept` is missing or malformed.
+    fn negotiate(req: &HttpRequest) -> Self {
+        let Some(accept) = req.get_header::<header::Accept>() else {
+            return Self::default();
+        };
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match (mime.type_(), mime.subtype().as_str()) {
+                (mime::APPLICATION, "msgpack" | "x-msgpack") => Some(Self::MessagePack),
+                (mime::APPLICATION, "json" | "*") | (mime::STAR, _) => Some(Self::Json),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    fn respond<T: Serialize>(self, value: &T) -> Result<HttpResponse, Error> {
+        Ok(match self {
+            Self::Json => HttpResponse::Ok().json(value),
+            Self::MessagePack => {
+                // Encodes structs as maps, and IDs and timestamps as strings, so the response has
+                // the same shape as the JSON one.
+                let mut body = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut body)
+                    .with_struct_map()
+                    .with_human_readable();
+                value
+                    .serialize(&mut serializer)
+                    .map_err(actix_web::error::ErrorInternalServerError)?;
+                HttpResponse::Ok().content_type(MSGPACK).body(body)
+            }
+        })
+    }
+}
+
+/// Analyzes an uploaded video and responds with the suggested memes.
+///
+/// A `metadata` requesting several modes with `modes` is answered with a
+/// `MultiModeUploadResponse` instead, which tells the outcome of each mode separately.
+#[utoipa::path(
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    params(
+        UploadQuery,
+        ("idempotency-key" = Option<String>, Header,
+            description = "A key identifying the upload across its retries"),
+    ),
+    responses(
+        (status = 200, description = "The video was analyzed",
+            headers(
+                ("x-request-id" = String, description = "The correlation ID of the upload"),
+                ("x-queue-depth" = u64, description = "The number of queued or running analyses"),
+                ("x-estimated-wait" = Option<u64>, description = "The estimated wait in seconds"),
+            ),
+            content(
+                (UploadResponse = "application/json"),
+                (UploadResponse = "application/msgpack"),
+            )),
+        (status = 400, description = "The form or the metadata is invalid", body = ErrorBody),
+        (status = 401, description = "The raw output requires a bearer token", body = ErrorBody),
+        (status = 403, description = "The raw output isn't allowed", body = ErrorBody),
+        (status = 409, description = "An upload with the same key is in progress", body = ErrorBody),
+        (status = 413, description = "The upload exceeds the size limit", body = String),
+        (status = 415, description = "The file isn't a supported video", body = ErrorBody),
+        (status = 422, description = "The video is rejected or exceeded a limit, or the key was used for another request", body = ErrorBody),
+        (status = 500, description = "The analyzer failed", body = ErrorBody),
+        (status = 503, description = "Too many uploads are in progress", body = ErrorBody),
+        (status = 504, description = "The upload took longer than allowed", body = ErrorBody),
+        (status = 507, description = "The backend is low on storage", body = ErrorBody),
+    )
+)]
+#[post(
+    "/upload",
+    wrap = "from_fn(decompress_upload)",
+    wrap = "from_fn(reject_oversized_upload)",
+    wrap = "from_fn(reject_when_storage_low)",
+    wrap = "from_fn(limit_concurrent_uploads)",
+    wrap = "from_fn(limit_request_duration)",
+    wrap = "from_fn(deduplicate_uploads)"
+)]
+pub(super) async fn upload_video(
+    req: HttpRequest,
+    config: web::Data<UploadConfig>,
     analyzer: web::Data<VideoAnalyzerBuffer>,
+    query: web::Query<UploadQuery>,
     MultipartForm(form): MultipartForm<UploadForm>,
-) -> Result<impl Responder, Error> {
-    let Some(file_name) = form.file.file_name.as_ref() else {
-        return Ok(HttpResponse::BadRequest().body("file name is missing"));
+) -> HttpResponse {
+    let id = Uuid::new_v4();
+    analyze_video(
+        id,
+        &req,
+        &config,
+        &analyzer,
+        &form.file.0,
+        form.metadata.0.into_inner(),
+        *query,
+    )
+    .await
+}
+
+/// Analyzes an uploaded video `file` as requested by `mdata`, and returns the response to the
+/// upload. This is shared by all the ways to upload a video.
+///
+/// `id` is the correlation ID of the request, which is returned in the `X-Request-Id` header.
+/// The analysis results are encoded in the format negotiated by `req`, and are adjusted as
+/// requested by the query string `query`. The upload is recorded in the
+/// [`crate::handlers::AuditLog`] if one is registered.
+pub(super) async fn analyze_video(
+    id: Uuid,
+    req: &HttpRequest,
+    config: &UploadConfig,
+    analyzer: &VideoAnalyzerBuffer,
+    file: &TempFile,
+    mdata: UploadFormMetadata,
+    query: UploadQuery,
+) -> HttpResponse {
+    let format = ResponseFormat::negotiate(req);
+    let mode = mdata
+        .modes()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let res = match check_raw_output(req, config, &query) {
+        Ok(()) => analyze_upload(id, config, analyzer, file, mdata, format, query).await,
+        Err(res) => Ok(res),
+    };
+    let mut res = match res {
+        Ok(res) => res,
+        Err(e) => {
+            log::error!("[{}] {}", id, e);
+            e.error_response()
+        }
+    };
+    // A hyphenated UUID is always a valid header value.
+    res.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&id.to_string()).unwrap(),
+    );
+    res.headers_mut()
+        .insert(QUEUE_DEPTH_HEADER, analyzer.queue_depth().into());
+    if let Some(wait) = analyzer.estimated_wait() {
+        // Rounded up, so that a short wait isn't reported as none.
+        let wait_sec = wait.as_millis().div_ceil(1000) as u64;
+        res.headers_mut()
+            .insert(ESTIMATED_WAIT_HEADER, wait_sec.into());
+    }
+    let file_name = file.file_name.as_deref();
+    AuditRecord::new(id, req, file_name, file.size, mode, &res).submit(req);
+    res
+}
+
+/// Checks that a request asking for the raw output with `?raw=true` may get it, which requires the
+/// bearer token set by [`UploadConfig::raw_output_token`].
+fn check_raw_output(
+    req: &HttpRequest,
+    config: &UploadConfig,
+    query: &UploadQuery,
+) -> Result<(), HttpResponse> {
+    if !query.raw {
+        return Ok(());
+    }
+    match &config.raw_output_token {
+        Some(token) if utils::has_bearer_token(req, token) => Ok(()),
+        Some(_) => Err(utils::unauthorized()),
+        None => Err(HttpResponse::Forbidden().json(ErrorBody::new(
+            "raw_output_disallowed",
+            "the raw output of the inference procedure isn't available on this server",
+        ))),
+    }
+}
+
+/// Does the actual work of [`analyze_video`].
+async fn analyze_upload(
+    id: Uuid,
+    config: &UploadConfig,
+    analyzer: &VideoAnalyzerBuffer,
+    file: &TempFile,
+    mdata: UploadFormMetadata,
+    format: ResponseFormat,
+    query: UploadQuery,
+) -> Result<HttpResponse, Error> {
+    // The whole video has been received by the time the handler runs.
+    let received_at = OffsetDateTime::now_utc();
+    if let Some(model) = &mdata.model
+        && !config.models.contains(model)
+    {
+        log::info!("[{}] unknown model requested: {:?}", id, model);
+        return Ok(HttpResponse::BadRequest().json(ErrorBody::new(
+            "unknown_model",
+            format!("model {:?} isn't available on this server", model),
+        )));
+    }
+    let Some(file_name) = file.file_name.as_ref() else {
+        return Ok(HttpResponse::BadRequest().json(ErrorBody::new(
+            Rejection::MissingFileName.code(),
+            "file name is missing",
+        )));
     };
 
     log::info!(
-        "file received: \"{}\", size: {} bytes, content type: {}",
+        "[{}] file received: \"{}\", size: {} bytes, content type: {}",
+        id,
         file_name,
-        form.file.size,
-        form.file
-            .content_type
-            .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+        file.size,
+        file.content_type
+            .as_ref()
+            .unwrap_or(&mime::APPLICATION_OCTET_STREAM)
             .essence_str()
     );
 
-    // Checks if the video format is supported.
-    let video_name = if let (Some(video_name), Some(ext)) = utils::split_file_name(file_name)
-        && SUPPORTED_VIDEO_FORMATS.contains(&ext.to_str().unwrap())
-    {
-        video_name.to_str().unwrap()
-    } else {
-        return Ok(HttpResponse::BadRequest().body(format!(
-            "supported video formats are: {}",
-            SUPPORTED_VIDEO_FORMATS.join(", ")
+    // Checks if the video is acceptable for analysis.
+    let validation =
+        utils::validate_upload(file, config.ffprobe_path(), config.duration_limits()).await?;
+    if let Some(res) = validation.rejection_response() {
+        log::info!(
+            "[{}] video rejected: {}",
+            id,
+            validation.reason.as_deref().unwrap_or_default()
+        );
+        return Ok(res);
+    }
+    // A file name with a supported extension always has a file stem, but a bug there mustn't
+    // bring the worker down.
+    let Some(video_name) = utils::video_name(file_name) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorBody::new(
+            Rejection::MissingFileName.code(),
+            "file name has nothing before the extension",
         )));
     };
 
     // Constructs analysis task. We need to complete the analysis config and setup a oneshot channel
     // for receiving analysis resutls. All the stuff is then wrapped into a `Task` instance.
-    let mdata = form.metadata.into_inner();
-    let task = TaskConfig::new(form.file.file.path())
-        .analyze_mode(mdata.mode)
-        .video_name(video_name)
-        .build();
-
-    // Sends the task to the analyzer.
-    log::debug!("sending analysis task to the analyzer");
-    let Ok(handle) = task.spawn(&analyzer) else {
-        log::debug!(
-            "failed to send task to the analyzer, indicating that the receiving-half might have been dropped"
-        );
-        return Ok(HttpResponse::InternalServerError().body("internal communication broken"));
-    };
+    if validation.is_image() && (mdata.start_sec.is_some() || mdata.end_sec.is_some()) {
+        log::info!("[{}] invalid time range: the upload is an image", id);
+        return Ok(HttpResponse::BadRequest().json(ErrorBody::new(
+            "invalid_time_range",
+            "start_sec and end_sec don't apply to an image",
+        )));
+    }
+    if validation.is_image() && mdata.fps_sample.is_some() {
+        log::info!("[{}] invalid fps_sample: the upload is an image", id);
+        return Ok(HttpResponse::BadRequest().json(ErrorBody::new(
+            "invalid_fps_sample",
+            "fps_sample doesn't apply to an image",
+        )));
+    }
+    if let Err(reason) = mdata.check_time_range(validation.duration_sec) {
+        log::info!("[{}] invalid time range: {}", id, reason);
+        return Ok(HttpResponse::BadRequest().json(ErrorBody::new("invalid_time_range", reason)));
+    }
+    let mut task_config = TaskConfig::new(file.file.path());
+    task_config
+        .id(id)
+        .video_name(&video_name)
+        .file_name(file_name);
+    if let Some(start_sec) = mdata.start_sec {
+        task_config.start_sec(start_sec);
+    }
+    if let Some(end_sec) = mdata.end_sec {
+        task_config.end_sec(end_sec);
+    }
+    if let Some(fps_sample) = mdata.fps_sample {
+        task_config.fps_sample(fps_sample);
+    }
+    if let Some(model) = &mdata.model {
+        task_config.model(model);
+    }
+    if query.raw {
+        task_config.raw_output();
+    }
+    if validation.is_image() {
+        task_config.image();
+    }
+    task_config.quality(mdata.quality);
+
+    // Sends a task per mode to the analyzer. All of them are queued before awaiting any, so that
+    // they are analyzed back to back.
+    let mut handles = Vec::with_capacity(mdata.modes().len());
+    for &mode in mdata.modes() {
+        log::debug!("[{}] sending {} analysis task to the analyzer", id, mode);
+        let Ok(handle) = task_config.analyze_mode(mode).build().spawn(analyzer) else {
+            log::error!(
+                "[{}] failed to send task to the analyzer, indicating that the receiving-half might have been dropped",
+                id
+            );
+            return Ok(HttpResponse::InternalServerError().json(ErrorBody::new(
+                "queue_send_failed",
+                "internal communication broken: the analysis task couldn't be queued",
+            )));
+        };
+        handles.push(handle);
+    }
 
     // Awaits the analysis results and then constructs the response.
-    if let Ok(output) = handle.recv().await {
-        let output = output?;
-        let res = UploadResponse::new(file_name, mdata.mode, output);
-        Ok(HttpResponse::Ok().json(res))
-    } else {
-        log::debug!(
-            "failed to receive analysis results from the analyzer, indicating that the sending-half might have been dropped"
+    if mdata.modes.is_none() {
+        // There is exactly one handle without `modes`.
+        let Ok(report) = handles.remove(0).recv().await else {
+            log_recv_failure(id);
+            return Ok(HttpResponse::InternalServerError().json(ErrorBody::new(
+                "result_recv_failed",
+                "internal communication broken: the analysis results couldn't be received",
+            )));
+        };
+        let mut report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                // The analyzer has logged the error already.
+                if let Some(exceeded) = ResourceExceeded::find(&e) {
+                    return Ok(HttpResponse::UnprocessableEntity().json(ErrorBody::new(
+                        "resource_exceeded",
+                        format!(
+                            "the analysis exceeded the memory limit of {} bytes",
+                            exceeded.limit()
+                        ),
+                    )));
+                }
+                return Err(e.into());
+            }
+        };
+        let truncated = finish_report(id, config, &validation, query, &mut report);
+        let timing = UploadTiming::new(config, received_at, report.timing.as_deref());
+        let res = UploadResponse::new(id, file_name, &mdata, report, truncated, timing, query);
+        return format.respond(&res);
+    }
+
+    let mut analyses = Vec::with_capacity(handles.len());
+    for (handle, &mode) in handles.into_iter().zip(mdata.modes()) {
+        let analysis = match handle.recv().await {
+            Ok(Ok(mut report)) => {
+                let truncated = finish_report(id, config, &validation, query, &mut report);
+                ModeAnalysis::new(config, mode, mdata.response_shape, report, truncated, query)
+            }
+            // The analyzer has logged the error already.
+            Ok(Err(e)) if ResourceExceeded::find(&e).is_some() => {
+                ModeAnalysis::failed(mode, "the analysis exceeded the memory limit")
+            }
+            Ok(Err(_)) => ModeAnalysis::failed(mode, "the analyzer failed to run the analysis"),
+            Err(_) => {
+                log_recv_failure(id);
+                ModeAnalysis::failed(mode, "the analysis results couldn't be received")
+            }
+        };
+        analyses.push(analysis);
+    }
+    let res = MultiModeUploadResponse {
+        id,
+        file_name: file_name.to_owned(),
+        timing: UploadTiming::new(config, received_at, None),
+        quality: mdata.quality,
+        fps_sample: mdata.fps_sample,
+        model: mdata.model.clone(),
+        analyses,
+    };
+    format.respond(&res)
+}
+
+/// Fits the suggestions of `report` into the video described by `validation` and the limit set
+/// by `config`, flattens them if `query` asks for it, and logs how long the analysis took.
+///
+/// Returns whether some suggestions were dropped for exceeding the limit.
+fn finish_report(
+    id: Uuid,
+    config: &UploadConfig,
+    validation: &VideoValidation,
+    query: UploadQuery,
+    report: &mut VideoAnalyzerReport,
+) -> bool {
+    if let Some(duration_sec) = validation.duration_sec {
+        // A suggestion ending within the last fractional second still ends within the video.
+        let (clamped, dropped) = report.output.clamp_to_duration(duration_sec.ceil() as u32);
+        if clamped > 0 || dropped > 0 {
+            log::warn!(
+                "[{}] {} suggestions clamped and {} dropped for exceeding the video duration ({:.3} seconds)",
+                id,
+                clamped,
+                dropped,
+                duration_sec
+            );
+        }
+    }
+    if query.flatten {
+        report.output.flatten();
+    }
+    let mut truncated = false;
+    if let Some(max_suggestions) = config.max_suggestions {
+        let dropped = report.output.truncate(max_suggestions);
+        if dropped > 0 {
+            log::warn!(
+                "[{}] {} suggestions dropped for exceeding the limit of {}",
+                id,
+                dropped,
+                max_suggestions
+            );
+            truncated = true;
+        }
+    }
+    if let Some(fps) = validation.fps {
+        report.output.set_frame_rate(fps);
+    }
+    if let Some(timing) = &report.timing {
+        log::info!(
+            "[{}] analysis done, waited {} ms in the queue and took {} ms",
+            id,
+            timing.queue_wait.as_millis(),
+            timing.inference.as_millis()
         );
-        Ok(HttpResponse::InternalServerError().body("internal communication broken"))
     }
+    truncated
+}
+
+fn log_recv_failure(id: Uuid) {
+    log::error!(
+        "[{}] failed to receive analysis results from the analyzer, indicating that the sending-half might have been dropped",
+        id
+    );
 }
 
 pub fn config(cfg: &mut ServiceConfig) {
     cfg.service(upload_video);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{MemeType, VideoAnalyzer, VideoAnalyzerOutputBuilder};
+    use actix_http::Request;
+    use actix_web::body::MessageBody;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::{App, test as actix_test};
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const BOUNDARY: &str = "streameme-test-boundary";
+
+    fn multipart_body(metadata: Option<&str>, video: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        if let Some(metadata) = metadata {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\n\
+                     Content-Disposition: form-data; name=\"metadata\"\r\n\
+                     Content-Type: application/json\r\n\r\n\
+                     {metadata}\r\n"
+                )
+                .as_bytes(),
+            );
+        }
+        body.extend_from_slice(
+            format!(
+                "--{BOUNDARY}\r\n\
+                 Content-Disposition: form-data; name=\"file\"; filename=\"video.mp4\"\r\n\
+                 Content-Type: video/mp4\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(video);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    /// Initializes an app serving the upload endpoints with `upload_config`, storing the uploads
+    /// under `tmp_dir`. No analyzer runs, so the uploads have to be rejected before analysis.
+    async fn test_app(
+        tmp_dir: &Path,
+        upload_config: UploadConfig,
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+        let (_analyzer, analyzer_buf) = VideoAnalyzer::new(PathBuf::from("."));
+        let upload_config = web::Data::new(upload_config);
+        actix_test::init_service(
+            App::new()
+                .app_data(
+                    actix_multipart::form::tempfile::TempFileConfig::default().directory(tmp_dir),
+                )
+                .app_data(upload_config.multipart_form_config())
+                .app_data(upload_config)
+                .app_data(web::Data::new(analyzer_buf))
+                .configure(config),
+        )
+        .await
+    }
+
+    fn upload_request(body: Vec<u8>) -> actix_test::TestRequest {
+        actix_test::TestRequest::post()
+            .uri("/upload")
+            .insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            ))
+            .set_payload(body)
+    }
+
+    #[test]
+    fn test_negotiate_response_format() {
+        let negotiate = |accept: Option<&str>| {
+            let mut req = actix_test::TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.insert_header((header::ACCEPT, accept));
+            }
+            ResponseFormat::negotiate(&req.to_http_request())
+        };
+        assert_eq!(negotiate(None), ResponseFormat::Json);
+        assert_eq!(negotiate(Some("*/*")), ResponseFormat::Json);
+        assert_eq!(negotiate(Some("application/json")), ResponseFormat::Json);
+        assert_eq!(
+            negotiate(Some("application/msgpack")),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate(Some("application/json;q=0.5, application/x-msgpack")),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate(Some("application/msgpack;q=0.5, application/json")),
+            ResponseFormat::Json
+        );
+        assert_eq!(negotiate(Some("text/html")), ResponseFormat::Json);
+    }
+
+    #[test]
+    fn test_upload_response_shape() {
+        let response = |metadata: &str| {
+            let mdata: UploadFormMetadata = serde_json::from_str(metadata).unwrap();
+            let report = VideoAnalyzerReport {
+                output: VideoAnalyzerOutputBuilder::default()
+                    .suggestion(60, 90, MemeType::Love)
+                    .suggestion(0, 30, MemeType::Happiness)
+                    .build(),
+                ..Default::default()
+            };
+            let res = UploadResponse::new(
+                Uuid::nil(),
+                "video.mp4",
+                &mdata,
+                report,
+                false,
+                UploadTiming::new(&UploadConfig::new(1024), OffsetDateTime::now_utc(), None),
+                UploadQuery::default(),
+            );
+            serde_json::to_value(res).unwrap()
+        };
+
+        let flat = response(r#"{"mode": 1}"#);
+        assert_eq!(flat["analyze_mode"], "multi");
+        assert_eq!(flat["quality"], "full");
+        assert_eq!(
+            flat["suggestions"],
+            serde_json::json!([
+                {"start": 0, "end": 30, "meme_type": 0, "meme_type_desc": "happiness"},
+                {"start": 60, "end": 90, "meme_type": 1, "meme_type_desc": "love"},
+            ])
+        );
+        assert_eq!(flat["summary"]["love"]["total_seconds"], 30);
+        let nested = response(r#"{"mode": 1, "response_shape": "nested", "quality": "preview"}"#);
+        assert_eq!(nested["quality"], "preview");
+        assert_eq!(
+            nested["analyze_mode"],
+            serde_json::json!({"id": 1, "key": "multi"})
+        );
+        assert_eq!(
+            nested["suggestions"][1]["meme_type"],
+            serde_json::json!({"id": 1, "key": "love"})
+        );
+        assert_eq!(nested["summary"], flat["summary"]);
+    }
+
+    #[test]
+    fn test_check_time_range() {
+        let mdata = |start_sec, end_sec| UploadFormMetadata {
+            mode: VideoAnalyzerMode::Multi,
+            modes: None,
+            start_sec,
+            end_sec,
+            fps_sample: None,
+            model: None,
+            quality: AnalysisQuality::default(),
+            response_shape: ResponseShape::default(),
+        };
+
+        assert!(mdata(None, None).check_time_range(None).is_ok());
+        assert!(mdata(Some(30), Some(60)).check_time_range(None).is_ok());
+        assert!(
+            mdata(Some(30), Some(60))
+                .check_time_range(Some(60.0))
+                .is_ok()
+        );
+        assert!(mdata(Some(30), None).check_time_range(Some(45.5)).is_ok());
+        assert!(mdata(Some(60), Some(60)).check_time_range(None).is_err());
+        assert!(mdata(Some(90), Some(60)).check_time_range(None).is_err());
+        assert!(mdata(None, Some(61)).check_time_range(Some(60.0)).is_err());
+        assert!(mdata(Some(60), None).check_time_range(Some(60.0)).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_upload_size_limit() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        let app = test_app(tmp_dir.path(), UploadConfig::new(64)).await;
+
+        // The declared content length exceeds the limit, so the body is never read.
+        let req = upload_request(multipart_body(Some(r#"{"mode":1}"#), &[0; 128])).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // The declared content length is within the limit, but the streamed body is not.
+        let req = upload_request(multipart_body(Some(r#"{"mode":1}"#), &[0; 128]))
+            .insert_header((header::CONTENT_LENGTH, 32))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_decompress_upload() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        let app = test_app(tmp_dir.path(), UploadConfig::new(1024)).await;
+        let gzip = |body: Vec<u8>| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&body).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        // The form is parsed from the decompressed body.
+        let body = gzip(multipart_body(Some(r#"{"mode":2}"#), &[0; 16]));
+        let req = upload_request(body)
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "malformed_metadata");
+
+        // The compressed body is within the size limit, but the decompressed one is not.
+        let body = gzip(multipart_body(Some(r#"{"mode":1}"#), &[0; 64 * 1024]));
+        assert!(body.len() < 1024);
+        let req = upload_request(body)
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+
+        let req = upload_request(multipart_body(Some(r#"{"mode":1}"#), &[0; 16]))
+            .insert_header((header::CONTENT_ENCODING, "br"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "unsupported_content_encoding");
+    }
+
+    #[actix_web::test]
+    async fn test_reject_when_storage_low() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        // No volume has that much free space.
+        let app = test_app(
+            tmp_dir.path(),
+            UploadConfig::new(1024).min_free_space(tmp_dir.path(), u64::MAX),
+        )
+        .await;
+
+        let req = upload_request(multipart_body(Some(r#"{"mode":1}"#), &[0; 128])).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INSUFFICIENT_STORAGE);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "insufficient_storage");
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_non_video_content_type() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        let app = test_app(tmp_dir.path(), UploadConfig::new(1024)).await;
+
+        let body = String::from_utf8(multipart_body(Some(r#"{"mode":1}"#), b"hello"))
+            .unwrap()
+            .replace("video/mp4", "text/plain");
+        let req = upload_request(body.into_bytes()).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "unsupported_format");
+        assert_eq!(
+            body["message"],
+            "the file is declared to be of type text/plain, which is not a video"
+        );
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_malformed_metadata() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        let app = test_app(tmp_dir.path(), UploadConfig::new(1024)).await;
+
+        for (metadata, code) in [
+            (None, "missing_field"),
+            (Some(r#"{"mode":2}"#), "malformed_metadata"),
+            (Some(r#"{"start_sec":30}"#), "malformed_metadata"),
+            (Some(r#"{"mode":1,"fps_sample":0}"#), "malformed_metadata"),
+            (Some(r#"{"mode":1,"fps_sample":-2}"#), "malformed_metadata"),
+            (
+                Some(r#"{"mode":1,"fps_sample":1000}"#),
+                "malformed_metadata",
+            ),
+            (Some(r#"{"mode":1,"fps_sample":"2"}"#), "malformed_metadata"),
+            (Some(r#"{"mode":1,"modes":[1]}"#), "malformed_metadata"),
+            (Some(r#"{"modes":[]}"#), "malformed_metadata"),
+            (Some(r#"{"modes":[1,0,1]}"#), "malformed_metadata"),
+            (Some(r#"{"modes":[0,2]}"#), "malformed_metadata"),
+            (Some("mode=1"), "malformed_metadata"),
+        ] {
+            let req = upload_request(multipart_body(metadata, &[0; 16])).to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = actix_test::read_body_json(res).await;
+            assert_eq!(body["code"], code);
+        }
+
+        let req = upload_request(multipart_body(Some(r#"{"mode":2}"#), &[0; 16])).to_request();
+        let body: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert!(
+            body["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("metadata is malformed: mode must be 0 (binary) or 1 (multi)")
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_upload_metadata_limit() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        let app = test_app(tmp_dir.path(), UploadConfig::new(1024).metadata_limit(32)).await;
+
+        let metadata = format!(r#"{{"mode":1,"padding":"{}"}}"#, "x".repeat(64));
+        let req = upload_request(multipart_body(Some(&metadata), &[0; 16])).to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "metadata_too_large");
+        assert_eq!(
+            body["message"],
+            "metadata exceeds the size limit of 32 bytes"
+        );
+
+        // A metadata part within the limit is read as usual.
+        let req = upload_request(multipart_body(Some(r#"{"mode":2}"#), &[0; 16])).to_request();
+        let body: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["code"], "malformed_metadata");
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_upload_unexpected_fields() {
+        let tmp_dir = tempfile::TempDir::new_in(".").unwrap();
+        let app = test_app(tmp_dir.path(), UploadConfig::new(1024)).await;
+
+        for (name, code, message) in [
+            ("metadata", "duplicate_field", "metadata is given twice"),
+            ("mode", "unknown_field", "mode is not a field of the form"),
+        ] {
+            let mut body = multipart_body(Some(r#"{"mode":1}"#), &[0; 16]);
+            let part = format!(
+                "--{BOUNDARY}\r\n\
+                 Content-Disposition: form-data; name=\"{name}\"\r\n\
+                 Content-Type: application/json\r\n\r\n\
+                 {{\"mode\":1}}\r\n"
+            );
+            body.splice(0..0, part.into_bytes());
+            let req = upload_request(body).to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = actix_test::read_body_json(res).await;
+            assert_eq!(body["code"], code);
+            assert_eq!(body["message"], message);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_limit_concurrent_uploads() {
+        // Requests hang in the handler until the gate is opened.
+        let gate = Arc::new(Semaphore::new(0));
+        let gate_2 = gate.clone();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(
+                    UploadConfig::new(1024).max_concurrent_uploads(2),
+                ))
+                .service(
+                    web::resource("/upload")
+                        .wrap(from_fn(limit_concurrent_uploads))
+                        .to(move || {
+                            let gate = gate_2.clone();
+                            async move {
+                                gate.acquire().await.unwrap().forget();
+                                HttpResponse::Ok().finish()
+                            }
+                        }),
+                ),
+        )
+        .await;
+
+        let call = || {
+            actix_test::call_service(
+                &app,
+                actix_test::TestRequest::post().uri("/upload").to_request(),
+            )
+        };
+        let (first, second, third) = tokio::join!(call(), call(), async {
+            let res = call().await;
+            gate.add_permits(2);
+            res
+        });
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(third.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // The slots are released once the responses are produced.
+        gate.add_permits(1);
+        assert_eq!(call().await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_limit_request_duration() {
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(
+                    UploadConfig::new(1024).max_request_duration(Duration::from_millis(100)),
+                ))
+                .service(
+                    web::resource("/upload/{ms}")
+                        .wrap(from_fn(limit_request_duration))
+                        .to(|ms: web::Path<u64>| async move {
+                            actix_web::rt::time::sleep(Duration::from_millis(*ms)).await;
+                            HttpResponse::Ok().finish()
+                        }),
+                ),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::post()
+            .uri("/upload/0")
+            .to_request();
+        assert_eq!(
+            actix_test::call_service(&app, req).await.status(),
+            StatusCode::OK
+        );
+        let req = actix_test::TestRequest::post()
+            .uri("/upload/5000")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body: serde_json::Value = actix_test::read_body_json(res).await;
+        assert_eq!(body["code"], "request_timeout");
+        let elapsed_ms = body["elapsed_ms"].as_u64().unwrap();
+        assert!((100..5000).contains(&elapsed_ms), "{}", elapsed_ms);
+    }
+}
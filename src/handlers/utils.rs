@@ -1,5 +1,693 @@
+use actix_multipart::form::{FieldReader, Limits, tempfile::TempFile};
+use actix_multipart::{Field, MultipartError};
+use actix_web::error::InternalError;
+use actix_web::http::{StatusCode, header};
+use actix_web::{HttpRequest, HttpResponse, ResponseError, web};
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
 use std::path::Path;
+use tokio::process::Command;
+use utoipa::ToSchema;
+
+/// The JSON body of an error response, carrying a machine-readable `code` along with a
+/// human-readable `message`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ErrorBody {
+    #[inline]
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Returns whether `req` carries `token` in its `Authorization` header as a bearer token. The
+/// token is compared in constant time, so that it can't be guessed from the response times.
+pub fn has_bearer_token(req: &HttpRequest, token: &str) -> bool {
+    let Some(given) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Returns the 401 response to a request without the bearer token an endpoint requires.
+pub fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .json(ErrorBody::new(
+            "unauthorized",
+            "a valid bearer token is required",
+        ))
+}
+
+/// Returns the configuration of the endpoints taking a JSON body, which answers a malformed body
+/// with a 400 carrying an [`ErrorBody`].
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| {
+        let res = HttpResponse::BadRequest().json(ErrorBody::new(
+            "malformed_request",
+            format!("the request body is malformed: {}", err),
+        ));
+        InternalError::from_response(err, res).into()
+    })
+}
+
+/// The number of leading bytes needed by [`VideoFormat::sniff`] and [`sniff_content_type`].
+const SNIFF_LEN: usize = 12;
+
+/// Detects the MIME type of a file from its leading bytes (magic bytes). At least the first 12
+/// bytes are needed.
+///
+/// Besides the supported video formats, some common formats that clients upload by mistake are
+/// recognized, so that the rejection can tell what the file actually is.
+pub fn sniff_content_type(header: &[u8]) -> Option<&'static str> {
+    if let Some(format) = VideoFormat::sniff(header) {
+        return Some(format.mime());
+    }
+    if header.len() < SNIFF_LEN {
+        return None;
+    }
+    match header {
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => Some("video/x-matroska"),
+        [b'F', b'L', b'V', 0x01, ..] => Some("video/x-flv"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'A',
+            b'V',
+            b'E',
+            ..,
+        ] => Some("audio/wav"),
+        [b'I', b'D', b'3', ..] | [0xff, 0xfb, ..] => Some("audio/mpeg"),
+        [b'O', b'g', b'g', b'S', ..] => Some("application/ogg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'%', b'P', b'D', b'F', ..] => Some("application/pdf"),
+        [b'P', b'K', 0x03, 0x04, ..] => Some("application/zip"),
+        _ => None,
+    }
+}
+
+/// A video container format accepted by the backend.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoFormat {
+    Mp4,
+    Avi,
+    Mov,
+}
+
+impl VideoFormat {
+    pub const ALL: [VideoFormat; 3] = [VideoFormat::Mp4, VideoFormat::Avi, VideoFormat::Mov];
+
+    /// Returns the format using `ext` as its file extension, if it is supported.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == ext)
+    }
+
+    #[inline]
+    pub fn extension(self) -> &'static str {
+        use VideoFormat::*;
+        match self {
+            Mp4 => "mp4",
+            Avi => "avi",
+            Mov => "mov",
+        }
+    }
+
+    #[inline]
+    pub fn mime(self) -> &'static str {
+        use VideoFormat::*;
+        match self {
+            Mp4 => "video/mp4",
+            Avi => "video/x-msvideo",
+            Mov => "video/quicktime",
+        }
+    }
+
+    /// Detects the format from the leading bytes (magic bytes) of a file. At least the first 12
+    /// bytes are needed.
+    ///
+    /// Both MP4 and QuickTime files are sequences of "atoms", whose type is stored in bytes 4..8.
+    /// An MP4 file starts with an `ftyp` atom, while a QuickTime file either starts with an `ftyp`
+    /// atom of brand `qt  ` or with one of the atoms predating `ftyp`. An AVI file is a RIFF file
+    /// of form type `AVI `.
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.len() < SNIFF_LEN {
+            return None;
+        }
+        match (&header[0..4], &header[4..8], &header[8..12]) {
+            (b"RIFF", _, b"AVI ") => Some(VideoFormat::Avi),
+            (_, b"ftyp", b"qt  ") => Some(VideoFormat::Mov),
+            (_, b"ftyp", _) => Some(VideoFormat::Mp4),
+            (_, b"moov" | b"mdat" | b"wide" | b"free" | b"skip", _) => Some(VideoFormat::Mov),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a file named with the extension of `self` may contain `detected`. MP4 and
+    /// QuickTime files are interchangeable, since they share the same container structure and
+    /// are commonly mislabeled as each other.
+    fn is_compatible_with(self, detected: Self) -> bool {
+        use VideoFormat::*;
+        matches!((self, detected), (Mp4 | Mov, Mp4 | Mov) | (Avi, Avi))
+    }
+}
+
+/// An image format accepted by the backend, whose frame is classified as a whole instead of
+/// being searched for memes over time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 2] = [ImageFormat::Jpeg, ImageFormat::Png];
+
+    /// The file extensions of the supported image formats, where `jpg` and `jpeg` are both JPEG.
+    pub const EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+    /// Returns the format using `ext` as its file extension, if it is supported.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// Returns the format whose MIME type is `mime`, if it is supported.
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.mime() == mime)
+    }
+
+    #[inline]
+    pub fn extension(self) -> &'static str {
+        use ImageFormat::*;
+        match self {
+            Jpeg => "jpg",
+            Png => "png",
+        }
+    }
+
+    #[inline]
+    pub fn mime(self) -> &'static str {
+        use ImageFormat::*;
+        match self {
+            Jpeg => "image/jpeg",
+            Png => "image/png",
+        }
+    }
+
+    /// Detects the format from the leading bytes (magic bytes) of a file.
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        match header {
+            [0x89, b'P', b'N', b'G', ..] => Some(ImageFormat::Png),
+            [0xff, 0xd8, 0xff, ..] => Some(ImageFormat::Jpeg),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of probing a video with `ffprobe`.
+#[derive(Debug)]
+pub enum ProbeOutcome {
+    /// `ffprobe` is not installed, so nothing is known about the video.
+    Unavailable,
+    /// `ffprobe` failed to read the video. The reason reported by `ffprobe` is wrapped inside.
+    Undecodable(String),
+    /// The video is decodable. Its duration in seconds and its frame rate are wrapped inside, if
+    /// known.
+    Decodable {
+        duration_sec: Option<f64>,
+        fps: Option<f64>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct FfprobeOutput {
+    format: FfprobeFormat,
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FfprobeFormat {
+    duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FfprobeStream {
+    avg_frame_rate: Option<String>,
+}
+
+/// Parses a frame rate reported by `ffprobe` as a fraction, such as `30000/1001`. `ffprobe` reports
+/// `0/0` if the frame rate is unknown, in which case [`None`] is returned.
+fn parse_frame_rate(frame_rate: &str) -> Option<f64> {
+    let (num, den) = frame_rate.split_once('/')?;
+    let fps = num.parse::<f64>().ok()? / den.parse::<f64>().ok()?;
+    (fps.is_finite() && fps > 0.0).then_some(fps)
+}
+
+/// Probes the video at `path` with the `ffprobe` executable `ffprobe_bin`.
+///
+/// # Errors
+/// An error is returned if `ffprobe` is installed but can not be spawned, or its output can not
+/// be parsed.
+pub async fn probe_video(path: &Path, ffprobe_bin: &Path) -> io::Result<ProbeOutcome> {
+    let output = match Command::new(ffprobe_bin)
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "format=duration:stream=avg_frame_rate",
+            "-of",
+            "json",
+        ])
+        .arg(path)
+        .output()
+        .await
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ProbeOutcome::Unavailable),
+        Err(e) => return Err(e),
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Ok(ProbeOutcome::Undecodable(String::from(stderr.trim())));
+    }
+
+    let probed: FfprobeOutput = serde_json::from_slice(&output.stdout)?;
+    let duration_sec = probed.format.duration.and_then(|d| d.parse::<f64>().ok());
+    let fps = probed
+        .streams
+        .first()
+        .and_then(|stream| stream.avg_frame_rate.as_deref())
+        .and_then(parse_frame_rate);
+    Ok(ProbeOutcome::Decodable { duration_sec, fps })
+}
+
+/// The kind of problem that makes an uploaded video rejected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    MissingFileName,
+    MissingExtension,
+    UnsupportedFormat,
+    Undecodable,
+    TooShort,
+    TooLong,
+}
+
+impl Rejection {
+    #[inline]
+    pub fn code(self) -> &'static str {
+        use Rejection::*;
+        match self {
+            MissingFileName => "missing_file_name",
+            MissingExtension => "missing_extension",
+            UnsupportedFormat => "unsupported_format",
+            Undecodable => "undecodable_video",
+            TooShort => "video_too_short",
+            TooLong => "video_too_long",
+        }
+    }
+
+    #[inline]
+    pub fn status(self) -> StatusCode {
+        use Rejection::*;
+        match self {
+            MissingFileName | MissingExtension | Undecodable => StatusCode::BAD_REQUEST,
+            UnsupportedFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            TooShort | TooLong => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+/// The range of durations of the videos accepted for analysis. Both ends are unbounded by
+/// default.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DurationLimits {
+    pub min_sec: Option<f64>,
+    pub max_sec: Option<f64>,
+}
+
+impl DurationLimits {
+    /// Checks a video lasting `duration_sec` seconds against the limits, and returns the rejection
+    /// along with its reason if the duration is out of range.
+    fn check(&self, duration_sec: f64) -> Option<(Rejection, String)> {
+        if let Some(min_sec) = self.min_sec
+            && duration_sec < min_sec
+        {
+            return Some((
+                Rejection::TooShort,
+                format!(
+                    "the video lasts {:.3} seconds, shorter than the minimum of {} seconds",
+                    duration_sec, min_sec
+                ),
+            ));
+        }
+        if let Some(max_sec) = self.max_sec
+            && duration_sec > max_sec
+        {
+            return Some((
+                Rejection::TooLong,
+                format!(
+                    "the video lasts {:.3} seconds, longer than the maximum of {} seconds",
+                    duration_sec, max_sec
+                ),
+            ));
+        }
+        None
+    }
+}
+
+/// What the backend detected about an uploaded video, and whether it is accepted for analysis.
+#[derive(Debug, Serialize)]
+pub struct VideoValidation {
+    pub file_name: Option<String>,
+    pub size: usize,
+    pub extension: Option<String>,
+    pub detected_format: Option<VideoFormat>,
+    /// The image format detected from the file content, if the file is named as an image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected_image_format: Option<ImageFormat>,
+    pub detected_content_type: Option<&'static str>,
+    pub duration_sec: Option<f64>,
+    pub fps: Option<f64>,
+    pub accepted: bool,
+    /// Why the video is rejected. This is [`None`] if the video is accepted.
+    pub reason: Option<String>,
+    #[serde(skip)]
+    pub rejection: Option<Rejection>,
+}
+
+impl VideoValidation {
+    /// Creates a validation accepting `file`, to be filled in by the checks.
+    fn new(file: &TempFile) -> Self {
+        Self {
+            file_name: file.file_name.clone(),
+            size: file.size,
+            extension: None,
+            detected_format: None,
+            detected_image_format: None,
+            detected_content_type: None,
+            duration_sec: None,
+            fps: None,
+            accepted: true,
+            reason: None,
+            rejection: None,
+        }
+    }
+
+    /// Returns whether the upload is an accepted image, which is analyzed as a single frame.
+    #[inline]
+    pub fn is_image(&self) -> bool {
+        self.accepted && self.detected_image_format.is_some()
+    }
+
+    fn reject(mut self, rejection: Rejection, reason: String) -> Self {
+        self.accepted = false;
+        self.reason = Some(reason);
+        self.rejection = Some(rejection);
+        self
+    }
+
+    /// Returns the error response to an upload rejected by this validation, or [`None`] if the
+    /// video is accepted. Besides the [`ErrorBody`], the response tells the extension and the
+    /// content type detected by the backend.
+    pub fn rejection_response(&self) -> Option<HttpResponse> {
+        #[derive(Serialize)]
+        struct RejectionBody<'a> {
+            #[serde(flatten)]
+            error: ErrorBody,
+            extension: Option<&'a str>,
+            detected_content_type: Option<&'static str>,
+        }
+
+        let rejection = self.rejection?;
+        let body = RejectionBody {
+            error: ErrorBody::new(rejection.code(), self.reason.clone().unwrap_or_default()),
+            extension: self.extension.as_deref(),
+            detected_content_type: self.detected_content_type,
+        };
+        Some(HttpResponse::build(rejection.status()).json(body))
+    }
+}
+
+/// The error of a file part declared to be of a content type that is obviously not a video.
+#[derive(Debug)]
+pub struct NonVideoContentType(pub mime::Mime);
+
+impl fmt::Display for NonVideoContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the file is declared to be of type {}, which is not a video",
+            self.0.essence_str()
+        )
+    }
+}
+
+impl std::error::Error for NonVideoContentType {}
+
+impl ResponseError for NonVideoContentType {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    }
+}
+
+/// A video file part of a multipart form, which is a [`TempFile`] rejected before its content is
+/// read if the declared `Content-Type` of the part is obviously not a video, such as
+/// `text/plain`. This fails the upload before the file is streamed to disk, complementing the
+/// checks of [`validate_upload`] on the file content.
+///
+/// Clients often declare a video as `application/octet-stream` or a vendor-specific
+/// `application/*` type, so only the types under `text`, `image`, `audio`, `font`, `model`,
+/// `multipart` and `message` are rejected, except for the supported [`ImageFormat`]s. A missing
+/// `Content-Type` is accepted as well.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct VideoFile(pub TempFile);
+
+impl VideoFile {
+    fn is_obviously_not_video(content_type: &mime::Mime) -> bool {
+        match content_type.type_().as_str() {
+            "image" => ImageFormat::from_mime(content_type.essence_str()).is_none(),
+            "text" | "audio" | "font" | "model" | "multipart" | "message" => true,
+            _ => false,
+        }
+    }
+}
+
+impl<'t> FieldReader<'t> for VideoFile {
+    type Future = LocalBoxFuture<'t, Result<Self, MultipartError>>;
+
+    fn read_field(req: &'t HttpRequest, field: Field, limits: &'t mut Limits) -> Self::Future {
+        Box::pin(async move {
+            if let Some(content_type) = field.content_type()
+                && Self::is_obviously_not_video(content_type)
+            {
+                return Err(MultipartError::Field {
+                    name: String::from(field.name().unwrap_or_default()),
+                    source: NonVideoContentType(content_type.clone()).into(),
+                });
+            }
+            TempFile::read_field(req, field, limits)
+                .await
+                .map(VideoFile)
+        })
+    }
+}
+
+/// Runs the pre-checks an upload has to pass before being analyzed, which are the ones of
+/// [`validate_image`] if the file is named as an image, or the ones of [`validate_video`]
+/// otherwise.
+///
+/// # Errors
+/// An error is returned if the uploaded file can not be read, or probing the video fails for
+/// reasons other than the video itself.
+pub async fn validate_upload(
+    file: &TempFile,
+    ffprobe_bin: &Path,
+    duration_limits: &DurationLimits,
+) -> io::Result<VideoValidation> {
+    let image_format = file
+        .file_name
+        .as_ref()
+        .and_then(|file_name| split_file_name(file_name).1)
+        .and_then(OsStr::to_str)
+        .and_then(ImageFormat::from_extension);
+    match image_format {
+        Some(format) => validate_image(file, format),
+        None => validate_video(file, ffprobe_bin, duration_limits).await,
+    }
+}
+
+/// Runs the pre-checks an uploaded image, named with the extension of `format`, has to pass
+/// before being analyzed: the leading bytes must match `format`. An image has no duration, so the
+/// limits on it don't apply.
+///
+/// # Errors
+/// An error is returned if the uploaded file can not be read.
+pub fn validate_image(file: &TempFile, format: ImageFormat) -> io::Result<VideoValidation> {
+    let mut validation = VideoValidation::new(file);
+    validation.extension = file
+        .file_name
+        .as_ref()
+        .and_then(|file_name| split_file_name(file_name).1)
+        .map(|ext| ext.to_string_lossy().into_owned());
+
+    let header = read_header(file)?;
+    let detected = ImageFormat::sniff(&header);
+    validation.detected_image_format = detected;
+    validation.detected_content_type = sniff_content_type(&header);
+    if detected != Some(format) {
+        let reason = format!(
+            "the file content is not a valid {} image (detected {})",
+            format.extension(),
+            validation
+                .detected_content_type
+                .unwrap_or("an unknown content type")
+        );
+        return Ok(validation.reject(Rejection::UnsupportedFormat, reason));
+    }
+    Ok(validation)
+}
+
+/// Reads the leading bytes of `file` needed to detect its format.
+fn read_header(file: &TempFile) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    File::open(file.file.path())?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Runs the pre-checks an uploaded video has to pass before being analyzed: the file name must
+/// have a supported extension, the leading bytes must match a format compatible with the
+/// extension, and the video must be decodable by the `ffprobe` executable `ffprobe_bin`, with a
+/// duration within `duration_limits`. The last two checks are skipped if `ffprobe` isn't installed.
+///
+/// # Errors
+/// An error is returned if the uploaded file can not be read, or probing the video fails for
+/// reasons other than the video itself.
+pub async fn validate_video(
+    file: &TempFile,
+    ffprobe_bin: &Path,
+    duration_limits: &DurationLimits,
+) -> io::Result<VideoValidation> {
+    let mut validation = VideoValidation::new(file);
+
+    let Some(file_name) = file.file_name.as_ref() else {
+        return Ok(validation.reject(
+            Rejection::MissingFileName,
+            String::from("file name is missing"),
+        ));
+    };
+    let ext = split_file_name(file_name)
+        .1
+        .map(|ext| ext.to_string_lossy());
+    validation.extension = ext.as_ref().map(|ext| ext.to_string());
+
+    let header = read_header(file)?;
+    let detected = VideoFormat::sniff(&header);
+    validation.detected_format = detected;
+    validation.detected_content_type = sniff_content_type(&header);
+    let detected_desc = validation
+        .detected_content_type
+        .unwrap_or("an unknown content type");
+
+    let supported = VideoFormat::ALL.map(VideoFormat::extension).join(", ");
+    let Some(ext) = ext else {
+        let reason = format!(
+            "the file name {:?} has no extension (detected {}); add the extension of its format, which must be one of: {}, {}",
+            file_name,
+            detected_desc,
+            supported,
+            ImageFormat::EXTENSIONS.join(", ")
+        );
+        return Ok(validation.reject(Rejection::MissingExtension, reason));
+    };
+    let Some(format) = VideoFormat::from_extension(&ext) else {
+        let reason = format!(
+            "unsupported extension {:?} (detected {}); supported video formats are: {}, and image formats are: {}",
+            ext,
+            detected_desc,
+            supported,
+            ImageFormat::EXTENSIONS.join(", ")
+        );
+        return Ok(validation.reject(Rejection::UnsupportedFormat, reason));
+    };
+    if !detected.is_some_and(|detected| format.is_compatible_with(detected)) {
+        let reason = format!(
+            "the file content is not a valid {} video (detected {})",
+            format.extension(),
+            detected_desc
+        );
+        return Ok(validation.reject(Rejection::UnsupportedFormat, reason));
+    }
+
+    match probe_video(file.file.path(), ffprobe_bin).await? {
+        ProbeOutcome::Unavailable => {
+            log::debug!("ffprobe is not available, skipped probing the video");
+        }
+        ProbeOutcome::Undecodable(reason) => {
+            log::debug!("ffprobe failed to read the video: {}", reason);
+            return Ok(validation.reject(
+                Rejection::Undecodable,
+                String::from("the video can not be decoded"),
+            ));
+        }
+        ProbeOutcome::Decodable { duration_sec, fps } => {
+            validation.duration_sec = duration_sec;
+            validation.fps = fps;
+        }
+    }
+    if let Some((rejection, reason)) = validation
+        .duration_sec
+        .and_then(|duration_sec| duration_limits.check(duration_sec))
+    {
+        return Ok(validation.reject(rejection, reason));
+    }
+
+    Ok(validation)
+}
 
 /// Returns the file stem and file extension parts of `file_name`. This internally uses
 /// [`std::path::Path::file_stem`] and [`std::path::Path::extension`]. See their document for how
@@ -22,10 +710,204 @@ pub fn split_file_name<P: AsRef<Path> + ?Sized>(file_name: &P) -> (Option<&OsStr
     (path.file_stem(), path.extension())
 }
 
+/// Returns the file stem of `file_name`, which names the video for the inference procedure, or
+/// [`None`] if there is no file stem. Invalid UTF-8 is replaced with `U+FFFD` instead of being
+/// rejected, since the name only serves as a label.
+pub fn video_name<P: AsRef<Path> + ?Sized>(file_name: &P) -> Option<Cow<'_, str>> {
+    split_file_name(file_name).0.map(OsStr::to_string_lossy)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sniff_video_format() {
+        assert_eq!(
+            VideoFormat::sniff(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"),
+            Some(VideoFormat::Mp4)
+        );
+        assert_eq!(
+            VideoFormat::sniff(b"\x00\x00\x00\x14ftypqt  \x00\x00\x00\x00"),
+            Some(VideoFormat::Mov)
+        );
+        assert_eq!(
+            VideoFormat::sniff(b"\x00\x00\x00\x08wide\x00\x00\x00\x00"),
+            Some(VideoFormat::Mov)
+        );
+        assert_eq!(
+            VideoFormat::sniff(b"RIFF\x00\x10\x00\x00AVI LIST"),
+            Some(VideoFormat::Avi)
+        );
+        assert_eq!(VideoFormat::sniff(b"RIFF\x00\x10\x00\x00WAVEfmt "), None);
+        assert_eq!(VideoFormat::sniff(b"hello, world!"), None);
+        assert_eq!(VideoFormat::sniff(b"RIFF"), None);
+    }
+
+    #[test]
+    fn test_sniff_content_type() {
+        assert_eq!(
+            sniff_content_type(b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00"),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            sniff_content_type(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\xf7\x81"),
+            Some("video/x-matroska")
+        );
+        assert_eq!(
+            sniff_content_type(b"RIFF\x00\x10\x00\x00WAVEfmt "),
+            Some("audio/wav")
+        );
+        assert_eq!(
+            sniff_content_type(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0d"),
+            Some("image/png")
+        );
+        assert_eq!(sniff_content_type(b"hello, world!"), None);
+    }
+
+    fn temp_file(file_name: &str, content: &[u8]) -> TempFile {
+        let mut file = tempfile::NamedTempFile::new_in(".").unwrap();
+        std::io::Write::write_all(&mut file, content).unwrap();
+        TempFile {
+            file,
+            content_type: None,
+            file_name: Some(String::from(file_name)),
+            size: content.len(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_reject_unsupported_format() {
+        let mkv = b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\xf7\x81";
+
+        for file_name in ["video.mkv", "video.mp4"] {
+            let validation = validate_video(
+                &temp_file(file_name, mkv),
+                Path::new("ffprobe"),
+                &DurationLimits::default(),
+            )
+            .await
+            .unwrap();
+            assert!(!validation.accepted);
+            assert_eq!(validation.rejection, Some(Rejection::UnsupportedFormat));
+
+            let res = validation.rejection_response().unwrap();
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "unsupported_format");
+            assert_eq!(body["extension"], file_name.split('.').nth(1).unwrap());
+            assert_eq!(body["detected_content_type"], "video/x-matroska");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_reject_missing_extension() {
+        let mp4 = b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00isomiso2avc1mp41";
+
+        let validation = validate_video(
+            &temp_file("video", mp4),
+            Path::new("ffprobe"),
+            &DurationLimits::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validation.rejection, Some(Rejection::MissingExtension));
+        assert_eq!(validation.extension, None);
+        let reason = validation.reason.as_deref().unwrap();
+        assert!(reason.contains("has no extension") && reason.contains("mp4, avi, mov"));
+        let res = validation.rejection_response().unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let validation = validate_video(
+            &temp_file("video.txt", mp4),
+            Path::new("ffprobe"),
+            &DurationLimits::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validation.rejection, Some(Rejection::UnsupportedFormat));
+        assert_eq!(validation.extension.as_deref(), Some("txt"));
+        assert!(
+            validation
+                .reason
+                .as_deref()
+                .unwrap()
+                .starts_with("unsupported extension \"txt\"")
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_validate_image() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00\x01";
+
+        for (file_name, content) in [("frame.png", &png[..]), ("frame.jpeg", &jpeg[..])] {
+            let validation = validate_upload(
+                &temp_file(file_name, content),
+                Path::new("ffprobe"),
+                &DurationLimits::default(),
+            )
+            .await
+            .unwrap();
+            assert!(validation.accepted, "{}", file_name);
+            assert!(validation.is_image());
+        }
+
+        let validation = validate_upload(
+            &temp_file("frame.jpg", png),
+            Path::new("ffprobe"),
+            &DurationLimits::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(validation.rejection, Some(Rejection::UnsupportedFormat));
+        assert_eq!(validation.detected_content_type, Some("image/png"));
+        assert!(!validation.is_image());
+
+        // A video named as an image is an image that fails its checks.
+        let mp4 = b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00isomiso2avc1mp41";
+        let validation = validate_upload(
+            &temp_file("frame.png", mp4),
+            Path::new("ffprobe"),
+            &DurationLimits::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            validation.reason.as_deref(),
+            Some("the file content is not a valid png image (detected video/mp4)")
+        );
+    }
+
+    #[test]
+    fn test_parse_frame_rate() {
+        assert_eq!(parse_frame_rate("30/1"), Some(30.0));
+        assert_eq!(parse_frame_rate("30000/1001"), Some(30000.0 / 1001.0));
+        assert_eq!(parse_frame_rate("0/0"), None);
+        assert_eq!(parse_frame_rate("25"), None);
+    }
+
+    #[test]
+    fn test_duration_limits() {
+        let limits = DurationLimits {
+            min_sec: Some(5.0),
+            max_sec: Some(3600.0),
+        };
+        assert!(limits.check(5.0).is_none());
+        assert!(limits.check(3600.0).is_none());
+
+        let (rejection, reason) = limits.check(4.5).unwrap();
+        assert_eq!(rejection, Rejection::TooShort);
+        assert!(reason.contains("4.500") && reason.contains("5 seconds"));
+        let (rejection, reason) = limits.check(7200.0).unwrap();
+        assert_eq!(rejection, Rejection::TooLong);
+        assert_eq!(rejection.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(reason.contains("7200.000") && reason.contains("3600 seconds"));
+
+        assert!(DurationLimits::default().check(0.0).is_none());
+    }
+
     #[test]
     fn test_split_file_name() {
         assert_eq!(
@@ -45,4 +927,25 @@ mod tests {
             (Some(OsStr::new("secret.tar")), Some(OsStr::new("gz")))
         );
     }
+
+    #[test]
+    fn test_video_name() {
+        assert_eq!(video_name("clip.mp4").as_deref(), Some("clip"));
+        assert_eq!(video_name("/"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_video_name() {
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(
+            video_name(OsStr::from_bytes(b"cl\xffip.mp4")).as_deref(),
+            Some("cl\u{fffd}ip")
+        );
+        assert_eq!(
+            split_file_name(OsStr::from_bytes(b"cl\xffip.mp4")).1,
+            Some(OsStr::new("mp4"))
+        );
+    }
 }
@@ -0,0 +1,35 @@
+use crate::handlers::upload::{UploadConfig, reject_oversized_upload};
+use crate::handlers::utils;
+use actix_multipart::form::{MultipartForm, tempfile::TempFile};
+use actix_web::error::Error;
+use actix_web::middleware::from_fn;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{HttpResponse, Responder, post};
+use log;
+
+#[derive(Debug, MultipartForm)]
+#[multipart(deny_unknown_fields, duplicate_field = "deny")]
+struct ValidateForm {
+    file: TempFile,
+}
+
+/// Runs the same pre-checks as `POST /upload` on the uploaded video and reports what the backend
+/// detected, without analyzing the video.
+#[post("/validate", wrap = "from_fn(reject_oversized_upload)")]
+async fn validate_video(
+    config: web::Data<UploadConfig>,
+    MultipartForm(form): MultipartForm<ValidateForm>,
+) -> Result<impl Responder, Error> {
+    let validation =
+        utils::validate_upload(&form.file, config.ffprobe_path(), config.duration_limits()).await?;
+    log::info!(
+        "validated file: {:?}, accepted: {}",
+        validation.file_name,
+        validation.accepted
+    );
+    Ok(HttpResponse::Ok().json(validation))
+}
+
+pub fn config(cfg: &mut ServiceConfig) {
+    cfg.service(validate_video);
+}
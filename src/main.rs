@@ -1,72 +1,478 @@
+mod config;
+
 use actix_cors::Cors;
-use actix_multipart::form::MultipartFormConfig;
 use actix_multipart::form::tempfile::TempFileConfig;
+use actix_web::http::KeepAlive;
 use actix_web::{App, HttpServer, http, middleware, web};
-use clap::{Arg, Command, value_parser};
+use config::{Config, CorsMode};
 use env_logger::Env;
+use rustls::ServerConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use std::fs;
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::thread;
-use streameme_backend::analyzer::VideoAnalyzer;
-use streameme_backend::handlers;
+use std::time::Duration;
+use streameme_backend::analyzer::{InferenceModel, MemeType, VideoAnalyzer, VideoAnalyzerBuffer};
+use streameme_backend::handlers::{
+    self, ACCESS_LOG_TARGET, AuditLog, IdempotencyStore, JobLogs, LocalUploadConfig,
+    RemoteUploadConfig, StatsToken, TusStore, UploadConfig,
+};
 use tempfile::TempDir;
 
-const UPLOAD_SIZE_LIMIT: usize = 2 * 1024 * 1024 * 1024; // 2 GiB
+/// Resolves the inference directory to an absolute path once at startup, so that a missing
+/// directory stops the backend right away instead of failing every analysis.
+fn resolve_inference_dir(dir: &Path) -> io::Result<PathBuf> {
+    let resolved = fs::canonicalize(dir).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "the inference directory {} can't be resolved: {}; set it with --inference_dir",
+                dir.display(),
+                e
+            ),
+        )
+    })?;
+    if !resolved.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!(
+                "the inference directory {} is not a directory",
+                resolved.display()
+            ),
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Builds the inference models given by `--inference_model`, resolving their directories once at
+/// startup as the default one's. Their interpreters are `--python_bin` relative to their own
+/// directories.
+fn inference_models(config: &Config) -> io::Result<Vec<(String, InferenceModel)>> {
+    if let Some((name, _)) = config.inference_model_args.iter().find(|(name, _)| {
+        !config
+            .inference_models
+            .iter()
+            .any(|(other, _)| other == name)
+    }) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--inference_model_arg is given for {}, which isn't given by --inference_model",
+                name
+            ),
+        ));
+    }
+    let mut models: Vec<(String, InferenceModel)> = Vec::new();
+    for (name, dir) in &config.inference_models {
+        if models.iter().any(|(other, _)| other == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the inference model {} is given more than once", name),
+            ));
+        }
+        let dir = fs::canonicalize(dir).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "the directory {} of the inference model {} can't be resolved: {}",
+                    dir.display(),
+                    name,
+                    e
+                ),
+            )
+        })?;
+        log::info!("inference model {} runs {}", name, dir.display());
+        let mut model = InferenceModel::new(&dir).interpreter(dir.join(&config.python_bin));
+        for (_, arg) in config
+            .inference_model_args
+            .iter()
+            .filter(|(other, _)| other == name)
+        {
+            model = model.arg(arg);
+        }
+        models.push((name.clone(), model));
+    }
+    Ok(models)
+}
+
+/// Loads the certificate chain in the PEM file `cert` and its private key in the PEM file `key`
+/// once at startup, so that a broken certificate stops the backend right away with an error
+/// naming the file, instead of failing every TLS handshake.
+fn load_tls_config(cert: &Path, key: &Path) -> io::Result<ServerConfig> {
+    let invalid = |path: &Path, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the TLS file {} can't be loaded: {}",
+                path.display(),
+                message
+            ),
+        )
+    };
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(cert, e.to_string()))?;
+    if certs.is_empty() {
+        return Err(invalid(cert, String::from("no certificate found")));
+    }
+    let key_der = PrivateKeyDer::from_pem_file(key).map_err(|e| invalid(key, e.to_string()))?;
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key_der)
+        .map_err(|e| invalid(key, format!("it doesn't fit the certificate: {}", e)))
+}
+
+/// Writes the log records with `env_logger`, and also keeps the ones of the jobs in [`JobLogs`] for
+/// `GET /jobs/{id}/logs`.
+struct JobLogsLogger {
+    logger: env_logger::Logger,
+    job_logs: web::Data<JobLogs>,
+}
+
+impl log::Log for JobLogsLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.logger.matches(record) {
+            self.logger.log(record);
+            self.job_logs.capture(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+/// Initializes the logger. If `json` is true, every log record is written as a line of JSON, and
+/// the access log records, which are already JSON, are written as they are. If `job_logs` is
+/// given, the records of the jobs are also kept in it.
+fn init_logger(json: bool, job_logs: Option<web::Data<JobLogs>>) {
+    let mut builder = env_logger::Builder::from_env(Env::new().default_filter_or("info"));
+    if json {
+        builder.format(|buf, record| {
+            if record.target() == ACCESS_LOG_TARGET {
+                return writeln!(buf, "{}", record.args());
+            }
+            let line = serde_json::json!({
+                "time": buf.timestamp_millis().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    let Some(job_logs) = job_logs else {
+        builder.init();
+        return;
+    };
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(JobLogsLogger { logger, job_logs }))
+        .expect("the logger is initialized only once");
+}
+
+/// Builds the CORS middleware. Any origin is allowed if `origins` is empty.
+fn cors(origins: &[String], methods: &[http::Method], headers: &[String]) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(methods.iter().cloned())
+        .allowed_headers(headers.iter().map(String::as_str))
+        .expose_headers([
+            "location",
+            "tus-resumable",
+            "upload-length",
+            "upload-offset",
+            "x-estimated-wait",
+            "x-queue-depth",
+            "x-request-id",
+        ]);
+    if origins.is_empty() {
+        cors = cors.allow_any_origin();
+    }
+    for origin in origins {
+        cors = cors.allowed_origin(origin);
+    }
+    cors
+}
+
+/// Creates an analyzer configured by `config`, which creates its temporary directories in
+/// `tmp_dir`, and runs `models` when the tasks select them.
+fn video_analyzer(
+    config: &Config,
+    tmp_dir: &Path,
+    models: Vec<(String, InferenceModel)>,
+) -> (VideoAnalyzer, VideoAnalyzerBuffer) {
+    let (analyzer, analyzer_buf) = VideoAnalyzer::new(config.inference_dir.clone());
+    let mut analyzer = analyzer
+        .interpreter(&config.python_bin)
+        .output_file_name(&config.output_file_name)
+        .temp_dir(tmp_dir)
+        .video_name_template(config.video_name_template.clone())
+        .dedup_tolerance_sec(config.dedup_tolerance_sec)
+        .stderr_tail_lines(config.stderr_tail_lines)
+        .meme_types(config.meme_types.iter().copied());
+    for (key, value) in &config.inference_env {
+        analyzer = analyzer.env(key, value.expose());
+    }
+    for (name, model) in models {
+        analyzer = analyzer.model(name, model);
+    }
+    if let Some(dir) = &config.keep_inference_output {
+        analyzer = analyzer.keep_inference_output(dir);
+    }
+    if let Some(timeout) = config.idle_timeout_sec {
+        analyzer = analyzer.idle_timeout(Duration::from_secs(timeout));
+    }
+    if config.partial_results {
+        analyzer = analyzer.partial_results();
+    }
+    if let Some(memory_limit_mib) = config.memory_limit_mib {
+        analyzer = analyzer.memory_limit(memory_limit_mib.saturating_mul(1024 * 1024));
+    }
+    (analyzer, analyzer_buf)
+}
+
+/// Creates the configuration of the uploads set by `config`, whose free space is checked on the
+/// volume of `tmp_dir`.
+fn upload_config(config: &Config, tmp_dir: &Path) -> UploadConfig {
+    let mut upload_config = UploadConfig::new(config.upload_size_limit)
+        .timestamp_precision(config.timestamp_precision)
+        .timestamp_format(config.timestamp_format)
+        .metadata_limit(config.metadata_limit)
+        .ffprobe_bin(&config.ffprobe_bin);
+    for (name, _) in &config.inference_models {
+        upload_config = upload_config.allowed_model(name);
+    }
+    if let Some(max_concurrent_uploads) = config.max_concurrent_uploads {
+        upload_config = upload_config.max_concurrent_uploads(max_concurrent_uploads);
+    }
+    if let Some(max_request_duration_sec) = config.max_request_duration_sec {
+        upload_config =
+            upload_config.max_request_duration(Duration::from_secs(max_request_duration_sec));
+    }
+    if let Some(min_duration_sec) = config.min_duration_sec {
+        upload_config = upload_config.min_duration_sec(min_duration_sec);
+    }
+    if let Some(max_duration_sec) = config.max_duration_sec {
+        upload_config = upload_config.max_duration_sec(max_duration_sec);
+    }
+    match config.max_suggestions {
+        0 => {}
+        max_suggestions => upload_config = upload_config.max_suggestions(max_suggestions),
+    }
+    if let Some(token) = &config.raw_output_token {
+        upload_config = upload_config.raw_output_token(token.expose());
+    }
+    if let Some(min_free_space_mib) = config.min_free_space_mib {
+        upload_config =
+            upload_config.min_free_space(tmp_dir, min_free_space_mib.saturating_mul(1024 * 1024));
+    }
+    upload_config
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(Env::new().default_filter_or("info"));
-
-    let matches = Command::new("streameme_backend")
-        .arg(
-            Arg::new("port")
-                .help("The port to listen on")
-                .short('p')
-                .long("port")
-                .value_parser(value_parser!(u16))
-                .default_value("9090"),
-        )
-        .arg(
-            Arg::new("inference_dir")
-                .help("The root directory of streameme_inference project")
-                .long("inference_dir")
-                .value_parser(value_parser!(PathBuf))
-                .default_value("../streameme_inference"),
-        )
-        .get_matches();
-    let port = *matches.get_one::<u16>("port").unwrap();
-    let inference_dir = matches.get_one::<PathBuf>("inference_dir").unwrap();
-    let inference_dir = fs::canonicalize(inference_dir)?;
+    let mut config = Config::parse();
+    let job_logs = config
+        .job_logs_token
+        .as_ref()
+        .map(|token| web::Data::new(JobLogs::new(token.expose())));
+    init_logger(config.json_log, job_logs.clone());
+    config.inference_dir = resolve_inference_dir(&config.inference_dir)?;
+    let inference_models = inference_models(&config)?;
+    config.python_bin = config.inference_dir.join(&config.python_bin);
+    log::info!("effective configuration: {}", config);
+    let tls_config = config
+        .tls
+        .as_ref()
+        .map(|(cert, key)| load_tls_config(cert, key))
+        .transpose()?;
+
+    // Create a temporary directory. This is for the purpose of storing uploaded videos and
+    // communicating with the inference script. The temporary directory is deleted automatically
+    // when the `TempDir` instance is dropped.
+    let tmp_dir = Arc::new(TempDir::new_in(".")?);
+    let tmp_dir_2 = tmp_dir.clone();
 
     // Initialize an analyzer on another thread, and setup a channel for queueing analysis requests.
-    let (analyzer, analyzer_buf) = VideoAnalyzer::new(inference_dir);
+    let (analyzer, analyzer_buf) = video_analyzer(&config, tmp_dir.path(), inference_models);
+    let disabled_meme_types: Vec<String> = MemeType::ALL
+        .into_iter()
+        .filter(|meme_type| !config.meme_types.contains(meme_type))
+        .map(|meme_type| meme_type.to_string())
+        .collect();
+    if !disabled_meme_types.is_empty() {
+        log::info!(
+            "suggestions of these meme types are disabled: {}",
+            disabled_meme_types.join(", ")
+        );
+    }
+    let mut readiness_probe = analyzer.readiness_probe();
+    // Every analysis would fail without these files, so it's better to fail now where it's
+    // obvious than deep in the first analysis.
+    if let Err(failure) = readiness_probe.check_files() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "the inference procedure can't run ({} check failed): {}",
+                failure.check, failure.message
+            ),
+        ));
+    }
+    if let Some(timeout) = config.ready_help_timeout_sec {
+        readiness_probe = readiness_probe.help_timeout(Duration::from_secs(timeout));
+    }
+    let readiness_probe = web::Data::new(readiness_probe);
     thread::spawn(move || {
         analyzer.run();
     });
     let analyzer = web::Data::new(analyzer_buf);
 
-    // Create a temporary directory. This is for the purpose of storing uploaded videos and
-    // communicating with the inference script. The temporary directory is deleted automatically
-    // when the `TempDir` instance is dropped.
-    let tmp_dir = Arc::new(TempDir::new_in(".")?);
-    let tmp_dir_2 = tmp_dir.clone();
-    HttpServer::new(move || {
+    let upload_config = web::Data::new(upload_config(&config, tmp_dir.path()));
+    let audit_log = config
+        .audit_log
+        .as_ref()
+        .map(AuditLog::open)
+        .transpose()?
+        .map(|audit_log| match &config.audit_token {
+            Some(token) => audit_log.token(token.expose()),
+            None => audit_log,
+        })
+        .map(web::Data::new);
+    let local_upload_config = match &config.analyze_local {
+        Some((root, token)) => {
+            let root = fs::canonicalize(root).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "the root of /analyze-local {} can't be resolved: {}",
+                        root.display(),
+                        e
+                    ),
+                )
+            })?;
+            log::info!("/analyze-local serves the files under {}", root.display());
+            Some(web::Data::new(LocalUploadConfig::new(
+                root,
+                tmp_dir.path(),
+                token.expose(),
+            )))
+        }
+        None => None,
+    };
+    let stats_token = config
+        .stats_token
+        .as_ref()
+        .map(|token| web::Data::new(StatsToken::new(token.expose())));
+    let idempotency_store = (config.idempotency_ttl_sec > 0).then(|| {
+        web::Data::new(IdempotencyStore::new(Duration::from_secs(
+            config.idempotency_ttl_sec,
+        )))
+    });
+    let tus_store = web::Data::new(
+        TusStore::new(tmp_dir.path().to_path_buf()).max_uploads(config.max_resumable_uploads),
+    );
+    let mut remote_upload_config =
+        RemoteUploadConfig::new(tmp_dir.path()).allowed_schemes(config.remote_url_schemes.iter());
+    for host in &config.remote_url_hosts {
+        remote_upload_config = remote_upload_config.allowed_host(host);
+    }
+    let remote_upload_config = web::Data::new(remote_upload_config);
+    let config = Arc::new(config);
+    let config_2 = config.clone();
+    let server = HttpServer::new(move || {
         let path = tmp_dir_2.path();
+        let config = &config_2;
         App::new()
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allowed_methods([http::Method::POST]),
-            )
-            .wrap(middleware::Logger::default())
+            // Videos and images aren't compressed, so this only applies to the other responses,
+            // such as the suggestions in JSON.
+            .wrap(middleware::Condition::new(
+                config.response_compression,
+                middleware::Compress::default(),
+            ))
+            .wrap(middleware::Condition::new(
+                !matches!(config.cors, CorsMode::Disabled),
+                cors(
+                    config.cors.origins(),
+                    &config.cors_methods,
+                    &config.cors_headers,
+                ),
+            ))
+            .wrap(middleware::Condition::new(
+                !config.json_log,
+                middleware::Logger::default(),
+            ))
+            .wrap(middleware::Condition::new(
+                config.json_log,
+                middleware::from_fn(handlers::json_access_log),
+            ))
             .app_data(TempFileConfig::default().directory(path))
-            .app_data(MultipartFormConfig::default().total_limit(UPLOAD_SIZE_LIMIT))
+            .app_data(upload_config.multipart_form_config())
+            .app_data(web::Data::clone(&upload_config))
             .app_data(web::Data::clone(&analyzer))
-            .configure(handlers::config)
+            .app_data(web::Data::clone(&tus_store))
+            .app_data(web::Data::clone(&remote_upload_config))
+            .app_data(web::Data::clone(&readiness_probe))
+            .configure(|cfg| {
+                if let Some(audit_log) = &audit_log {
+                    cfg.app_data(web::Data::clone(audit_log));
+                }
+                if let Some(idempotency_store) = &idempotency_store {
+                    cfg.app_data(web::Data::clone(idempotency_store));
+                }
+                if let Some(stats_token) = &stats_token {
+                    cfg.app_data(web::Data::clone(stats_token));
+                }
+                if let Some(local_upload_config) = &local_upload_config {
+                    cfg.app_data(web::Data::clone(local_upload_config));
+                }
+                if let Some(job_logs) = &job_logs {
+                    cfg.app_data(web::Data::clone(job_logs));
+                }
+            })
+            .service(web::scope(&config.path_prefix).configure(handlers::config))
+            .configure(|cfg| {
+                if config.ready_at_root && !config.path_prefix.is_empty() {
+                    handlers::probe_config(cfg);
+                }
+            })
     })
-    .bind((Ipv4Addr::UNSPECIFIED, port))?
-    .run()
-    .await
+    .workers(config.workers)
+    .client_request_timeout(Duration::from_secs(config.client_request_timeout_sec))
+    .keep_alive(match config.keep_alive_sec {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+    })
+    .shutdown_timeout(config.shutdown_timeout_sec);
+    // HTTP/2 is negotiated with ALPN, so it's only served over TLS.
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23((config.host, config.port), tls_config)?,
+        None => server.bind((config.host, config.port))?,
+    };
+    let res = server.run().await;
+
+    // The server stops gracefully on SIGINT and SIGTERM, but the uploads still being analyzed when
+    // the shutdown timeout expires are cut off, and the analyzer thread may still be using the
+    // temporary directory. So it's removed explicitly rather than when the last reference to it
+    // is dropped, which may never happen before the process exits.
+    log::info!(
+        "removing the temporary directory {}",
+        tmp_dir.path().display()
+    );
+    if let Err(e) = fs::remove_dir_all(tmp_dir.path())
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        log::error!(
+            "failed to remove the temporary directory {}: {}",
+            tmp_dir.path().display(),
+            e
+        );
+    }
+    res
 }
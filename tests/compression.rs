@@ -0,0 +1,82 @@
+//! Tests of how the server binary compresses its responses.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// Creates an inference directory whose files pass the startup checks.
+fn inference_dir(work_dir: &Path) -> PathBuf {
+    let inference_dir = work_dir.join("inference");
+    fs::create_dir_all(inference_dir.join(".venv/bin")).unwrap();
+    let python = inference_dir.join(".venv/bin/python");
+    fs::write(&python, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&python, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(inference_dir.join("inference.py"), "").unwrap();
+    inference_dir
+}
+
+/// Starts the server with `args`, and returns the head of its response to `GET /openapi.json`
+/// accepting gzip, in lowercase.
+fn openapi_response_head(args: &[&str]) -> String {
+    let work_dir = TempDir::new().unwrap();
+    let inference_dir = inference_dir(work_dir.path());
+    let port = free_port();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_streameme_backend"))
+        .current_dir(work_dir.path())
+        .args(["--port", &port.to_string()])
+        .arg("--inference_dir")
+        .arg(&inference_dir)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .expect("the server didn't start listening");
+
+    stream
+        .write_all(
+            b"GET /openapi.json HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let head_len = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("the response has no head");
+    String::from_utf8_lossy(&response[..head_len]).to_lowercase()
+}
+
+#[test]
+fn test_response_compression() {
+    let head = openapi_response_head(&[]);
+    assert!(head.starts_with("http/1.1 200"), "{}", head);
+    assert!(head.contains("\r\ncontent-encoding: gzip"), "{}", head);
+
+    let head = openapi_response_head(&["--no_response_compression"]);
+    assert!(head.starts_with("http/1.1 200"), "{}", head);
+    assert!(!head.contains("content-encoding"), "{}", head);
+}
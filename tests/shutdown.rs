@@ -0,0 +1,104 @@
+//! Tests of how the server binary shuts down on a signal.
+
+use std::fs;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const BOUNDARY: &str = "streameme-test-boundary";
+
+/// The leading bytes of an MP4 file, which are enough to pass the magic bytes check.
+const MP4_HEADER: &[u8] = b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00isomiso2avc1mp41";
+
+/// Stands in for `.venv/bin/python`, taking longer than the shutdown timeout of the tests.
+const SLOW_PYTHON: &str = "#!/bin/sh\nsleep 5\n";
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_for_exit(child: &mut Child, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if child.try_wait().unwrap().is_some() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+#[test]
+fn test_sigterm_removes_temp_dir() {
+    let work_dir = TempDir::new().unwrap();
+    let inference_dir = work_dir.path().join("inference");
+    fs::create_dir_all(inference_dir.join(".venv/bin")).unwrap();
+    let python = inference_dir.join(".venv/bin/python");
+    fs::write(&python, SLOW_PYTHON).unwrap();
+    fs::set_permissions(&python, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(inference_dir.join("inference.py"), "").unwrap();
+
+    let port = free_port();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_streameme_backend"))
+        .current_dir(work_dir.path())
+        .args(["--port", &port.to_string(), "--shutdown_timeout_sec", "1"])
+        .arg("--inference_dir")
+        .arg(&inference_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .expect("the server didn't start listening");
+
+    // Leave an upload being analyzed when the signal arrives.
+    let mut body = format!(
+        "--{BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"metadata\"\r\n\
+         Content-Type: application/json\r\n\r\n\
+         {{\"mode\": 1}}\r\n\
+         --{BOUNDARY}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"video.mp4\"\r\n\
+         Content-Type: video/mp4\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(MP4_HEADER);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+    write!(
+        stream,
+        "POST /upload HTTP/1.1\r\n\
+         Host: 127.0.0.1\r\n\
+         Content-Type: multipart/form-data; boundary={BOUNDARY}\r\n\
+         Content-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(&body).unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    let status = Command::new("kill")
+        .args(["-TERM", &server.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(wait_for_exit(&mut server, Duration::from_secs(10)));
+
+    let entries: Vec<_> = fs::read_dir(work_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(entries, ["inference"]);
+}
@@ -0,0 +1,112 @@
+//! Tests of how the server binary terminates TLS.
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+/// Creates an inference directory whose files pass the startup checks.
+fn inference_dir(work_dir: &Path) -> PathBuf {
+    let inference_dir = work_dir.join("inference");
+    fs::create_dir_all(inference_dir.join(".venv/bin")).unwrap();
+    let python = inference_dir.join(".venv/bin/python");
+    fs::write(&python, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&python, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(inference_dir.join("inference.py"), "").unwrap();
+    inference_dir
+}
+
+#[test]
+fn test_tls_negotiates_h2() {
+    let work_dir = TempDir::new().unwrap();
+    let inference_dir = inference_dir(work_dir.path());
+    let port = free_port();
+    let mut server = Command::new(env!("CARGO_BIN_EXE_streameme_backend"))
+        .current_dir(work_dir.path())
+        .args(["--port", &port.to_string()])
+        .arg("--inference_dir")
+        .arg(&inference_dir)
+        .arg("--tls_cert")
+        .arg(fixture("cert.pem"))
+        .arg("--tls_key")
+        .arg(fixture("key.pem"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stream = (0..100)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| thread::sleep(Duration::from_millis(50)))
+                .ok()
+        })
+        .expect("the server didn't start listening");
+
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(fixture("ca.pem")).unwrap() {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let mut client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let connection = ClientConnection::new(Arc::new(client_config), server_name).unwrap();
+    let mut tls = StreamOwned::new(connection, stream);
+    // The HTTP/2 connection preface completes the handshake, and the server answers with its
+    // SETTINGS frame.
+    tls.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+    tls.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).unwrap();
+    let mut frame_header = [0; 9];
+    tls.read_exact(&mut frame_header).unwrap();
+    let alpn_protocol = tls.conn.alpn_protocol().map(<[u8]>::to_vec);
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+    assert_eq!(alpn_protocol.as_deref(), Some(&b"h2"[..]));
+    // A SETTINGS frame.
+    assert_eq!(frame_header[3], 4);
+}
+
+#[test]
+fn test_tls_invalid_key() {
+    let work_dir = TempDir::new().unwrap();
+    let inference_dir = inference_dir(work_dir.path());
+    let output = Command::new(env!("CARGO_BIN_EXE_streameme_backend"))
+        .current_dir(work_dir.path())
+        .args(["--port", &free_port().to_string()])
+        .arg("--inference_dir")
+        .arg(&inference_dir)
+        .arg("--tls_cert")
+        .arg(fixture("cert.pem"))
+        .arg("--tls_key")
+        .arg(fixture("ca.pem"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ca.pem"), "{}", stderr);
+}
@@ -0,0 +1,1311 @@
+//! End-to-end tests of `POST /upload` and its companion endpoints, running the whole upload →
+//! analyze → response flow against a fake inference procedure.
+//!
+//! The fake inference procedure and `ffprobe` are shell scripts, so these only run on Unix.
+#![cfg(unix)]
+
+use actix_multipart::form::tempfile::TempFileConfig;
+use actix_web::http::StatusCode;
+use actix_web::http::header;
+use actix_web::{App, HttpResponse, HttpServer, test, web};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use streameme_backend::analyzer::{
+    InferenceModel, MemeType, StubInference, VideoAnalyzer, VideoAnalyzerClient, VideoAnalyzerMode,
+    VideoAnalyzerOutput, VideoAnalyzerSuggestion,
+};
+use streameme_backend::handlers::{
+    self, IdempotencyStore, LocalUploadConfig, RemoteUploadConfig, TusStore, UploadConfig,
+};
+use tempfile::TempDir;
+
+const BOUNDARY: &str = "streameme-test-boundary";
+
+/// The bearer token of `/analyze-local`, whose root is the temporary directory of the app.
+const LOCAL_TOKEN: &str = "local-token";
+const RAW_TOKEN: &str = "raw-token";
+
+/// The leading bytes of an MP4 file, which are enough to pass the magic bytes check.
+const MP4_HEADER: &[u8] = b"\x00\x00\x00\x20ftypisom\x00\x00\x02\x00isomiso2avc1mp41";
+
+/// The leading bytes of a PNG file, which are enough to pass the magic bytes check.
+const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+/// Stands in for `.venv/bin/python`. It ignores the inference script, reports a warning, and writes
+/// fixed results to the output directory. `STREAMEME_TEST_ENV` is reported as another warning if
+/// set, and so are `--preview`, `--image` and `--fps` if given.
+const FAKE_PYTHON: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --output_dir) out="$2"; shift ;;
+        --preview) preview=1 ;;
+        --image) image=1 ;;
+        --fps) fps="$2"; shift ;;
+    esac
+    shift
+done
+echo "loading model..." >&2
+echo "WARN frame rate too low" >&2
+if [ -n "$preview" ]; then
+    echo "WARN preview requested" >&2
+fi
+if [ -n "$image" ]; then
+    echo "WARN image requested" >&2
+fi
+if [ -n "$fps" ]; then
+    echo "WARN sampling $fps fps" >&2
+fi
+if [ -n "$STREAMEME_TEST_ENV" ]; then
+    echo "WARN STREAMEME_TEST_ENV=$STREAMEME_TEST_ENV" >&2
+fi
+cat > "$out/suggestions.json" <<'EOF'
+[
+    {"start": 30, "end": 60, "suggestion": "sorrow"},
+    {"start": 90, "end": 100, "suggestion": "boredom"},
+    {"start": 120, "end": 150, "suggestion": "anger"}
+]
+EOF
+"#;
+
+/// Stands in for `ffprobe`, reporting every video to last 200 seconds at 24 frames per second.
+const FAKE_FFPROBE: &str = r#"#!/bin/sh
+echo '{"streams": [{"avg_frame_rate": "24/1"}], "format": {"duration": "200.000000"}}'
+"#;
+
+fn write_script(path: &Path, content: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Creates a fake `streameme_inference` project.
+fn fake_inference_dir() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    write_script(&dir.path().join(".venv/bin/python"), FAKE_PYTHON);
+    fs::write(dir.path().join("inference.py"), "").unwrap();
+    dir
+}
+
+/// Writes a fake `ffprobe` under `dir`, and returns its path to be given to
+/// [`UploadConfig::ffprobe_bin`], so that the tests don't depend on the tools installed on the
+/// host.
+fn fake_ffprobe(dir: &Path) -> PathBuf {
+    let path = dir.join("bin/ffprobe");
+    write_script(&path, FAKE_FFPROBE);
+    path
+}
+
+fn multipart_body(metadata: &str, file_name: &str, video: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{BOUNDARY}\r\n\
+             Content-Disposition: form-data; name=\"metadata\"\r\n\
+             Content-Type: application/json\r\n\r\n\
+             {metadata}\r\n\
+             --{BOUNDARY}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\
+             Content-Type: video/mp4\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(video);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+    body
+}
+
+fn upload_request(metadata: &str, file_name: &str, video: &[u8]) -> test::TestRequest {
+    test::TestRequest::post()
+        .uri("/upload")
+        .insert_header((
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        ))
+        .set_payload(multipart_body(metadata, file_name, video))
+}
+
+macro_rules! init_app {
+    ($inference_dir:expr, $tmp_dir:expr) => {
+        init_app!($inference_dir, $tmp_dir, |analyzer| analyzer)
+    };
+    ($inference_dir:expr, $tmp_dir:expr, $configure_analyzer:expr) => {
+        init_app!($inference_dir, $tmp_dir, $configure_analyzer, |config| {
+            config
+        })
+    };
+    ($inference_dir:expr, $tmp_dir:expr, $configure_analyzer:expr, $configure_upload:expr) => {
+        init_app!(
+            $inference_dir,
+            $tmp_dir,
+            $configure_analyzer,
+            $configure_upload,
+            |remote| remote
+        )
+    };
+    (
+        $inference_dir:expr,
+        $tmp_dir:expr,
+        $configure_analyzer:expr,
+        $configure_upload:expr,
+        $configure_remote:expr
+    ) => {{
+        let (analyzer, analyzer_buf) = VideoAnalyzer::new($inference_dir.path().to_path_buf());
+        let analyzer = $configure_analyzer(analyzer);
+        let readiness_probe = analyzer.readiness_probe();
+        thread::spawn(move || analyzer.run());
+        let upload_config: UploadConfig = $configure_upload(
+            UploadConfig::new(1024 * 1024).ffprobe_bin(fake_ffprobe($inference_dir.path())),
+        );
+        let upload_config = web::Data::new(upload_config);
+        test::init_service(
+            App::new()
+                .app_data(TempFileConfig::default().directory($tmp_dir.path()))
+                .app_data(upload_config.multipart_form_config())
+                .app_data(upload_config)
+                .app_data(web::Data::new(analyzer_buf))
+                .app_data(web::Data::new(readiness_probe))
+                .app_data(web::Data::new(TusStore::new($tmp_dir.path().to_path_buf())))
+                .app_data(web::Data::new($configure_remote(
+                    RemoteUploadConfig::new($tmp_dir.path()).allowed_schemes(["http"]),
+                )))
+                .app_data(web::Data::new(IdempotencyStore::new(Duration::from_secs(
+                    60,
+                ))))
+                .app_data(web::Data::new(LocalUploadConfig::new(
+                    $tmp_dir.path(),
+                    $tmp_dir.path(),
+                    LOCAL_TOKEN,
+                )))
+                .configure(handlers::config),
+        )
+        .await
+    }};
+}
+
+#[actix_web::test]
+async fn test_upload_video() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let request_id = res.headers().get("x-request-id").unwrap().clone();
+    // The task has been analyzed, so it's no longer in the queue.
+    assert_eq!(res.headers().get("x-queue-depth").unwrap(), "0");
+    assert_eq!(res.headers().get("x-estimated-wait").unwrap(), "0");
+
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["id"], request_id.to_str().unwrap());
+    assert_eq!(body["file_name"], "video.mp4");
+    assert_eq!(body["analyze_mode"], "multi");
+    assert_eq!(body["quality"], "full");
+    assert!(body["analyze_time"].is_string());
+    // The timestamps are in the same format, so they are ordered as strings too.
+    let received_at = body["upload_received_at"].as_str().unwrap();
+    let started_at = body["analysis_started_at"].as_str().unwrap();
+    let finished_at = body["analysis_finished_at"].as_str().unwrap();
+    assert!(received_at <= started_at && started_at <= finished_at);
+    assert!(body["queue_wait_ms"].is_u64());
+    assert!(body["inference_ms"].is_u64());
+    assert_eq!(
+        body["suggestions"],
+        serde_json::json!([
+            {"start": 30, "end": 60, "start_frame": 720, "end_frame": 1440, "meme_type": 3, "meme_type_desc": "sorrow"},
+            {"start": 120, "end": 150, "start_frame": 2880, "end_frame": 3600, "meme_type": 2, "meme_type_desc": "anger"},
+        ])
+    );
+    assert_eq!(
+        body["summary"]["sorrow"],
+        serde_json::json!({"count": 1, "total_seconds": 30})
+    );
+    assert_eq!(body["time_unit"], "s");
+    assert_eq!(body["analysis_status"], "ok");
+    assert_eq!(body["warnings"], serde_json::json!(["frame rate too low"]));
+}
+
+#[actix_web::test]
+async fn test_upload_video_time_unit() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?unit=ms")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["time_unit"], "ms");
+    // Only the boundaries are in milliseconds.
+    assert_eq!(
+        body["suggestions"][0],
+        serde_json::json!({
+            "start": 30000,
+            "end": 60000,
+            "start_frame": 720,
+            "end_frame": 1440,
+            "meme_type": 3,
+            "meme_type_desc": "sorrow",
+        })
+    );
+    assert_eq!(
+        body["summary"]["sorrow"],
+        serde_json::json!({"count": 1, "total_seconds": 30})
+    );
+
+    let req = upload_request(r#"{"modes": [1, 0]}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?unit=ms")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analyses"]["multi"]["time_unit"], "ms");
+    assert_eq!(body["analyses"]["multi"]["suggestions"][0]["start"], 30000);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?unit=min")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_upload_video_nested_shape() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let metadata = r#"{"mode": 1, "response_shape": "nested"}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(
+        body["analyze_mode"],
+        serde_json::json!({"id": 1, "key": "multi"})
+    );
+    assert_eq!(
+        body["suggestions"][0],
+        serde_json::json!({
+            "start": 30,
+            "end": 60,
+            "start_frame": 720,
+            "end_frame": 1440,
+            "meme_type": {"id": 3, "key": "sorrow"},
+        })
+    );
+    assert!(body["warnings"].is_array());
+}
+
+#[actix_web::test]
+async fn test_upload_video_modes() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let metadata = r#"{"modes": [1, 0]}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = test::read_body(res).await;
+    let text = std::str::from_utf8(&body).unwrap();
+    // The modes are in the requested order, which a parsed map would lose.
+    assert!(text.find(r#""multi":{"#).unwrap() < text.find(r#""binary":{"#).unwrap());
+    let body: serde_json::Value = serde_json::from_str(text).unwrap();
+    assert_eq!(body["file_name"], "video.mp4");
+    assert!(body["upload_received_at"].is_string());
+    assert!(body.get("suggestions").is_none());
+    let analyses = body["analyses"].as_object().unwrap();
+    assert_eq!(analyses.len(), 2);
+    for (mode, analysis) in analyses {
+        assert_eq!(analysis["status"], "ok");
+        assert_eq!(&analysis["analyze_mode"], mode);
+        assert!(analysis["analysis_started_at"].is_string());
+        assert_eq!(analysis["suggestions"].as_array().unwrap().len(), 2);
+        assert_eq!(analysis["summary"]["anger"]["count"], 1);
+        assert_eq!(
+            analysis["warnings"],
+            serde_json::json!(["frame rate too low"])
+        );
+    }
+}
+
+#[actix_web::test]
+async fn test_upload_video_preview() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let metadata = r#"{"mode": 1, "quality": "preview"}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["quality"], "preview");
+    assert_eq!(
+        body["warnings"],
+        serde_json::json!(["frame rate too low", "preview requested"])
+    );
+
+    let metadata = r#"{"mode": 1, "quality": "draft"}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_upload_video_fps_sample() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let metadata = r#"{"mode": 1, "fps_sample": 2.5}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["fps_sample"], 2.5);
+    assert_eq!(
+        body["warnings"],
+        serde_json::json!(["frame rate too low", "sampling 2.5 fps"])
+    );
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["fps_sample"], serde_json::Value::Null);
+    assert_eq!(body["warnings"], serde_json::json!(["frame rate too low"]));
+
+    let metadata = r#"{"mode": 1, "fps_sample": 0}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let metadata = r#"{"mode": 1, "fps_sample": 2}"#;
+    let req = upload_request(metadata, "image.png", PNG_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "invalid_fps_sample");
+}
+
+#[actix_web::test]
+async fn test_upload_video_msgpack() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .insert_header((header::ACCEPT, "application/msgpack"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/msgpack"
+    );
+
+    let body = test::read_body(res).await;
+    let body: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(body["file_name"], "video.mp4");
+    assert!(body["id"].is_string());
+    assert!(body["analyze_time"].is_string());
+    assert_eq!(body["suggestions"][1]["meme_type_desc"], "anger");
+}
+
+#[actix_web::test]
+async fn test_upload_video_stub_inference() {
+    // No inference procedure is needed with the stub.
+    let inference_dir = TempDir::new().unwrap();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let stub = StubInference::new()
+        .output(VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(10, 20, MemeType::Love),
+        ]))
+        .output(VideoAnalyzerOutput::default());
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| analyzer
+        .backend(stub));
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body["suggestions"],
+        serde_json::json!([
+            {"start": 10, "end": 20, "start_frame": 240, "end_frame": 480, "meme_type": 1, "meme_type_desc": "love"},
+        ])
+    );
+    assert_eq!(body["analysis_status"], "ok");
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["suggestions"], serde_json::Value::Null);
+    assert_eq!(body["analysis_status"], "failed");
+}
+
+#[actix_web::test]
+async fn test_upload_video_idempotency_key() {
+    let inference_dir = TempDir::new().unwrap();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let stub = StubInference::new()
+        .output(VideoAnalyzerOutput::from_iter([
+            VideoAnalyzerSuggestion::new(10, 20, MemeType::Love),
+        ]))
+        .output(VideoAnalyzerOutput::default());
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| analyzer
+        .backend(stub));
+    let request = || {
+        upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+            .insert_header(("Idempotency-Key", "retry-me"))
+            .to_request()
+    };
+
+    let res = test::call_service(&app, request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("idempotent-replayed").is_none());
+    let request_id = res.headers().get("x-request-id").unwrap().clone();
+    let body = test::read_body(res).await;
+
+    // The retry gets the same response without analyzing the video again.
+    let res = test::call_service(&app, request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("idempotent-replayed").unwrap(), "true");
+    assert_eq!(res.headers().get("x-request-id").unwrap(), &request_id);
+    assert_eq!(test::read_body(res).await, body);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analysis_status"], "failed");
+}
+
+#[actix_web::test]
+async fn test_upload_video_flatten() {
+    let inference_dir = TempDir::new().unwrap();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let stub = StubInference::new().output(VideoAnalyzerOutput::from_iter([
+        VideoAnalyzerSuggestion::new(0, 30, MemeType::Sorrow),
+        VideoAnalyzerSuggestion::new(10, 20, MemeType::Love),
+    ]));
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| analyzer
+        .backend(stub));
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?flatten=true")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let spans: Vec<_> = body["suggestions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            (
+                s["start"].clone(),
+                s["end"].clone(),
+                s["meme_type_desc"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        spans,
+        [
+            (0.into(), 10.into(), "sorrow".into()),
+            (10.into(), 20.into(), "love".into()),
+            (20.into(), 30.into(), "sorrow".into()),
+        ]
+    );
+    // The frame indices are of the flattened boundaries.
+    assert_eq!(body["suggestions"][2]["start_frame"], 480);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn test_upload_video_group_by() {
+    let inference_dir = TempDir::new().unwrap();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let stub = StubInference::new().output(VideoAnalyzerOutput::from_iter([
+        VideoAnalyzerSuggestion::new(0, 30, MemeType::Sorrow),
+        VideoAnalyzerSuggestion::new(10, 20, MemeType::Love),
+        VideoAnalyzerSuggestion::new(40, 50, MemeType::Sorrow),
+    ]));
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| analyzer
+        .backend(stub));
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?group_by=type")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analyze_mode"], "multi");
+    assert_eq!(
+        body["suggestions"],
+        serde_json::json!({
+            "love": [{"start": 10, "end": 20, "start_frame": 240, "end_frame": 480}],
+            "sorrow": [
+                {"start": 0, "end": 30, "start_frame": 0, "end_frame": 720},
+                {"start": 40, "end": 50, "start_frame": 960, "end_frame": 1200},
+            ],
+        })
+    );
+
+    let metadata = r#"{"modes": [1], "response_shape": "nested"}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER)
+        .uri("/upload?group_by=type&unit=ms")
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let multi = &body["analyses"]["multi"];
+    assert_eq!(multi["analyze_mode"]["key"], "multi");
+    assert_eq!(multi["suggestions"]["love"][0]["start"], 10000);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?group_by=mode")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_analyze_local() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+    fs::create_dir(tmp_dir.path().join("batch")).unwrap();
+    let video_path = tmp_dir.path().join("batch/video.mp4");
+    fs::write(&video_path, MP4_HEADER).unwrap();
+    fs::write(tmp_dir.path().join("batch/notes.txt"), "notes").unwrap();
+    let request = |body: serde_json::Value, token: &str| {
+        test::TestRequest::post()
+            .uri("/analyze-local")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .set_json(body)
+            .to_request()
+    };
+
+    let req = request(
+        serde_json::json!({"path": "batch/video.mp4", "mode": 1}),
+        LOCAL_TOKEN,
+    );
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["file_name"], "video.mp4");
+    assert_eq!(body["suggestions"][0]["start"], 30);
+    // The file is left in place, and its link is removed.
+    assert!(video_path.exists());
+    let links = fs::read_dir(tmp_dir.path())
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with("local-")
+        })
+        .count();
+    assert_eq!(links, 0);
+
+    let cases = [
+        ("batch/video.mp4", "wrong-token", StatusCode::UNAUTHORIZED),
+        ("../video.mp4", LOCAL_TOKEN, StatusCode::FORBIDDEN),
+        ("/etc/hostname", LOCAL_TOKEN, StatusCode::FORBIDDEN),
+        ("batch/missing.mp4", LOCAL_TOKEN, StatusCode::NOT_FOUND),
+        (
+            "batch/notes.txt",
+            LOCAL_TOKEN,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ),
+    ];
+    for (path, token, status) in cases {
+        let req = request(serde_json::json!({"path": path, "mode": 1}), token);
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), status, "{}", path);
+    }
+}
+
+#[actix_web::test]
+async fn test_upload_image() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "frame.png", PNG_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        body["suggestions"],
+        serde_json::json!([
+            {"start": 0, "end": 0, "meme_type": 3, "meme_type_desc": "sorrow"}
+        ])
+    );
+    assert_eq!(
+        body["warnings"],
+        serde_json::json!(["frame rate too low", "image requested"])
+    );
+
+    let req =
+        upload_request(r#"{"mode": 1, "start_sec": 10}"#, "frame.png", PNG_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "invalid_time_range");
+
+    // A PNG named as a JPEG is rejected like a mislabeled video.
+    let req = upload_request(r#"{"mode": 1}"#, "frame.jpg", PNG_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[actix_web::test]
+async fn test_upload_video_time_range() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    // The fake inference procedure reports the same results regardless of the time range, so they
+    // are simply shifted by `start_sec`.
+    let metadata = r#"{"mode": 1, "start_sec": 10, "end_sec": 180}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["suggestions"][0]["start"], 40);
+    assert_eq!(body["suggestions"][1]["end"], 160);
+
+    // The fake `ffprobe` reports the video to last 200 seconds.
+    let metadata = r#"{"mode": 1, "start_sec": 10, "end_sec": 300}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_upload_video_max_suggestions() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["truncated"], false);
+
+    let app = init_app!(inference_dir, tmp_dir, |analyzer| analyzer, |config| {
+        UploadConfig::max_suggestions(config, 1)
+    });
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["truncated"], true);
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["suggestions"][0]["meme_type_desc"], "sorrow");
+    assert_eq!(body["summary"]["anger"]["count"], 0);
+}
+
+#[actix_web::test]
+async fn test_upload_video_model() {
+    let inference_dir = fake_inference_dir();
+    let canary_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let canary = InferenceModel::new(canary_dir.path()).arg("--fps").arg("7");
+    let app = init_app!(
+        inference_dir,
+        tmp_dir,
+        |analyzer: VideoAnalyzer| analyzer
+            .model("canary", canary.clone())
+            .model("unlisted", canary),
+        |config| UploadConfig::allowed_model(config, "canary")
+    );
+
+    let metadata = r#"{"mode": 1, "model": "canary"}"#;
+    let req = upload_request(metadata, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["model"], "canary");
+    assert_eq!(
+        body["warnings"],
+        serde_json::json!(["frame rate too low", "sampling 7 fps"])
+    );
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["model"], serde_json::Value::Null);
+    assert_eq!(body["warnings"], serde_json::json!(["frame rate too low"]));
+
+    // Only the allowed models can be selected, even if the analyzer has others.
+    for model in ["unlisted", "production"] {
+        let metadata = format!(r#"{{"mode": 1, "model": "{}"}}"#, model);
+        let req = upload_request(&metadata, "video.mp4", MP4_HEADER).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "unknown_model");
+    }
+}
+
+#[actix_web::test]
+async fn test_upload_video_meme_types() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(
+        inference_dir,
+        tmp_dir,
+        |analyzer: VideoAnalyzer| analyzer.meme_types([MemeType::Sorrow]),
+        |config: UploadConfig| config.raw_output_token(RAW_TOKEN)
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/upload?raw=true")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {RAW_TOKEN}")))
+        .insert_header((
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        ))
+        .set_payload(multipart_body(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["suggestions"][0]["meme_type_desc"], "sorrow");
+    assert_eq!(body["summary"]["anger"]["count"], 0);
+    let raw_types: Vec<&str> = body["raw_output"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|unit| unit["suggestion"].as_str().unwrap())
+        .collect();
+    assert_eq!(raw_types, ["sorrow", "boredom"]);
+}
+
+#[actix_web::test]
+async fn test_upload_video_raw_output() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?raw=true")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "raw_output_disallowed");
+
+    let app = init_app!(
+        inference_dir,
+        tmp_dir,
+        |analyzer| analyzer,
+        |config: UploadConfig| config.raw_output_token(RAW_TOKEN)
+    );
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert!(body.get("raw_output").is_none());
+
+    // The raw output requires the bearer token.
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?raw=true")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?raw=true")
+        .insert_header((header::AUTHORIZATION, "Bearer wrong"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?raw=true")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {RAW_TOKEN}")))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    // The unknown meme type is dropped from the suggestions, but not from the raw output.
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        body["raw_output"][1],
+        serde_json::json!({"start": 90, "end": 100, "suggestion": "boredom"})
+    );
+}
+
+#[actix_web::test]
+async fn test_upload_video_memory_limit() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| {
+        analyzer.memory_limit(u64::MAX)
+    });
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 2);
+    assert_eq!(body["warnings"], serde_json::json!(["frame rate too low"]));
+
+    // Any process uses more than a byte.
+    write_script(
+        &inference_dir.path().join(".venv/bin/python"),
+        "#!/bin/sh\nsleep 5\n",
+    );
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| {
+        analyzer.memory_limit(1)
+    });
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "resource_exceeded");
+
+    let req = upload_request(r#"{"modes": [1, 0]}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analyses"]["multi"]["status"], "failed");
+    assert_eq!(
+        body["analyses"]["multi"]["error"],
+        "the analysis exceeded the memory limit"
+    );
+}
+
+#[actix_web::test]
+async fn test_upload_video_partial_results() {
+    let inference_dir = fake_inference_dir();
+    let script = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        --output_dir) out="$2"; shift ;;
+    esac
+    shift
+done
+printf '[{"start": 30, "end": 60, "suggestion": "sorrow"}, {"start": 120, "en' > "$out/suggestions.json"
+echo "CUDA error: out of memory" >&2
+exit 1
+"#;
+    write_script(&inference_dir.path().join(".venv/bin/python"), script);
+    let tmp_dir = TempDir::new_in(".").unwrap();
+
+    let app = init_app!(inference_dir, tmp_dir);
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analysis_status"], "failed");
+    assert_eq!(body["suggestions"], serde_json::Value::Null);
+
+    let app = init_app!(inference_dir, tmp_dir, VideoAnalyzer::partial_results);
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analysis_status"], "partial");
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["suggestions"][0]["start"], 30);
+    assert_eq!(body["summary"]["sorrow"]["count"], 1);
+
+    let req = upload_request(r#"{"modes": [1, 0]}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analyses"]["multi"]["status"], "partial");
+
+    // Nothing can be salvaged from an output cut off within the first suggestion.
+    write_script(
+        &inference_dir.path().join(".venv/bin/python"),
+        &script.replace(r#"{"start": 30, "end": 60"#, r#"{"sta"#),
+    );
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["analysis_status"], "failed");
+    assert_eq!(body["suggestions"], serde_json::Value::Null);
+}
+
+#[actix_web::test]
+async fn test_upload_video_stderr_tail() {
+    let inference_dir = fake_inference_dir();
+    let script = format!(
+        "#!/bin/sh\necho 'loading model...' >&2\necho 'OSError: {}/model.bin not found' >&2\nexit 1\n",
+        inference_dir.path().display()
+    );
+    write_script(&inference_dir.path().join(".venv/bin/python"), &script);
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(
+        inference_dir,
+        tmp_dir,
+        |analyzer: VideoAnalyzer| analyzer.stderr_tail_lines(1),
+        |config: UploadConfig| config.raw_output_token(RAW_TOKEN)
+    );
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["suggestions"], serde_json::Value::Null);
+    assert!(body.get("inference_stderr_tail").is_none());
+
+    // Nor is it given to a request without the bearer token.
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?raw=true")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert!(body.get("inference_stderr_tail").is_none());
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER)
+        .uri("/upload?raw=true")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {RAW_TOKEN}")))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["suggestions"], serde_json::Value::Null);
+    assert_eq!(
+        body["inference_stderr_tail"],
+        serde_json::json!(["OSError: <inference_dir>/model.bin not found"])
+    );
+}
+
+#[actix_web::test]
+async fn test_upload_video_keep_inference_output() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let output_dir_path = output_dir.path().to_path_buf();
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| {
+        analyzer.keep_inference_output(output_dir_path)
+    });
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let kept_dir = output_dir
+        .path()
+        .join(body["id"].as_str().unwrap())
+        .join("multi");
+    let kept = fs::read_to_string(kept_dir.join("suggestions.json")).unwrap();
+    let kept: serde_json::Value = serde_json::from_str(&kept).unwrap();
+    assert_eq!(kept.as_array().unwrap().len(), 3);
+
+    let meta = fs::read_to_string(kept_dir.join("meta.json")).unwrap();
+    let meta: serde_json::Value = serde_json::from_str(&meta).unwrap();
+    assert_eq!(meta["id"], body["id"]);
+    assert_eq!(meta["mode"], "multi");
+    assert_eq!(meta["quality"], "full");
+    assert_eq!(meta["file_name"], "video.mp4");
+    assert_eq!(meta["video_name"], "video");
+    assert_eq!(meta["output_file_name"], "suggestions.json");
+    let started_at = meta["started_at"].as_str().unwrap();
+    assert!(
+        time::OffsetDateTime::parse(started_at, &time::format_description::well_known::Rfc3339)
+            .is_ok()
+    );
+
+    // The modes of an upload are kept apart.
+    let req = upload_request(r#"{"modes": [0, 1]}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let kept_dir = output_dir.path().join(body["id"].as_str().unwrap());
+    for mode in ["binary", "multi"] {
+        let meta = fs::read_to_string(kept_dir.join(mode).join("meta.json")).unwrap();
+        let meta: serde_json::Value = serde_json::from_str(&meta).unwrap();
+        assert_eq!(meta["mode"], mode);
+        assert!(kept_dir.join(mode).join("suggestions.json").exists());
+    }
+}
+
+#[actix_web::test]
+async fn test_upload_video_name_template() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let output_dir_path = output_dir.path().to_path_buf();
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| {
+        analyzer
+            .keep_inference_output(output_dir_path)
+            .video_name_template("{name}_{id}".parse().unwrap())
+    });
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let id = body["id"].as_str().unwrap();
+    let meta = fs::read_to_string(output_dir.path().join(id).join("multi/meta.json")).unwrap();
+    let meta: serde_json::Value = serde_json::from_str(&meta).unwrap();
+    assert_eq!(meta["video_name"], format!("video_{}", id));
+}
+
+#[actix_web::test]
+async fn test_upload_video_output_file_name() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+
+    // The fake inference procedure only writes `suggestions.json`.
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| analyzer
+        .output_file_name("results.json"));
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[actix_web::test]
+async fn test_upload_video_inference_env() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| analyzer
+        .env("STREAMEME_TEST_ENV", "42"));
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(
+        body["warnings"],
+        serde_json::json!(["frame rate too low", "STREAMEME_TEST_ENV=42"])
+    );
+}
+
+#[actix_web::test]
+async fn test_upload_unsupported_video() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.txt", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "unsupported_format");
+    assert_eq!(body["detected_content_type"], "video/mp4");
+}
+
+#[actix_web::test]
+async fn test_resumable_upload() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    // The metadata is `filename video.mp4, metadata {"mode":1}` with Base64-encoded values.
+    let req = test::TestRequest::post()
+        .uri("/uploads")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", MP4_HEADER.len()))
+        .insert_header((
+            "Upload-Metadata",
+            "filename dmlkZW8ubXA0,metadata eyJtb2RlIjoxfQ==",
+        ))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let location = res
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    let patch = |offset: usize, chunk: &'static [u8]| {
+        test::TestRequest::patch()
+            .uri(&location)
+            .insert_header(("Tus-Resumable", "1.0.0"))
+            .insert_header(("Upload-Offset", offset))
+            .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+            .set_payload(chunk)
+            .to_request()
+    };
+    let res = test::call_service(&app, patch(0, &MP4_HEADER[..10])).await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers().get("upload-offset").unwrap(), "10");
+
+    // Resuming from a wrong offset is refused.
+    let res = test::call_service(&app, patch(4, &MP4_HEADER[4..])).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    let req = test::TestRequest::default()
+        .method(actix_web::http::Method::HEAD)
+        .uri(&location)
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("upload-offset").unwrap(), "10");
+
+    // The last chunk completes the upload, which is analyzed right away.
+    let res = test::call_service(&app, patch(10, &MP4_HEADER[10..])).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["file_name"], "video.mp4");
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 2);
+
+    // A complete upload is no longer resumable.
+    let res = test::call_service(&app, patch(0, MP4_HEADER)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn test_resumable_upload_without_metadata() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    // The metadata is `filename video.mp4` with a Base64-encoded value, lacking `metadata`.
+    let req = test::TestRequest::post()
+        .uri("/uploads")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .insert_header(("Upload-Length", MP4_HEADER.len()))
+        .insert_header(("Upload-Metadata", "filename dmlkZW8ubXA0"))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "missing_field");
+}
+
+/// Starts a server on a random local port, serving [`MP4_HEADER`] at `/video.mp4` and a redirect to
+/// it at `/redirect`, and returns its address.
+fn serve_video() -> std::net::SocketAddr {
+    let server = HttpServer::new(|| {
+        App::new()
+            .route(
+                "/video.mp4",
+                web::get().to(|| async {
+                    HttpResponse::Ok()
+                        .content_type("video/mp4")
+                        .body(MP4_HEADER)
+                }),
+            )
+            .route(
+                "/redirect",
+                web::get().to(|| async {
+                    HttpResponse::Found()
+                        .insert_header((header::LOCATION, "/video.mp4"))
+                        .finish()
+                }),
+            )
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    addr
+}
+
+#[actix_web::test]
+async fn test_analyze_url() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let addr = serve_video();
+    let app = init_app!(
+        inference_dir,
+        tmp_dir,
+        |analyzer| analyzer,
+        |config| config,
+        |remote: RemoteUploadConfig| remote.allowed_host(addr.to_string())
+    );
+    let analyze_url = |body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/analyze-url")
+            .set_json(body)
+            .to_request()
+    };
+
+    let req = analyze_url(serde_json::json!({
+        "url": format!("http://{addr}/video.mp4"),
+        "mode": 1,
+    }));
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["file_name"], "video.mp4");
+    assert_eq!(body["suggestions"].as_array().unwrap().len(), 2);
+
+    for (url, status, code) in [
+        (
+            format!("http://localhost:{}/video.mp4", addr.port()),
+            StatusCode::FORBIDDEN,
+            "url_not_allowed",
+        ),
+        (
+            format!("http://{}/video.mp4", addr.ip()),
+            StatusCode::FORBIDDEN,
+            "url_not_allowed",
+        ),
+        (
+            format!("https://{addr}/video.mp4"),
+            StatusCode::FORBIDDEN,
+            "url_not_allowed",
+        ),
+        (
+            format!("http://{addr}/redirect"),
+            StatusCode::BAD_GATEWAY,
+            "download_failed",
+        ),
+        (
+            format!("http://{addr}/missing.mp4"),
+            StatusCode::BAD_GATEWAY,
+            "download_failed",
+        ),
+    ] {
+        let req = analyze_url(serde_json::json!({"url": url, "mode": 1}));
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), status, "{}", url);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], code, "{}", url);
+    }
+
+    let req = analyze_url(serde_json::json!({"url": format!("http://{addr}/video.mp4")}));
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "malformed_request");
+}
+
+#[actix_web::test]
+async fn test_ready_idle() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir, |analyzer: VideoAnalyzer| {
+        analyzer.idle_timeout(Duration::from_millis(100))
+    });
+    let ready = || async {
+        let req = test::TestRequest::get().uri("/ready").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        body["idle"].as_bool().unwrap()
+    };
+
+    assert!(!ready().await);
+    thread::sleep(Duration::from_millis(300));
+    assert!(ready().await);
+
+    let req = upload_request(r#"{"mode": 1}"#, "video.mp4", MP4_HEADER).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!ready().await);
+}
+
+#[actix_web::test]
+async fn test_ready() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let app = init_app!(inference_dir, tmp_dir);
+
+    let req = test::TestRequest::get().uri("/ready").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body, serde_json::json!({"ready": true, "idle": false}));
+
+    fs::remove_file(inference_dir.path().join("inference.py")).unwrap();
+    let req = test::TestRequest::get().uri("/ready").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "not_ready");
+    assert_eq!(body["check"], "inference_script");
+    // The paths on the host are only logged.
+    assert_eq!(body["message"], "the inference script is not a file");
+}
+
+#[actix_web::test]
+async fn test_analyzer_client() {
+    let inference_dir = fake_inference_dir();
+    let tmp_dir = TempDir::new_in(".").unwrap();
+    let video_path = tmp_dir.path().join("video.mp4");
+    fs::write(&video_path, MP4_HEADER).unwrap();
+    let (analyzer, analyzer_buf) = VideoAnalyzer::new(inference_dir.path().to_path_buf());
+    thread::spawn(move || analyzer.run());
+    let client = VideoAnalyzerClient::new(analyzer_buf);
+
+    let output = client
+        .analyze(&video_path, VideoAnalyzerMode::Multi, "video")
+        .await
+        .unwrap();
+    assert!(!output.is_failed());
+    let suggestions: Vec<_> = output
+        .suggestions()
+        .unwrap()
+        .iter()
+        .map(|s| (s.start(), s.end(), s.meme_type()))
+        .collect();
+    assert_eq!(
+        suggestions,
+        [(30, 60, MemeType::Sorrow), (120, 150, MemeType::Anger)]
+    );
+}